@@ -0,0 +1,111 @@
+use std::hint::black_box;
+use std::io::{self, Cursor, Read, Write};
+use std::net::SocketAddr;
+use std::time::Duration;
+use criterion::{criterion_group, criterion_main, Criterion};
+use rust_http::client::{Client, Connection};
+use rust_http::request::ParseOptions;
+
+// In-memory connection replaying a captured request
+struct Replay(Cursor<Vec<u8>>);
+
+impl Read for Replay {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.0.read(buf)
+    }
+}
+
+impl Write for Replay {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Connection for Replay {
+    fn set_read_timeout(&self, _: Option<Duration>) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn set_write_timeout(&self, _: Option<Duration>) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn peer_addr(&self) -> io::Result<SocketAddr> {
+        Err(io::ErrorKind::NotConnected.into())
+    }
+
+    fn shutdown(&self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+fn browser_get() -> Vec<u8> {
+    b"GET /users/42?expand=orders HTTP/1.1\r\n\
+Host: localhost:8080\r\n\
+User-Agent: Mozilla/5.0 (X11; Linux x86_64; rv:124.0) Gecko/20100101 Firefox/124.0\r\n\
+Accept: text/html,application/xhtml+xml,application/xml;q=0.9,*/*;q=0.8\r\n\
+Accept-Language: en-US,en;q=0.5\r\n\
+Accept-Encoding: gzip, deflate, br\r\n\
+Referer: http://localhost:8080/users\r\n\
+Connection: keep-alive\r\n\
+Cookie: sessionId=0b5f6e9c-8d0e-4d0b-9d8e-5c1f3b7a2e11\r\n\
+Upgrade-Insecure-Requests: 1\r\n\r\n"
+        .to_vec()
+}
+
+fn json_post() -> Vec<u8> {
+    let body = serde_json::to_vec(&serde_json::json!({
+        "name": "Ada Lovelace",
+        "email": "ada@example.com",
+        "tags": ["math", "engines", "poetry"],
+    }))
+    .unwrap();
+    let mut request = format!(
+        "POST /users/42 HTTP/1.1\r\nHost: localhost\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n",
+        body.len()
+    )
+    .into_bytes();
+    request.extend_from_slice(&body);
+    request
+}
+
+fn chunked_upload() -> Vec<u8> {
+    let mut request = b"POST /upload HTTP/1.1\r\nHost: localhost\r\nTransfer-Encoding: chunked\r\n\r\n".to_vec();
+    for _ in 0..16 {
+        request.extend_from_slice(b"400\r\n");
+        request.extend(std::iter::repeat_n(b'x', 0x400));
+        request.extend_from_slice(b"\r\n");
+    }
+    request.extend_from_slice(b"0\r\n\r\n");
+    request
+}
+
+fn bench_parse(c: &mut Criterion) {
+    let options = ParseOptions::default();
+    for (name, request) in [("browser_get", browser_get()), ("json_post", json_post()), ("chunked_16k", chunked_upload())] {
+        c.bench_function(&format!("parse_request/{}", name), |b| {
+            b.iter(|| {
+                let mut client = Client::new(Replay(Cursor::new(request.clone())));
+                black_box(client.parse_request_with(&options).unwrap())
+            })
+        });
+    }
+
+    // Several requests on one connection, where buffers can carry over between them
+    let pipelined = browser_get().repeat(8);
+    c.bench_function("parse_request/keep_alive_8x_browser_get", |b| {
+        b.iter(|| {
+            let mut client = Client::new(Replay(Cursor::new(pipelined.clone())));
+            for _ in 0..8 {
+                black_box(client.parse_request_with(&options).unwrap());
+            }
+        })
+    });
+}
+
+criterion_group!(benches, bench_parse);
+criterion_main!(benches);
@@ -0,0 +1,72 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use crate::request::HttpRequest;
+use crate::response::HttpResponse;
+
+// Closure deciding whether a username and password are valid
+pub type CredentialCheck = Arc<dyn Fn(&str, &str) -> bool + Send + Sync>;
+
+// Basic auth protection for every path under a prefix
+#[derive(Clone)]
+pub struct BasicAuthGuard {
+    pub prefix: String,
+    pub realm: String,
+    check: CredentialCheck,
+}
+
+impl BasicAuthGuard {
+    pub fn new<F>(prefix: &str, realm: &str, check: F) -> Self
+    where
+        F: Fn(&str, &str) -> bool + Send + Sync + 'static,
+    {
+        Self {
+            prefix: prefix.trim_end_matches('/').to_string(),
+            realm: realm.to_string(),
+            check: Arc::new(check),
+        }
+    }
+
+    // Check whether the guard applies to a request target
+    pub fn covers(&self, target: &str) -> bool {
+        let path = target.split('?').next().unwrap_or_default();
+        match path.strip_prefix(&self.prefix) {
+            Some(rest) => rest.is_empty() || rest.starts_with('/'),
+            None => false,
+        }
+    }
+
+    // Return a 401 challenge unless the request carries valid credentials
+    pub fn authorize(&self, request: &HttpRequest) -> Option<HttpResponse> {
+        match request.basic_auth() {
+            Some((username, password)) if (self.check)(&username, &password) => None,
+            _ => Some(self.challenge()),
+        }
+    }
+
+    fn challenge(&self) -> HttpResponse {
+        let mut headers = HashMap::new();
+        headers.insert(
+            "WWW-Authenticate".to_string(),
+            format!("Basic realm=\"{}\"", self.realm.replace('"', "")),
+        );
+        HttpResponse::new(401, headers, Some(serde_json::json!({
+            "status_code": 401,
+            "message": "Unauthorized"
+        }).to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_guard_covers_prefix_segments_only() {
+        let guard = BasicAuthGuard::new("/admin/", "Admin", |_, _| true);
+
+        assert!(guard.covers("/admin"));
+        assert!(guard.covers("/admin/users?page=2"));
+        assert!(!guard.covers("/administrator"));
+        assert!(!guard.covers("/public"));
+    }
+}
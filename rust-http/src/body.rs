@@ -0,0 +1,190 @@
+use std::io::{self, ErrorKind, Read};
+
+// How a request body is delimited on the wire
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BodyFraming {
+    // No body, anything after the headers is the next request
+    Empty,
+    // Exactly this many bytes follow the headers
+    Length(usize),
+    // Chunks up to a zero-size chunk and the trailer section
+    Chunked,
+}
+
+// Where a body reader is in the body
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    // Bytes of a Content-Length body still to come
+    Length(usize),
+    // Expecting the size line of the next chunk
+    ChunkSize,
+    // Bytes left in the current chunk, followed by its CRLF
+    ChunkData(usize),
+    Done,
+}
+
+// Request body handed to streaming handlers as it arrives, with the chunked
+// encoding undone, so uploads can be processed without buffering them whole
+pub struct BodyReader<'a> {
+    stream: Option<&'a mut dyn Read>,
+    // Bytes received but not handed out yet start at `pos`
+    buffer: Vec<u8>,
+    pos: usize,
+    state: State,
+    // Bytes asked from the stream per read
+    read_size: usize,
+}
+
+impl<'a> BodyReader<'a> {
+    // Read a body framed as `framing` from `stream`, starting with the bytes that came with the headers
+    pub fn new(stream: &'a mut dyn Read, received: &[u8], framing: BodyFraming, read_size: usize) -> Self {
+        let state = match framing {
+            BodyFraming::Empty | BodyFraming::Length(0) => State::Done,
+            BodyFraming::Length(length) => State::Length(length),
+            BodyFraming::Chunked => State::ChunkSize,
+        };
+        Self { stream: Some(stream), buffer: received.to_vec(), pos: 0, state, read_size: read_size.max(1) }
+    }
+
+    // Read a body that was already received in full
+    pub fn from_bytes(body: &[u8]) -> Self {
+        let state = if body.is_empty() { State::Done } else { State::Length(body.len()) };
+        Self { stream: None, buffer: body.to_vec(), pos: 0, state, read_size: 1 }
+    }
+
+    // Check whether the whole body has been read
+    pub fn is_finished(&self) -> bool {
+        self.state == State::Done
+    }
+
+    // Bytes received past the end of the body, the start of a pipelined request
+    pub fn remaining(&self) -> &[u8] {
+        &self.buffer[self.pos..]
+    }
+
+    // Hand out up to `limit` body bytes, reading more from the stream when none are buffered
+    fn copy_out(&mut self, out: &mut [u8], limit: usize) -> io::Result<usize> {
+        if self.pos == self.buffer.len() {
+            self.fill()?;
+        }
+        let count = out.len().min(limit).min(self.buffer.len() - self.pos);
+        out[..count].copy_from_slice(&self.buffer[self.pos..self.pos + count]);
+        self.pos += count;
+        Ok(count)
+    }
+
+    // Read the next CRLF terminated line of a chunked body
+    fn read_line(&mut self) -> io::Result<String> {
+        loop {
+            if let Some(offset) = self.buffer[self.pos..].windows(2).position(|window| window == b"\r\n") {
+                let line = String::from_utf8_lossy(&self.buffer[self.pos..self.pos + offset]).to_string();
+                self.pos += offset + 2;
+                return Ok(line);
+            }
+            self.fill()?;
+        }
+    }
+
+    // Append the next bytes from the stream, failing if the client closed first
+    fn fill(&mut self) -> io::Result<()> {
+        self.buffer.drain(..self.pos);
+        self.pos = 0;
+        let Some(stream) = self.stream.as_mut() else {
+            return Err(io::Error::new(ErrorKind::UnexpectedEof, "Incomplete body"));
+        };
+
+        let len = self.buffer.len();
+        self.buffer.resize(len + self.read_size, 0);
+        let read = stream.read(&mut self.buffer[len..]);
+        self.buffer.truncate(len + *read.as_ref().unwrap_or(&0));
+        match read? {
+            0 => Err(io::Error::new(ErrorKind::UnexpectedEof, "Incomplete body")),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl Read for BodyReader<'_> {
+    fn read(&mut self, out: &mut [u8]) -> io::Result<usize> {
+        let invalid = || io::Error::new(ErrorKind::InvalidData, "Invalid chunked body");
+        if out.is_empty() {
+            return Ok(0);
+        }
+
+        loop {
+            match self.state {
+                State::Done => return Ok(0),
+                State::Length(remaining) => {
+                    let count = self.copy_out(out, remaining)?;
+                    self.state = if count == remaining { State::Done } else { State::Length(remaining - count) };
+                    return Ok(count);
+                }
+                State::ChunkSize => {
+                    let line = self.read_line()?;
+                    let size = line.split(';').next().unwrap_or_default().trim();
+                    let size = usize::from_str_radix(size, 16).map_err(|_| invalid())?;
+                    if size > 0 {
+                        self.state = State::ChunkData(size);
+                        continue;
+                    }
+                    // Streaming handlers don't see trailers, skip up to the final blank line
+                    while !self.read_line()?.is_empty() {}
+                    self.state = State::Done;
+                }
+                State::ChunkData(0) => {
+                    if !self.read_line()?.is_empty() {
+                        return Err(invalid());
+                    }
+                    self.state = State::ChunkSize;
+                }
+                State::ChunkData(remaining) => {
+                    let count = self.copy_out(out, remaining)?;
+                    self.state = State::ChunkData(remaining - count);
+                    return Ok(count);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_length_body_leaves_next_request() {
+        let mut stream: &[u8] = b"llo world GET / HTTP/1.1\r\n\r\n";
+        let mut reader = BodyReader::new(&mut stream, b"he", BodyFraming::Length(11), 64);
+
+        let mut body = String::new();
+        reader.read_to_string(&mut body).unwrap();
+
+        assert_eq!(body, "hello world");
+        assert!(reader.is_finished());
+        assert_eq!(reader.remaining(), b" GET / HTTP/1.1\r\n\r\n".as_slice());
+    }
+
+    #[test]
+    fn test_chunked_body_is_decoded() {
+        let mut stream: &[u8] = b"lo\r\n6\r\n world\r\n0\r\nX-Checksum: 1\r\n\r\n";
+        let mut reader = BodyReader::new(&mut stream, b"5\r\nhel", BodyFraming::Chunked, 3);
+
+        let mut body = Vec::new();
+        reader.read_to_end(&mut body).unwrap();
+
+        assert_eq!(body, b"hello world");
+        assert!(reader.is_finished());
+        assert!(reader.remaining().is_empty());
+    }
+
+    #[test]
+    fn test_truncated_body_fails() {
+        let mut stream: &[u8] = b"abc";
+        let mut reader = BodyReader::new(&mut stream, b"", BodyFraming::Length(10), 8);
+
+        let error = reader.read_to_end(&mut Vec::new()).unwrap_err();
+
+        assert_eq!(error.kind(), ErrorKind::UnexpectedEof);
+        assert!(!reader.is_finished());
+    }
+}
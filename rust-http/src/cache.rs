@@ -0,0 +1,143 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use crate::context::Context;
+use crate::request::HttpRequest;
+use crate::response::HttpResponse;
+use crate::router::Handler;
+
+// Response kept for one request target and the values of the headers it varies on
+struct Entry {
+    expires: Instant,
+    vary: Vec<(String, Option<String>)>,
+    response: HttpResponse,
+}
+
+// In-memory cache of successful responses, each kept for `ttl`
+pub struct ResponseCache {
+    ttl: Duration,
+    entries: Mutex<HashMap<String, Vec<Entry>>>,
+}
+
+impl ResponseCache {
+    pub fn new(ttl: Duration) -> Self {
+        Self { ttl, entries: Mutex::new(HashMap::new()) }
+    }
+
+    // Cached response for the request, if a fresh one matches its Vary headers
+    pub fn get(&self, request: &HttpRequest) -> Option<HttpResponse> {
+        let entries = self.entries.lock().unwrap();
+        let now = Instant::now();
+        entries.get(&cache_key(request))?.iter().find_map(|entry| {
+            let matches = entry.expires > now
+                && entry.vary.iter().all(|(name, value)| request.header(name) == value.as_deref());
+            matches.then(|| entry.response.clone())
+        })
+    }
+
+    // Keep a 200 response, replacing the entry for the same Vary values
+    pub fn insert(&self, request: &HttpRequest, response: &HttpResponse) {
+        if response.status_code != 200 {
+            return;
+        }
+        let vary: Vec<(String, Option<String>)> = vary_headers(response)
+            .into_iter()
+            .map(|name| {
+                let value = request.header(&name).map(str::to_string);
+                (name, value)
+            })
+            .collect();
+
+        let mut entries = self.entries.lock().unwrap();
+        let now = Instant::now();
+        // Drop whatever expired while we hold the lock anyway
+        entries.retain(|_, variants| {
+            variants.retain(|entry| entry.expires > now);
+            !variants.is_empty()
+        });
+        let variants = entries.entry(cache_key(request)).or_default();
+        variants.retain(|entry| entry.vary != vary);
+        variants.push(Entry { expires: now + self.ttl, vary, response: response.clone() });
+    }
+}
+
+// Wrap a handler so its responses are reused for `ttl`. Requests sending
+// `Cache-Control: no-cache` skip the cached copy and refresh it, with `no-store`
+// they also leave the cache as it was.
+pub fn cached(ttl: Duration, handler: Handler) -> Handler {
+    let cache = ResponseCache::new(ttl);
+    Arc::new(move |ctx: &Context| {
+        let no_store = has_directive(ctx.request, "no-store");
+        if !no_store && !has_directive(ctx.request, "no-cache") {
+            if let Some(response) = cache.get(ctx.request) {
+                return response;
+            }
+        }
+        let response = handler(ctx);
+        if !no_store {
+            cache.insert(ctx.request, &response);
+        }
+        response
+    })
+}
+
+fn cache_key(request: &HttpRequest) -> String {
+    format!("{} {}", request.method, request.path)
+}
+
+// Check whether the request's Cache-Control header carries `directive`
+fn has_directive(request: &HttpRequest, directive: &str) -> bool {
+    request
+        .header_values("Cache-Control")
+        .iter()
+        .flat_map(|value| value.split(','))
+        .any(|value| value.trim().eq_ignore_ascii_case(directive))
+}
+
+// Header names listed in the response's Vary header
+fn vary_headers(response: &HttpResponse) -> Vec<String> {
+    response
+        .headers
+        .iter()
+        .filter(|(key, _)| key.eq_ignore_ascii_case("Vary"))
+        .flat_map(|(_, value)| value.split(','))
+        .map(|name| name.trim().to_string())
+        .filter(|name| !name.is_empty())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn get(path: &str, headers: &[&str]) -> HttpRequest {
+        HttpRequest {
+            method: "GET".to_string(),
+            path: path.to_string(),
+            headers: headers.iter().map(|h| h.to_string()).collect(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_entries_expire() {
+        let cache = ResponseCache::new(Duration::from_millis(50));
+        let request = get("/report", &[]);
+        cache.insert(&request, &HttpResponse::ok("report"));
+
+        assert!(cache.get(&request).is_some());
+        std::thread::sleep(Duration::from_millis(80));
+        assert!(cache.get(&request).is_none());
+    }
+
+    #[test]
+    fn test_entries_respect_vary() {
+        let cache = ResponseCache::new(Duration::from_secs(60));
+        let english = get("/greeting", &["Accept-Language: en"]);
+        cache.insert(&english, &HttpResponse::ok("hello").with_header("Vary", "Accept-Language"));
+
+        assert!(cache.get(&english).is_some());
+        assert!(cache.get(&get("/greeting", &["Accept-Language: fr"])).is_none());
+        assert!(cache.get(&get("/greeting?x=1", &["Accept-Language: en"])).is_none());
+    }
+}
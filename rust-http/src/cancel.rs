@@ -0,0 +1,109 @@
+use std::net::TcpStream;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+// How often the watcher checks whether the handler finished
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+// Flag a long handler can check to stop early once nobody waits for its response
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken(Arc<AtomicBool>);
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
+}
+
+// Peeks at a connection while its handler runs and cancels the token if the client goes away
+pub struct DisconnectWatcher {
+    done: Arc<AtomicBool>,
+    thread: JoinHandle<()>,
+}
+
+impl DisconnectWatcher {
+    pub fn spawn(socket: TcpStream, token: CancellationToken) -> Self {
+        let done = Arc::new(AtomicBool::new(false));
+        let thread_done = Arc::clone(&done);
+
+        let thread = thread::spawn(move || {
+            let previous_timeout = socket.read_timeout().ok().flatten();
+            if socket.set_read_timeout(Some(POLL_INTERVAL)).is_err() {
+                return;
+            }
+
+            let mut byte = [0; 1];
+            while !thread_done.load(Ordering::SeqCst) {
+                match socket.peek(&mut byte) {
+                    // The client closed its side of the connection
+                    Ok(0) => {
+                        token.cancel();
+                        break;
+                    }
+                    // More data waiting, e.g. a pipelined request; nothing left to learn
+                    Ok(_) => break,
+                    Err(e) if matches!(e.kind(), std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut) => {}
+                    Err(_) => {
+                        token.cancel();
+                        break;
+                    }
+                }
+            }
+
+            let _ = socket.set_read_timeout(previous_timeout);
+        });
+
+        Self { done, thread }
+    }
+
+    // Stop watching once the handler returned
+    pub fn stop(self) {
+        self.done.store(true, Ordering::SeqCst);
+        let _ = self.thread.join();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+
+    #[test]
+    fn test_watcher_cancels_when_client_disconnects() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (accepted, _) = listener.accept().unwrap();
+        let token = CancellationToken::new();
+
+        let watcher = DisconnectWatcher::spawn(accepted, token.clone());
+        drop(client);
+        thread::sleep(Duration::from_millis(200));
+        watcher.stop();
+
+        assert!(token.is_cancelled());
+    }
+
+    #[test]
+    fn test_watcher_leaves_open_connections_alone() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let _client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (accepted, _) = listener.accept().unwrap();
+        let token = CancellationToken::new();
+
+        let watcher = DisconnectWatcher::spawn(accepted, token.clone());
+        thread::sleep(Duration::from_millis(120));
+        watcher.stop();
+
+        assert!(!token.is_cancelled());
+    }
+}
@@ -1,126 +1,1067 @@
+use std::cell::RefCell;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::mpsc::{self, RecvTimeoutError};
 use std::sync::{Arc, Mutex};
-use crate::server::Server;
-use crate::request::HttpRequest;
+use std::thread;
+use crate::server::{lock_server, Server};
+use crate::body::{BodyFraming, BodyReader};
+use crate::request::{HttpRequest, ParseOptions, RequestError, SniffedBody};
+use crate::response::{negotiate_trailers, HttpResponse};
+use crate::router::{Matched, Route, RouteMatch};
+use crate::context::Context;
+use crate::cancel::{CancellationToken, DisconnectWatcher};
+use crate::logging::{log_response, sent_response_line, AccessLogEntry};
+use crate::middleware;
+use crate::conditional;
+use crate::session;
+use crate::compression::gzip_response;
+use crate::range::apply_range;
+use crate::sse::EventStream;
+use crate::stats::stats_response;
+use crate::trace::{debug, error, warn};
 use serde_json;
-use crate::methods::{handle_get, handle_post, handle_put,handle_delete, handle_patch, handle_method_not_allowed};
-use std::io::{Read, Write};
-use std::net::TcpStream;
+use std::collections::HashMap;
+use crate::methods::{handle_get, handle_post, handle_put,handle_delete, handle_patch, handle_method_not_allowed, health_check_response, resource_modified, server_options_response};
+use std::io::{BufWriter, ErrorKind, Read, Write};
+use std::net::{Shutdown, SocketAddr, TcpStream};
+use std::time::{Duration, SystemTime};
+#[cfg(feature = "tracing")]
+use uuid::Uuid;
+
+// Size of the buffer responses are written through
+const WRITE_BUFFER_SIZE: usize = 8 * 1024;
+
+// Limits for connections kept open between requests
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeepAliveOptions {
+    // How long to wait for the next request before closing
+    pub idle_timeout: Duration,
+    // Requests served on one connection before it is closed
+    pub max_requests: usize,
+    // Tell clients the limits with a `Keep-Alive: timeout=5, max=100` header
+    pub advertise: bool,
+}
+
+impl Default for KeepAliveOptions {
+    fn default() -> Self {
+        Self { idle_timeout: Duration::from_secs(5), max_requests: 100, advertise: false }
+    }
+}
+
+// Stream a client talks through, plain TCP or TLS
+pub trait Connection: Read + Write {
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> std::io::Result<()>;
+    fn set_write_timeout(&self, timeout: Option<Duration>) -> std::io::Result<()>;
+    fn peer_addr(&self) -> std::io::Result<SocketAddr>;
+    fn shutdown(&self) -> std::io::Result<()>;
+
+    // Clone of the underlying socket, used to notice clients that disconnect
+    fn socket(&self) -> Option<TcpStream> {
+        None
+    }
+}
+
+impl Connection for TcpStream {
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> std::io::Result<()> {
+        TcpStream::set_read_timeout(self, timeout)
+    }
+
+    fn set_write_timeout(&self, timeout: Option<Duration>) -> std::io::Result<()> {
+        TcpStream::set_write_timeout(self, timeout)
+    }
+
+    fn peer_addr(&self) -> std::io::Result<SocketAddr> {
+        TcpStream::peer_addr(self)
+    }
+
+    fn shutdown(&self) -> std::io::Result<()> {
+        TcpStream::shutdown(self, Shutdown::Both)
+    }
+
+    fn socket(&self) -> Option<TcpStream> {
+        self.try_clone().ok()
+    }
+}
 
 // Struct to represent a client
-pub struct Client {
-    pub stream: TcpStream,
-}
-
-impl Client {
-    // Handle the client connection
-    pub fn handle(&mut self, server: Arc<Mutex<Server>>) {
-        if let Some(request) = self.parse_request() {
-            // Handle the session cookie
-            let mut server_lock = server.lock().unwrap();
-            let session_id = server_lock.handle_cookie(&request);
-            drop(server_lock);
-
-            // Parse JSON body if present
-            let json_body = if !request.body.is_empty() {
-                serde_json::from_str(&request.body).ok()
-            } else {
-                None
+pub struct Client<S = TcpStream> {
+    pub stream: S,
+    // Bytes read past the end of the last request, the start of a pipelined one
+    pending: Vec<u8>,
+    // Request buffer handed back after each request, so keep-alive requests reuse it
+    buffer: Vec<u8>,
+    // Requests read on this connection so far
+    requests_read: usize,
+}
+
+impl<S> Client<S> {
+    pub fn new(stream: S) -> Self {
+        Self { stream, pending: Vec::new(), buffer: Vec::new(), requests_read: 0 }
+    }
+}
+
+impl<S: Connection> Client<S> {
+    // Handle the client connection, `connection` is its id in the server's lifecycle when tracked
+    pub fn handle(&mut self, server: Arc<Mutex<Server>>, connection: Option<u64>) {
+        let (read_timeout, write_timeout, log_format, log_bodies, parse_options, gzip, method_override, watch_disconnects, trust_proxy, keep_alive, lifecycle, banner, metrics) = {
+            let server_lock = lock_server(&server);
+            (
+                server_lock.read_timeout,
+                server_lock.write_timeout,
+                server_lock.access_log_format,
+                server_lock.log_bodies,
+                server_lock.parse_options.clone(),
+                server_lock.gzip,
+                server_lock.method_override,
+                server_lock.watch_disconnects,
+                server_lock.trust_proxy,
+                server_lock.keep_alive,
+                Arc::clone(&server_lock.lifecycle),
+                server_lock.banner.clone(),
+                Arc::clone(&server_lock.metrics),
+            )
+        };
+        let _open = metrics.open_connection();
+        if let Err(e) = self.stream.set_read_timeout(read_timeout) {
+            warn!("Failed to set read timeout: {}", e);
+        }
+        if let Err(e) = self.stream.set_write_timeout(write_timeout) {
+            warn!("Failed to set write timeout: {}", e);
+        }
+
+        // Requests on one connection are answered one at a time, so pipelined
+        // responses always go out in the order their requests came in
+        let mut requests_left = keep_alive.map_or(1, |options| options.max_requests);
+        loop {
+            // The idle reaper may close the connection while it waits for a request
+            if let Some(id) = connection {
+                lifecycle.set_idle(id, true);
+            }
+            let mut streamed = None;
+            let mut body_skipped = false;
+            let request = self.parse_head(&parse_options);
+            if let Some(id) = connection {
+                lifecycle.set_idle(id, false);
+            }
+            let request = match request {
+                Ok((mut request, received)) => {
+                    if method_override {
+                        apply_method_override(&mut request);
+                    }
+                    self.receive_body(&mut request, received, &server, &parse_options).map(|pending| {
+                        match pending {
+                            PendingBody::Read => {}
+                            PendingBody::Streamed(received, framing) => streamed = Some((received, framing)),
+                            PendingBody::Skipped => body_skipped = true,
+                        }
+                        request
+                    })
+                }
+                Err(e) => Err(e),
             };
+            requests_left = requests_left.saturating_sub(1);
+            self.requests_read += 1;
 
-            // Handle request based on method
-            let mut response = match request.method.as_str() {
-                "GET" => handle_get(&request.path),
-                "POST" => handle_post(&request.path, json_body.as_ref()),
-                "PUT" => handle_put(&request.path, json_body.as_ref()),
-                "DELETE" => handle_delete(&request.path),
-                "PATCH" => handle_patch(&request.path, json_body.as_ref()),
-                _ => handle_method_not_allowed(),
+            #[cfg(feature = "tracing")]
+            let span = match &request {
+                Ok(request) => crate::trace::request_span(request, &Uuid::new_v4().to_string()),
+                Err(_) => tracing::Span::none(),
+            };
+            #[cfg(feature = "tracing")]
+            let _entered = span.enter();
+
+            // A body left on the connection can't be told apart from the next request
+            let mut rejected = request.is_err() || body_skipped;
+            let (request, mut response) = match request {
+                Ok(mut request) => {
+                    debug!("Dispatch started");
+                    // The watcher peeks at the socket, which a streamed body is still arriving on
+                    let watcher = if watch_disconnects && streamed.is_none() {
+                        self.stream.socket().map(|socket| DisconnectWatcher::spawn(socket, request.cancellation.clone()))
+                    } else {
+                        None
+                    };
+                    let read_size = parse_options.read_buffer_size;
+                    let body_stream = streamed.map(|(received, framing)| {
+                        RefCell::new(BodyReader::new(&mut self.stream, &received, framing, read_size))
+                    });
+                    let mut response = Self::dispatch(&mut request, &server, body_stream.as_ref());
+                    if let Some(watcher) = watcher {
+                        watcher.stop();
+                    }
+                    // Close the connection like after a rejected request when the handler left the body unread
+                    let leftover = body_stream.as_ref().map(|body| {
+                        let body = body.borrow();
+                        body.is_finished().then(|| body.remaining().to_vec())
+                    });
+                    match leftover {
+                        Some(Some(leftover)) => self.keep_pending(&leftover),
+                        Some(None) => rejected = true,
+                        None => {}
+                    }
+                    apply_range(&request, &mut response);
+                    if let Some(options) = gzip {
+                        gzip_response(&request, &mut response, options);
+                    }
+                    negotiate_trailers(&request, &mut response);
+                    debug!("Dispatch finished with status {}", response.status_code);
+                    (Some(request), response)
+                }
+                Err(RequestError::Rejected(status_code, message)) => (None, HttpResponse::error(status_code, &message)),
+                Err(RequestError::Closed) => return,
+            };
+
+            // Keep the connection for the next request unless either side asked to close it.
+            // An event stream has no length and only ends when the connection does.
+            let streams_events = response.extensions.get::<EventStream>().is_some();
+            let persistent = keep_alive.is_some()
+                && requests_left > 0
+                && !rejected
+                && !streams_events
+                && request.as_ref().is_some_and(HttpRequest::keep_alive)
+                && !lifecycle.is_stopping();
+            if let Some(options) = keep_alive {
+                response.headers.insert("Connection".to_string(), if persistent { "keep-alive" } else { "close" }.to_string());
+                if persistent && options.advertise {
+                    response.headers.insert(
+                        "Keep-Alive".to_string(),
+                        format!("timeout={}, max={}", options.idle_timeout.as_secs(), requests_left),
+                    );
+                }
+                // The client needs a length to find where the next response starts
+                if response.body.is_none() && !response.forbids_body() && !streams_events {
+                    response.body = Some(Vec::new());
+                }
+            }
+
+            // A handler may set its own Server header
+            if let Some(banner) = &banner {
+                if !response.headers.keys().any(|key| key.eq_ignore_ascii_case("Server")) {
+                    response.headers.insert("Server".to_string(), banner.clone());
+                }
+            }
+
+            metrics.record_response(response.status_code);
+
+            // Send the response back to the client, giving up on clients that stop reading
+            if let Err(e) = self.send_response(&response) {
+                error!("Failed to send response: {}", e);
+                let _ = self.stream.shutdown();
+                return;
+            }
+
+            // Send events as the handler produces them until it stops or the client leaves
+            if let Some(events) = response.extensions.get::<EventStream>() {
+                if let Err(e) = events.write_to(&mut self.stream) {
+                    debug!("Event stream ended: {}", e);
+                }
+            }
+
+            // Read what is left of a rejected request, or of pipelined requests that won't be
+            // served, so closing doesn't reset the connection before the client reads the response
+            if rejected || (!persistent && !self.pending.is_empty()) {
+                self.discard_input();
+            }
+
+            // Log the response, by size unless whole bodies were asked for
+            debug!("{}", sent_response_line(request.as_ref(), &response, log_bodies));
+
+            match &request {
+                Some(request) => {
+                    let entry = AccessLogEntry {
+                        client_ip: request.client_ip(trust_proxy),
+                        time: SystemTime::now(),
+                        request,
+                        response: &response,
+                    };
+                    log_response(response.status_code, &entry.format(log_format));
+                }
+                None => log_response(response.status_code, &format!("Rejected request with status {}", response.status_code)),
+            }
+
+            if !persistent {
+                return;
+            }
+            // Between requests the connection may only sit idle for the keep-alive timeout
+            if let Some(options) = keep_alive {
+                if let Err(e) = self.stream.set_read_timeout(Some(options.idle_timeout)) {
+                    warn!("Failed to set idle timeout: {}", e);
+                }
+            }
+        }
+    }
+
+    // Build the response for a parsed request
+    fn dispatch<'a>(request: &'a mut HttpRequest, server: &Arc<Mutex<Server>>, body_stream: Option<&'a RefCell<BodyReader<'a>>>) -> HttpResponse {
+        // Health checks answer before middleware, sessions and routes
+        let (middleware, max_path_depth, sniff_bodies) = {
+            let server_lock = lock_server(server);
+            if server_lock.is_health_check(&request.path) {
+                return health_check_response();
+            }
+            if server_lock.is_stats_path(&request.path) {
+                return stats_response(&server_lock.stats());
+            }
+            if request.method == "OPTIONS" && request.path == "*" {
+                return server_options_response(&server_lock.allowed_methods());
+            }
+            (server_lock.middleware.clone(), server_lock.max_path_depth, server_lock.sniff_bodies)
+        };
+
+        // Sloppy clients leave out the Content-Type, label the body by its content
+        if sniff_bodies && !request.body.is_empty() && request.header("Content-Type").is_none() {
+            let content_type = match request.sniff_body() {
+                SniffedBody::Json => Some("application/json"),
+                SniffedBody::Form => Some("application/x-www-form-urlencoded"),
+                SniffedBody::Raw => None,
             };
+            if let Some(content_type) = content_type {
+                debug!("Sniffed body as {}", content_type);
+                request.headers.push(format!("Content-Type: {}", content_type));
+            }
+        }
+
+        // Nobody waits for an answer past the caller's deadline
+        if request.deadline().is_some_and(|deadline| deadline <= SystemTime::now()) {
+            debug!("Request deadline already passed");
+            return HttpResponse::error(504, "Request deadline exceeded");
+        }
 
-            // Add Set-Cookie header if session ID is new
-            response.headers.insert("Set-Cookie".to_string(), format!("sessionId={}; Path=/", session_id));
+        // Middleware runs without holding the server lock
+        let early = max_path_depth
+            .and_then(|limit| middleware::check_path_depth(request, limit))
+            .or_else(|| middleware::run(&middleware, request));
+        if let Some(response) = early {
+            return response;
+        }
+        let request = &*request;
+
+        // Copy what the request needs out of the server, nothing below runs under its lock
+        let server_lock = lock_server(server);
+        let guard = server_lock.auth_guard(&request.path);
+        let route = server_lock.router.find(&request.method, &request.path);
+        let allowed = (request.method == "OPTIONS").then(|| server_lock.allowed_methods_for(&request.path));
+        let static_files = server_lock.static_files.clone();
+        let handler_timeout = server_lock.handler_timeout;
+        let error_details = server_lock.error_details;
+        let state = server_lock.state.clone();
+        let cookie_sessions = server_lock.cookie_sessions.clone().filter(|_| server_lock.sessions_enabled);
+        let sessions = (server_lock.sessions_enabled && cookie_sessions.is_none()).then(|| Arc::clone(&server_lock.sessions));
+        let cookie_secret = server_lock.cookie_secret.clone();
+        drop(server_lock);
+
+        // Protected paths need valid Basic credentials
+        if let Some(challenge) = guard.and_then(|guard| guard.authorize(request)) {
+            return challenge;
+        }
 
-            let full_response = response.to_string();
+        // Handle the session cookie under the store's own lock
+        let secret = cookie_secret.as_deref();
+        let session_cookie = match cookie_sessions {
+            Some(cookie_sessions) => Some(cookie_sessions.resolve(request.cookie.as_deref())),
+            None => sessions.map(|sessions| {
+                let session_id = sessions.resolve(request.cookie.as_deref().and_then(|cookie| session::id_from_cookie(cookie, secret)));
+                session::cookie_value(&session_id, secret)
+            }),
+        };
 
-            // Send the response back to the client
-            if let Err(e) = self.send_response(&full_response) {
-                eprintln!("Failed to send response: {}", e);
+        // Registered routes take precedence over the file handlers
+        let mut response = match route {
+            RouteMatch::Found(Matched { route, params }) => match route.timeout.or(handler_timeout) {
+                Some(timeout) => run_with_timeout(&route, Context::new(request, params, state), timeout, error_details),
+                None => call_handler(&route, &Context::new(request, params, state).with_body_stream(body_stream))
+                    .unwrap_or_else(|detail| handler_failed(&route, &detail, error_details)),
+            },
+            RouteMatch::Redirect(location) => {
+                let mut headers = HashMap::new();
+                headers.insert("Location".to_string(), location);
+                HttpResponse::new(301, headers, None)
+            }
+            // Static files are served for GET requests under their prefix
+            RouteMatch::NotFound if request.method == "GET"
+                && static_files.as_ref().is_some_and(|files| files.covers(&request.path)) =>
+            {
+                static_files.unwrap().serve(request)
+            }
+            // Handle request based on method
+            // Writes don't overwrite a file changed since the client's If-Unmodified-Since
+            RouteMatch::NotFound if matches!(request.method.as_str(), "PUT" | "DELETE")
+                && resource_modified(&request.path).is_some_and(|modified| !conditional::unmodified_since(request, modified)) =>
+            {
+                conditional::precondition_failed()
             }
+            // Only the file handlers take the parsed body, routes parse their own
+            RouteMatch::NotFound => match request.method.as_str() {
+                "GET" => handle_get(&request.path),
+                "DELETE" => handle_delete(&request.path),
+                // Without a route of its own, OPTIONS on a path lists what that path answers
+                "OPTIONS" => server_options_response(&allowed.unwrap_or_default()),
+                method @ ("POST" | "PUT" | "PATCH") => match parse_json_body(request) {
+                    Ok(json_body) if method == "POST" => handle_post(&request.path, json_body.as_ref()),
+                    Ok(json_body) if method == "PUT" => handle_put(&request.path, json_body.as_ref()),
+                    Ok(json_body) => handle_patch(&request.path, json_body.as_ref()),
+                    Err(e) => {
+                        warn!("Invalid JSON body: {}", e);
+                        HttpResponse::error(400, &format!("Invalid JSON body: {}", e))
+                    }
+                },
+                _ => handle_method_not_allowed(),
+            },
+        };
 
-            // Log the response
-            
-            println!("Sent Response: {}", full_response);
+        // Add Set-Cookie header if session ID is new, next to any cookie the handler set
+        if let Some(session_cookie) = session_cookie {
+            response = response.append_header("Set-Cookie", &format!("sessionId={}; Path=/", session_cookie));
         }
+        response
+    }
+
+    // Parse the incoming request with the default options
+    #[cfg(test)]
+    fn parse_request(&mut self) -> Result<HttpRequest, RequestError> {
+        self.parse_request_with(&ParseOptions::default())
     }
 
     // Parse the incoming request and extract cookie if available
-    fn parse_request(&mut self) -> Option<HttpRequest> {
-        let mut buffer = [0; 1024];
-        let bytes_read = match self.stream.read(&mut buffer) {
-            Ok(bytes_read) => bytes_read,
-            Err(e) => {
-                eprintln!("Failed to read from stream: {}", e);
-                return None;
+    pub fn parse_request_with(&mut self, options: &ParseOptions) -> Result<HttpRequest, RequestError> {
+        let (mut request, received) = self.parse_head(options)?;
+        let framing = body_framing(&request, &received, options)?;
+        request.body = self.read_request_body(&mut request, &received, framing, options)?;
+        self.buffer = received;
+        Ok(request)
+    }
+
+    // Parse the request line and headers, returning the bytes received after them
+    fn parse_head(&mut self, options: &ParseOptions) -> Result<(HttpRequest, Vec<u8>), RequestError> {
+        let buffer_size = options.read_buffer_size.max(1);
+        let mut received = std::mem::take(&mut self.buffer);
+        received.clear();
+        received.append(&mut self.pending);
+        let mut searched: usize = 0;
+        // Where the line being received starts
+        let mut line_start: usize = 0;
+
+        // Keep reading until the blank line ending the headers arrives
+        let header_end = loop {
+            // Only scan the new bytes, plus enough old ones to catch a split terminator
+            let from = searched.saturating_sub(3);
+            let found = find_header_end(&received[from..], options.strict_line_endings).map(|(end, begin)| (from + end, from + begin));
+
+            // Bound each line as it accumulates, stopping at the body
+            let scan_end = found.map_or(received.len(), |(end, _)| end);
+            let mut longest = 0;
+            for position in (searched..scan_end).filter(|&position| received[position] == b'\n') {
+                longest = longest.max(position - line_start);
+                line_start = position + 1;
+            }
+            if longest.max(scan_end.saturating_sub(line_start)) > options.max_header_line_size {
+                warn!("Request header line exceeds {} bytes", options.max_header_line_size);
+                return Err(RequestError::Rejected(431, "Request header line too large".to_string()));
+            }
+            if found.is_some() {
+                break found;
+            }
+            searched = received.len();
+            if received.len() > options.max_header_size {
+                warn!("Request headers exceed {} bytes", options.max_header_size);
+                return Err(RequestError::Rejected(431, "Request header fields too large".to_string()));
+            }
+
+            match self.read_into(&mut received, buffer_size) {
+                Ok(0) => break None,
+                Ok(_) => {}
+                Err(e) if !received.is_empty() && matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {
+                    warn!("Timed out waiting for the headers");
+                    return Err(RequestError::Rejected(408, "Request timed out".to_string()));
+                }
+                // A new connection that never sends its request line gets told why it's closed,
+                // an idle keep-alive connection just ends
+                Err(e) if self.requests_read == 0 && matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {
+                    warn!("Timed out waiting for the request line");
+                    return Err(RequestError::Rejected(408, "Request timed out".to_string()));
+                }
+                Err(e) => {
+                    warn!("Failed to read from stream: {}", e);
+                    return Err(RequestError::Closed);
+                }
+            }
+        };
+
+        // Split the headers from the start of the body
+        let (header_bytes, body_begin) = match header_end {
+            Some((header_end, _)) if header_end > options.max_header_size => {
+                warn!("Request headers exceed {} bytes", options.max_header_size);
+                return Err(RequestError::Rejected(431, "Request header fields too large".to_string()));
             }
+            Some((header_end, body_begin)) => (&received[..header_end], body_begin),
+            None => (&received[..], received.len()),
         };
 
-        let request_str = String::from_utf8_lossy(&buffer[..bytes_read]);
-        let mut headers_and_body = request_str.split("\r\n\r\n");
+        // Strict mode only accepts CRLF line endings
+        if options.strict_line_endings && has_bare_lf(header_bytes) {
+            warn!("Malformed request: Bare LF line ending.");
+            return Err(RequestError::Rejected(400, "Bare LF line endings are not allowed".to_string()));
+        }
 
-        let header_part = headers_and_body.next().unwrap_or_default();
+        // Reject invalid bytes instead of silently replacing them
+        let Ok(header_part) = std::str::from_utf8(header_bytes) else {
+            warn!("Malformed request: Invalid UTF-8 in the headers.");
+            return Err(RequestError::Rejected(400, "Invalid UTF-8 in request headers".to_string()));
+        };
         if header_part.is_empty() {
             // Malformed request: No headers
-            eprintln!("Malformed request: No headers.");
-            return None;
+            warn!("Malformed request: No headers.");
+            return Err(RequestError::Closed);
         }
 
-        let body_part = headers_and_body.next().unwrap_or_default().to_string();
-
         let mut header_lines = header_part.lines();
         let request_line = header_lines.next().unwrap_or_default();
+        if !request_line.is_ascii() {
+            warn!("Malformed request: Non-ASCII request line.");
+            return Err(RequestError::Rejected(400, "Request line must be ASCII".to_string()));
+        }
+
+        if options.hardened {
+            validate_request_line(request_line)?;
+        }
 
         let mut request_parts = request_line.split_whitespace();
-        let method = request_parts.next().unwrap_or("").to_string();
+        // Lenient parsing takes `get` to mean GET
+        let method = request_parts.next().unwrap_or("").to_ascii_uppercase();
         if method.is_empty() {
             // Malformed request: No HTTP method
-            eprintln!("Malformed request: No HTTP method.");
-            return None;
+            warn!("Malformed request: No HTTP method.");
+            return Err(RequestError::Closed);
         }
 
-        let path = request_parts.next().unwrap_or("").to_string();
-        let _headers: Vec<String> = header_lines.map(|h| h.to_string()).collect();
+        let mut path = request_parts.next().unwrap_or("").to_string();
+        let version = request_parts.next().unwrap_or("HTTP/1.1").to_string();
+
+        // Only HTTP/1.x is spoken here; this also catches the HTTP/2 connection
+        // preface `PRI * HTTP/2.0`, whose `SM` line would otherwise read as a request
+        if version.strip_prefix("HTTP/").is_some_and(|number| !number.starts_with("1.")) {
+            warn!("Unsupported protocol version {}", version);
+            return Err(RequestError::Rejected(505, format!("{} is not supported, use HTTP/1.1", version)));
+        }
+
+        // Bound the map handlers build from the query string
+        let query_params = path
+            .split_once('?')
+            .map_or(0, |(_, query)| query.split('&').filter(|pair| !pair.is_empty()).count());
+        if query_params > options.max_query_params {
+            warn!("Request has {} query parameters", query_params);
+            return Err(RequestError::Rejected(400, format!("More than {} query parameters", options.max_query_params)));
+        }
+        let mut headers: Vec<String> = header_lines.map(|h| h.to_string()).collect();
+        if options.hardened && headers.iter().any(|header| header.contains('\0')) {
+            warn!("Malformed request: Null byte in a header.");
+            return Err(RequestError::Rejected(400, "Null byte in request headers".to_string()));
+        }
+
+        // An absolute-form target carries the host, which replaces any Host header
+        if let Some((authority, origin_path)) = split_absolute_form(&path) {
+            headers.retain(|h| !h.split(':').next().unwrap_or_default().trim().eq_ignore_ascii_case("Host"));
+            headers.push(format!("Host: {}", authority));
+            path = origin_path;
+        }
 
         // Extract cookie from headers if present
-        let cookie_header = _headers.iter().find(|h| h.starts_with("Cookie"));
+        let cookie_header = headers.iter().find(|h| h.starts_with("Cookie"));
         let cookie = cookie_header.and_then(|h| {
             h.split('=').nth(1).map(|c| c.trim().to_string()) // Extract the sessionId value
         });
 
-        Some(HttpRequest {
+        let request = HttpRequest {
             method,
             path,
-            _headers,
-            body: body_part,
+            version,
+            headers,
+            body: Vec::new(),
             cookie, // Include the cookie if available
-        })
+            extensions: HashMap::new(),
+            typed_extensions: Default::default(),
+            cancellation: CancellationToken::new(),
+            peer: self.stream.peer_addr().ok(),
+        };
+
+        received.drain(..body_begin);
+        Ok((request, received))
+    }
+
+    // Get the body of a parsed request to its handler: read in full, left for a streaming
+    // route, or never asked for when a client expecting 100 Continue would be rejected anyway
+    fn receive_body(&mut self, request: &mut HttpRequest, received: Vec<u8>, server: &Arc<Mutex<Server>>, options: &ParseOptions) -> Result<PendingBody, RequestError> {
+        let framing = body_framing(request, &received, options)?;
+        // Chunked wins over a Content-Length sent with it, and the connection closes after
+        // the answer since the two sides may disagree on where the next request starts
+        if framing == BodyFraming::Chunked && request.header("Content-Length").is_some() {
+            warn!("Dropping Content-Length sent with chunked Transfer-Encoding, closing after the response");
+            request.headers.retain(|h| !h.split(':').next().unwrap_or_default().trim().eq_ignore_ascii_case("Content-Length"));
+            request.headers.push("Connection: close".to_string());
+        }
+        let expects_continue = request.expects_continue() && framing != BodyFraming::Empty;
+        let (streams, guard) = {
+            let server_lock = lock_server(server);
+            (server_lock.streams_body(request), server_lock.auth_guard(&request.path).filter(|_| expects_continue))
+        };
+        let challenged = guard.is_some_and(|guard| guard.authorize(request).is_some());
+
+        // Send the final answer instead of 100 Continue so the client skips the upload
+        if challenged {
+            return Ok(PendingBody::Skipped);
+        }
+        if expects_continue {
+            self.stream
+                .write_all(b"HTTP/1.1 100 Continue\r\n\r\n")
+                .and_then(|_| self.stream.flush())
+                .map_err(|_| RequestError::Closed)?;
+        }
+
+        // Streaming routes read the body themselves while the handler runs
+        if streams {
+            return Ok(PendingBody::Streamed(received, framing));
+        }
+        request.body = self.read_request_body(request, &received, framing, options)?;
+        self.buffer = received;
+        Ok(PendingBody::Read)
+    }
+
+    // Read the body as framed by Transfer-Encoding or Content-Length
+    fn read_request_body(&mut self, request: &mut HttpRequest, body_start: &[u8], framing: BodyFraming, options: &ParseOptions) -> Result<Vec<u8>, RequestError> {
+        match framing {
+            BodyFraming::Chunked => {
+                let (body, trailers) = self.read_chunked_body(body_start, options)?;
+                let allowed = allowed_trailers(request);
+                request.headers.extend(trailers.into_iter().filter(|trailer| {
+                    let name = trailer.split(':').next().unwrap_or_default().trim().to_ascii_lowercase();
+                    allowed.contains(&name)
+                }));
+                Ok(body)
+            }
+            // Read the rest of the body declared by Content-Length
+            BodyFraming::Length(length) => self.read_body(body_start, length, options.read_buffer_size),
+            // Without a length the request has no body, anything after the headers
+            // is the next request
+            BodyFraming::Empty => {
+                self.keep_pending(body_start);
+                Ok(Vec::new())
+            }
+        }
+    }
+
+    // Read exactly `length` body bytes, starting with the ones that came with the headers
+    fn read_body(&mut self, received: &[u8], length: usize, buffer_size: usize) -> Result<Vec<u8>, RequestError> {
+        let mut body = received.to_vec();
+
+        while body.len() < length {
+            let remaining = length - body.len();
+            self.fill_buffer(&mut body, buffer_size.min(remaining))?;
+        }
+
+        self.keep_pending(&body[length..]);
+        body.truncate(length);
+        Ok(body)
+    }
+
+    // Hold on to bytes that belong to the next request on the connection,
+    // skipping blank lines some clients send between requests
+    fn keep_pending(&mut self, bytes: &[u8]) {
+        let start = bytes.iter().position(|byte| *byte != b'\r' && *byte != b'\n').unwrap_or(bytes.len());
+        self.pending.clear();
+        self.pending.extend_from_slice(&bytes[start..]);
+    }
+
+    // Decode a chunked body, starting with the bytes that came with the headers.
+    // Returns the body and the raw trailer lines sent after the last chunk.
+    fn read_chunked_body(&mut self, received: &[u8], options: &ParseOptions) -> Result<(Vec<u8>, Vec<String>), RequestError> {
+        let invalid = || RequestError::Rejected(400, "Invalid chunked body".to_string());
+        let buffer_size = options.read_buffer_size;
+        let mut buffer = received.to_vec();
+        let mut pos = 0;
+        let mut body = Vec::new();
+
+        loop {
+            let line = self.read_line(&mut buffer, &mut pos, buffer_size)?;
+            let size = line.split(';').next().unwrap_or_default().trim();
+            let size = usize::from_str_radix(size, 16).map_err(|_| invalid())?;
+
+            if size == 0 {
+                // Collect the trailer section up to the final blank line
+                let mut trailers = Vec::new();
+                loop {
+                    let line = self.read_line(&mut buffer, &mut pos, buffer_size)?;
+                    if line.is_empty() {
+                        self.keep_pending(&buffer[pos..]);
+                        return Ok((body, trailers));
+                    }
+                    trailers.push(line);
+                }
+            }
+
+            if options.max_body_size.is_some_and(|limit| body.len().saturating_add(size) > limit) {
+                warn!("Chunked body exceeds {:?} bytes", options.max_body_size);
+                return Err(RequestError::Rejected(413, "Request body too large".to_string()));
+            }
+            let chunk_end = pos.checked_add(size).filter(|end| end.checked_add(2).is_some()).ok_or_else(invalid)?;
+            while buffer.len() < chunk_end + 2 {
+                self.fill_buffer(&mut buffer, buffer_size)?;
+            }
+            if &buffer[chunk_end..chunk_end + 2] != b"\r\n" {
+                return Err(invalid());
+            }
+
+            body.extend_from_slice(&buffer[pos..chunk_end]);
+            pos = chunk_end + 2;
+        }
+    }
+
+    // Read the next CRLF terminated line of a chunked body
+    fn read_line(&mut self, buffer: &mut Vec<u8>, pos: &mut usize, buffer_size: usize) -> Result<String, RequestError> {
+        loop {
+            if let Some(offset) = buffer[*pos..].windows(2).position(|window| window == b"\r\n") {
+                let line = String::from_utf8_lossy(&buffer[*pos..*pos + offset]).to_string();
+                *pos += offset + 2;
+                return Ok(line);
+            }
+            self.fill_buffer(buffer, buffer_size)?;
+        }
+    }
+
+    // Read up to `size` more body bytes, failing if the client closes or stalls first
+    fn fill_buffer(&mut self, buffer: &mut Vec<u8>, size: usize) -> Result<(), RequestError> {
+        match self.read_into(buffer, size) {
+            Ok(0) => {
+                warn!("Incomplete body: connection closed after {} bytes", buffer.len());
+                Err(RequestError::Rejected(400, "Incomplete body".to_string()))
+            }
+            Ok(_) => Ok(()),
+            Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {
+                warn!("Timed out waiting for the body");
+                Err(RequestError::Rejected(408, "Request timed out".to_string()))
+            }
+            Err(e) => {
+                warn!("Failed to read body: {}", e);
+                Err(RequestError::Closed)
+            }
+        }
+    }
+
+    // Read up to `size` bytes straight onto the end of `buffer`
+    fn read_into(&mut self, buffer: &mut Vec<u8>, size: usize) -> std::io::Result<usize> {
+        let len = buffer.len();
+        buffer.resize(len + size.max(1), 0);
+        let read = self.stream.read(&mut buffer[len..]);
+        buffer.truncate(len + *read.as_ref().unwrap_or(&0));
+        read
+    }
+
+    // Drain unread request bytes for a short while before the connection is closed
+    fn discard_input(&mut self) {
+        let _ = self.stream.set_read_timeout(Some(Duration::from_millis(100)));
+        let mut buffer = [0; 1024];
+        let mut discarded = 0;
+        while discarded < 64 * 1024 {
+            match self.stream.read(&mut buffer) {
+                Ok(0) | Err(_) => break,
+                Ok(bytes_read) => discarded += bytes_read,
+            }
+        }
     }
 
     // Send the response back to the client
-    fn send_response(&mut self, response: &str) -> std::io::Result<()> {
-        self.stream.write_all(response.as_bytes())?;
-        self.stream.flush()
+    fn send_response(&mut self, response: &HttpResponse) -> std::io::Result<()> {
+        // Coalesce the status line, headers and body into as few writes as possible
+        let mut writer = BufWriter::with_capacity(WRITE_BUFFER_SIZE, &mut self.stream);
+        response.write_to(&mut writer)?;
+        writer.flush()
+    }
+}
+
+// Where the body of a parsed request is
+enum PendingBody {
+    // Read into `request.body`
+    Read,
+    // Still on the connection for a streaming handler, after the bytes already received
+    Streamed(Vec<u8>, BodyFraming),
+    // Never sent, the client waits for a 100 Continue it won't get
+    Skipped,
+}
+
+// Split an absolute-form target like `http://example.com/path` into its
+// authority and origin-form path
+fn split_absolute_form(target: &str) -> Option<(String, String)> {
+    let (scheme, rest) = target.split_once("://")?;
+    if !scheme.eq_ignore_ascii_case("http") && !scheme.eq_ignore_ascii_case("https") {
+        return None;
+    }
+
+    let end = rest.find(['/', '?']).unwrap_or(rest.len());
+    let (authority, path) = rest.split_at(end);
+    // Drop any userinfo before the host
+    let authority = authority.rsplit('@').next().unwrap_or_default();
+    if authority.is_empty() {
+        return None;
+    }
+
+    let path = if path.starts_with('/') { path.to_string() } else { format!("/{}", path) };
+    Some((authority.to_string(), path))
+}
+
+// Check that a request line is exactly `METHOD SP target SP HTTP/x.y`
+fn validate_request_line(line: &str) -> Result<(), RequestError> {
+    let reject = |reason: &str| {
+        warn!("Malformed request: {}.", reason);
+        Err(RequestError::Rejected(400, format!("Malformed request line: {}", reason)))
+    };
+
+    if line.bytes().any(|byte| byte.is_ascii_control()) {
+        return reject("control character");
+    }
+    let [method, target, version] = line.split(' ').collect::<Vec<_>>()[..] else {
+        return reject("expected method, target and version separated by single spaces");
+    };
+    if method.is_empty() || !method.bytes().all(is_token_byte) {
+        return reject("invalid method");
+    }
+    // Methods are case-sensitive, `get` is not GET
+    if method.bytes().any(|byte| byte.is_ascii_lowercase()) {
+        return reject("method must be uppercase");
+    }
+    if target.is_empty() {
+        return reject("missing target");
+    }
+    let valid_version = version
+        .strip_prefix("HTTP/")
+        .is_some_and(|number| matches!(number.as_bytes(), [major, b'.', minor] if major.is_ascii_digit() && minor.is_ascii_digit()));
+    if !valid_version {
+        return reject("invalid version");
+    }
+    Ok(())
+}
+
+// Check for a character allowed in a token like the method name
+fn is_token_byte(byte: u8) -> bool {
+    byte.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&byte)
+}
+
+// Dispatch a POST as the method named in X-HTTP-Method-Override, for clients
+// that can only send GET and POST
+fn apply_method_override(request: &mut HttpRequest) {
+    if request.method != "POST" {
+        return;
+    }
+    let Some(method) = request.header("X-HTTP-Method-Override").map(str::to_ascii_uppercase) else {
+        return;
+    };
+    if matches!(method.as_str(), "PUT" | "PATCH" | "DELETE") {
+        debug!("Overriding POST with {}", method);
+        request.method = method;
+    }
+}
+
+// Parse the body for the file handlers, a body declared as JSON must be valid
+fn parse_json_body(request: &HttpRequest) -> Result<Option<serde_json::Value>, serde_json::Error> {
+    if request.body.is_empty() {
+        return Ok(None);
+    }
+    // Parse straight from the bytes; invalid UTF-8 is a parse error
+    match serde_json::from_slice::<serde_json::Value>(&request.body) {
+        Ok(json) => Ok(Some(json)),
+        Err(e) if request.is_json() => Err(e),
+        Err(_) => Ok(None),
+    }
+}
+
+// Run a route handler, catching a panic as its message
+fn call_handler(route: &Route, ctx: &Context) -> Result<HttpResponse, String> {
+    panic::catch_unwind(AssertUnwindSafe(|| (route.handler)(ctx))).map_err(|payload| {
+        payload
+            .downcast_ref::<&str>()
+            .map(|message| message.to_string())
+            .or_else(|| payload.downcast_ref::<String>().cloned())
+            .unwrap_or_else(|| "unknown panic".to_string())
+    })
+}
+
+// Log why a handler panicked and answer 500, with the reason only when details are exposed
+fn handler_failed(route: &Route, detail: &str, error_details: bool) -> HttpResponse {
+    error!("Handler for {} {} panicked: {}", route.method, route.path, detail);
+    HttpResponse::error(500, if error_details { detail } else { "Internal Server Error" })
+}
+
+// Run a route handler on its own thread, answering 504 if it misses the deadline
+fn run_with_timeout(route: &Route, ctx: Context, timeout: Duration, error_details: bool) -> HttpResponse {
+    let (sender, receiver) = mpsc::channel();
+    let handler_route = route.clone();
+    let request = ctx.request;
+    let (handler_request, params, state) = (request.clone(), ctx.params, ctx.state);
+
+    thread::spawn(move || {
+        let _ = sender.send(call_handler(&handler_route, &Context::new(&handler_request, params, state)));
+    });
+
+    match receiver.recv_timeout(timeout) {
+        Ok(Ok(response)) => response,
+        Ok(Err(detail)) => handler_failed(route, &detail, error_details),
+        Err(RecvTimeoutError::Timeout) => {
+            // Let a cooperative handler stop working on the abandoned response
+            request.cancellation.cancel();
+            warn!("Handler for {} {} timed out after {:?}", route.method, route.path, timeout);
+            HttpResponse::error(504, "Handler timed out")
+        }
+        Err(RecvTimeoutError::Disconnected) => {
+            error!("Handler for {} {} panicked", route.method, route.path);
+            HttpResponse::error(500, "Internal Server Error")
+        }
+    }
+}
+
+// Lowercase names the Trailer header announces, minus fields that must never
+// arrive as trailers because they frame, route or authenticate the request
+fn allowed_trailers(request: &HttpRequest) -> Vec<String> {
+    request
+        .header_values("Trailer")
+        .iter()
+        .flat_map(|value| value.split(','))
+        .map(|name| name.trim().to_ascii_lowercase())
+        .filter(|name| {
+            !name.is_empty()
+                && !matches!(
+                    name.as_str(),
+                    "content-length" | "transfer-encoding" | "host" | "trailer" | "authorization"
+                        | "content-type" | "content-encoding" | "cookie" | "set-cookie"
+                )
+        })
+        .collect()
+}
+
+// Work out how the body is delimited from Transfer-Encoding and Content-Length
+fn body_framing(request: &HttpRequest, body_start: &[u8], options: &ParseOptions) -> Result<BodyFraming, RequestError> {
+    let content_length = content_length(request)?;
+
+    // `identity` means no encoding at all, the body is framed by Content-Length
+    let encoding = request
+        .header("Transfer-Encoding")
+        .filter(|encoding| !encoding.split(',').all(|coding| coding.trim().eq_ignore_ascii_case("identity")));
+    if let Some(encoding) = encoding {
+        let is_chunked = encoding
+            .rsplit(',')
+            .next()
+            .is_some_and(|coding| coding.trim().eq_ignore_ascii_case("chunked"));
+        if !is_chunked {
+            return Err(RequestError::Rejected(501, "Unsupported Transfer-Encoding".to_string()));
+        }
+
+        // Both framings at once is a request smuggling vector
+        if content_length.is_some() && options.reject_length_with_chunked {
+            warn!("Malformed request: Content-Length with chunked Transfer-Encoding.");
+            return Err(RequestError::Rejected(400, "Content-Length not allowed with chunked Transfer-Encoding".to_string()));
+        }
+        return Ok(BodyFraming::Chunked);
+    }
+
+    match content_length {
+        Some(length) if options.max_body_size.is_some_and(|limit| length > limit) => {
+            warn!("Content-Length {} exceeds the body limit", length);
+            Err(RequestError::Rejected(413, "Request body too large".to_string()))
+        }
+        Some(length) => Ok(BodyFraming::Length(length)),
+        // A body without a declared length can't be delimited
+        None if !body_start.is_empty() && matches!(request.method.as_str(), "POST" | "PUT" | "PATCH") => {
+            Err(RequestError::Rejected(411, "Content-Length required".to_string()))
+        }
+        None => Ok(BodyFraming::Empty),
+    }
+}
+
+// Get the declared Content-Length, rejecting invalid or conflicting values
+fn content_length(request: &HttpRequest) -> Result<Option<usize>, RequestError> {
+    let mut length = None;
+
+    for value in request.header_values("Content-Length").into_iter().flat_map(|v| v.split(',')) {
+        let parsed = value.trim().parse::<usize>().map_err(|_| {
+            RequestError::Rejected(400, "Invalid Content-Length".to_string())
+        })?;
+        if length.is_some_and(|existing| existing != parsed) {
+            warn!("Malformed request: Conflicting Content-Length headers.");
+            return Err(RequestError::Rejected(400, "Conflicting Content-Length headers".to_string()));
+        }
+        length = Some(parsed);
+    }
+
+    Ok(length)
+}
+
+// Find where the header section ends and where the body starts
+fn find_header_end(bytes: &[u8], strict: bool) -> Option<(usize, usize)> {
+    if strict {
+        return bytes.windows(4).position(|window| window == b"\r\n\r\n").map(|end| (end, end + 4));
     }
+
+    // Accept bare LF as a line terminator too
+    bytes.iter().enumerate().find_map(|(i, &byte)| {
+        if byte != b'\n' {
+            return None;
+        }
+        let header_end = if i > 0 && bytes[i - 1] == b'\r' { i - 1 } else { i };
+        match &bytes[i + 1..] {
+            [b'\n', ..] => Some((header_end, i + 2)),
+            [b'\r', b'\n', ..] => Some((header_end, i + 3)),
+            _ => None,
+        }
+    })
+}
+
+// Check for a LF that isn't part of a CRLF pair
+fn has_bare_lf(bytes: &[u8]) -> bool {
+    bytes.iter().enumerate().any(|(i, &byte)| byte == b'\n' && (i == 0 || bytes[i - 1] != b'\r'))
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
-    use std::net::{TcpListener, TcpStream};
+    use std::net::{Shutdown, TcpListener, TcpStream};
     use std::sync::{Arc, Mutex};
     use std::io::Write;
     use crate::server::Server;
-    use crate::request::HttpRequest;
-    
+    use crate::session::MemorySessionStore;
+    use crate::test_util::read_full_response;
+    use crate::router::TrailingSlash;
+    use crate::error::HttpError;
+    use crate::negotiate::Representations;
+    use std::time::Duration;
+
+    // Send a raw request through Client::handle and collect the full response
+    fn send_request(server: Arc<Mutex<Server>>, request: &[u8]) -> String {
+        let request = request.to_vec();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let handle = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            stream.write_all(&request).unwrap();
+            stream.flush().unwrap();
+            stream.shutdown(Shutdown::Write).unwrap();
+
+            read_full_response(&mut stream)
+        });
+
+        let stream = TcpStream::connect(addr).unwrap();
+        let mut client = Client::new(stream);
+        client.handle(server, None);
+        drop(client);
+
+        handle.join().unwrap()
+    }
+
+    fn about_server(policy: TrailingSlash) -> Arc<Mutex<Server>> {
+        let mut server = Server::new();
+        server.set_trailing_slash(policy);
+        server.route("GET", "/about", |_| {
+            HttpResponse::new(200, HashMap::new(), Some("about page".to_string()))
+        });
+        Arc::new(Mutex::new(server))
+    }
 
     #[test]
     // Verify that a client may handle a request, simulate a session and returns a valid response
@@ -129,7 +1070,7 @@ mod test {
 
         // Defines a session with ID 1234
         {
-            let mut server_lock = server.lock().unwrap();
+            let server_lock = server.lock().unwrap();
             server_lock.sessions.insert("1234".to_string(), "user_data".to_string());
         }
 
@@ -144,9 +1085,9 @@ mod test {
         });
 
         let stream = TcpStream::connect(addr).unwrap();
-        let mut client = Client { stream };
+        let mut client = Client::new(stream);
 
-        client.handle(Arc::clone(&server));
+        client.handle(Arc::clone(&server), None);
 
         handle.join().unwrap();
 
@@ -169,7 +1110,7 @@ mod test {
         });
 
         let stream = TcpStream::connect(addr).unwrap();
-        let mut client = Client { stream };
+        let mut client = Client::new(stream);
 
         let parsed_request = client.parse_request().unwrap();
 
@@ -189,19 +1130,1687 @@ mod test {
 
         let handle = std::thread::spawn(move || {
             let (mut stream, _) = listener.accept().unwrap();
-            let mut buffer = [0; 512];
-            let bytes_read = stream.read(&mut buffer).unwrap();
-            let response = String::from_utf8_lossy(&buffer[..bytes_read]);
+            let response = read_full_response(&mut stream);
 
             assert!(response.contains("HTTP/1.1 200 OK"));
         });
 
         let stream = TcpStream::connect(addr).unwrap();
-        let mut client = Client { stream };
-        let response = "HTTP/1.1 200 OK\r\n\r\n";
-        client.send_response(response).unwrap();
+        let mut client = Client::new(stream);
+        let response = HttpResponse::new(200, HashMap::new(), None);
+        client.send_response(&response).unwrap();
 
         handle.join().unwrap();
     }
 
+    #[test]
+    // Verify that a response larger than one 1024-byte read arrives whole while the connection stays open
+    fn test_send_response_larger_than_one_read() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let body = "y".repeat(5000);
+        let expected = body.clone();
+
+        let handle = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            read_full_response(&mut stream)
+        });
+
+        let stream = TcpStream::connect(addr).unwrap();
+        let mut client = Client::new(stream);
+        client.send_response(&HttpResponse::new(200, HashMap::new(), Some(body))).unwrap();
+
+        let response = handle.join().unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.ends_with(&expected));
+    }
+
+
+    #[test]
+    // Verify that the redirect policy answers a trailing slash with a 301 to the registered route
+    fn test_trailing_slash_redirect() {
+        let response = send_request(about_server(TrailingSlash::Redirect), b"GET /about/ HTTP/1.1\r\n\r\n");
+
+        assert!(response.starts_with("HTTP/1.1 301 Moved Permanently"));
+        assert!(response.contains("Location: /about\r\n"));
+    }
+
+    #[test]
+    // Verify that the merge policy serves the registered route for both forms of the path
+    fn test_trailing_slash_merge() {
+        let server = about_server(TrailingSlash::Merge);
+
+        let with_slash = send_request(Arc::clone(&server), b"GET /about/ HTTP/1.1\r\n\r\n");
+        let without_slash = send_request(server, b"GET /about HTTP/1.1\r\n\r\n");
+
+        assert!(with_slash.starts_with("HTTP/1.1 200 OK"));
+        assert!(with_slash.ends_with("about page"));
+        assert!(without_slash.ends_with("about page"));
+    }
+
+    #[test]
+    // Verify that the default strict policy does not route the trailing slash form
+    fn test_trailing_slash_strict_by_default() {
+        let server = Arc::new(Mutex::new(Server::new()));
+        server.lock().unwrap().route("GET", "/about", |_| {
+            HttpResponse::new(200, HashMap::new(), Some("about page".to_string()))
+        });
+
+        let response = send_request(server, b"GET /about/ HTTP/1.1\r\n\r\n");
+
+        assert!(response.starts_with("HTTP/1.1 404 Not Found"));
+    }
+
+
+    #[test]
+    // Verify that the health check answers 200 with its status body and skips the session cookie
+    fn test_health_check_endpoint() {
+        let store = Arc::new(MemorySessionStore::new());
+        let mut server = Server::new();
+        server.set_session_store(store.clone());
+        server.enable_health_check("/healthz");
+
+        let response = send_request(Arc::new(Mutex::new(server)), b"GET /healthz HTTP/1.1\r\n\r\n");
+
+        assert!(response.starts_with("HTTP/1.1 200 OK"));
+        assert!(response.ends_with("{\"status\":\"ok\"}"));
+        assert!(!response.contains("Set-Cookie"));
+        assert!(store.is_empty());
+    }
+
+
+    fn admin_server() -> Arc<Mutex<Server>> {
+        let mut server = Server::new();
+        server.protect_basic("/admin", "Admin area", "admin", "secret");
+        server.route("GET", "/admin/panel", |_| {
+            HttpResponse::new(200, HashMap::new(), Some("panel".to_string()))
+        });
+        Arc::new(Mutex::new(server))
+    }
+
+    #[test]
+    // Verify that a protected route without credentials gets a 401 with the Basic challenge
+    fn test_basic_auth_missing_credentials() {
+        let response = send_request(admin_server(), b"GET /admin/panel HTTP/1.1\r\n\r\n");
+
+        assert!(response.starts_with("HTTP/1.1 401 Unauthorized"));
+        assert!(response.contains("WWW-Authenticate: Basic realm=\"Admin area\"\r\n"));
+    }
+
+    #[test]
+    // Verify that wrong credentials are rejected with a 401
+    fn test_basic_auth_wrong_credentials() {
+        // "admin:wrong"
+        let response = send_request(admin_server(), b"GET /admin/panel HTTP/1.1\r\nAuthorization: Basic YWRtaW46d3Jvbmc=\r\n\r\n");
+
+        assert!(response.starts_with("HTTP/1.1 401 Unauthorized"));
+    }
+
+    #[test]
+    // Verify that correct credentials reach the handler
+    fn test_basic_auth_correct_credentials() {
+        // "admin:secret"
+        let response = send_request(admin_server(), b"GET /admin/panel HTTP/1.1\r\nAuthorization: Basic YWRtaW46c2VjcmV0\r\n\r\n");
+
+        assert!(response.starts_with("HTTP/1.1 200 OK"));
+        assert!(response.ends_with("panel"));
+    }
+
+    #[test]
+    // Verify that credential checks run without the server lock, so they may take it themselves
+    fn test_basic_auth_check_runs_unlocked() {
+        let shared: Arc<std::sync::OnceLock<Arc<Mutex<Server>>>> = Arc::new(std::sync::OnceLock::new());
+        let lookup = Arc::clone(&shared);
+        let mut server = Server::new();
+        server.protect_basic_with("/admin", "Admin area", move |user, _| {
+            lookup.get().is_some_and(|server| lock_server(server).state.is_none()) && user == "admin"
+        });
+        server.route("GET", "/admin/panel", |_| {
+            HttpResponse::new(200, HashMap::new(), Some("panel".to_string()))
+        });
+        let server = Arc::new(Mutex::new(server));
+        shared.set(Arc::clone(&server)).ok();
+
+        // "admin:secret"
+        let response = send_request(server, b"GET /admin/panel HTTP/1.1\r\nAuthorization: Basic YWRtaW46c2VjcmV0\r\n\r\n");
+
+        assert!(response.starts_with("HTTP/1.1 200 OK"));
+        assert!(response.ends_with("panel"));
+    }
+
+
+    fn echo_server() -> Arc<Mutex<Server>> {
+        let mut server = Server::new();
+        server.route("POST", "/echo", |request| {
+            HttpResponse::from_bytes(200, HashMap::new(), request.body.clone())
+        });
+        Arc::new(Mutex::new(server))
+    }
+
+    #[test]
+    // Verify that a body shorter than its Content-Length is rejected instead of parsed truncated
+    fn test_under_delivered_body() {
+        let response = send_request(echo_server(), b"POST /echo HTTP/1.1\r\nContent-Length: 20\r\n\r\nshort");
+
+        assert!(response.starts_with("HTTP/1.1 400 Bad Request"));
+        assert!(response.contains("Incomplete body"));
+    }
+
+    #[test]
+    // Verify that a body matching its Content-Length reaches the handler complete
+    fn test_correctly_sized_body() {
+        let response = send_request(echo_server(), b"POST /echo HTTP/1.1\r\nContent-Length: 11\r\n\r\nhello world");
+
+        assert!(response.starts_with("HTTP/1.1 200 OK"));
+        assert!(response.ends_with("\r\n\r\nhello world"));
+    }
+
+    #[test]
+    // Verify that a client declaring more bytes than it sends is cut off by the read timeout
+    fn test_stalled_body_times_out() {
+        let server = echo_server();
+        server.lock().unwrap().read_timeout = Some(Duration::from_millis(100));
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let handle = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            stream.write_all(b"POST /echo HTTP/1.1\r\nContent-Length: 20\r\n\r\nshort").unwrap();
+
+            // Keep the connection open without sending the rest
+            let mut response = String::new();
+            stream.read_to_string(&mut response).unwrap();
+            response
+        });
+
+        let stream = TcpStream::connect(addr).unwrap();
+        let mut client = Client::new(stream);
+        client.handle(server, None);
+        drop(client);
+
+        assert!(handle.join().unwrap().starts_with("HTTP/1.1 408 Request Timeout"));
+    }
+
+    #[test]
+    // Verify that a connection sending nothing gets a 408 once the read timeout passes
+    fn test_missing_request_line_times_out() {
+        let server = echo_server();
+        server.lock().unwrap().read_timeout = Some(Duration::from_millis(100));
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let handle = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let started = std::time::Instant::now();
+            let mut response = String::new();
+            stream.read_to_string(&mut response).unwrap();
+            (response, started.elapsed())
+        });
+
+        let stream = TcpStream::connect(addr).unwrap();
+        let mut client = Client::new(stream);
+        client.handle(server, None);
+        drop(client);
+
+        let (response, elapsed) = handle.join().unwrap();
+        assert!(response.starts_with("HTTP/1.1 408 Request Timeout"), "{}", response);
+        assert!(elapsed < Duration::from_secs(2));
+    }
+
+
+    #[test]
+    // Verify that a POST body without Content-Length or chunked encoding gets a 411
+    fn test_body_without_content_length() {
+        let response = send_request(echo_server(), b"POST /echo HTTP/1.1\r\n\r\n{\"key\": \"value\"}");
+
+        assert!(response.starts_with("HTTP/1.1 411 Length Required"));
+    }
+
+
+    #[test]
+    // Verify that the default lenient mode accepts a request using bare LF line endings
+    fn test_bare_lf_request_lenient() {
+        let response = send_request(echo_server(), b"POST /echo HTTP/1.1\nContent-Length: 5\n\nhello");
+
+        assert!(response.starts_with("HTTP/1.1 200 OK"));
+        assert!(response.ends_with("\r\n\r\nhello"));
+    }
+
+    #[test]
+    // Verify that strict mode rejects a request using bare LF line endings
+    fn test_bare_lf_request_strict() {
+        let server = echo_server();
+        server.lock().unwrap().parse_options.strict_line_endings = true;
+
+        let response = send_request(server, b"POST /echo HTTP/1.1\nContent-Length: 5\n\nhello");
+
+        assert!(response.starts_with("HTTP/1.1 400 Bad Request"));
+    }
+
+
+    // Writer that counts how many writes reach it
+    struct CountingWriter {
+        writes: usize,
+        bytes: Vec<u8>,
+    }
+
+    impl Write for CountingWriter {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.writes += 1;
+            self.bytes.extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    // Verify that buffering coalesces a multi-header response into a single underlying write
+    fn test_buffered_response_reduces_writes() {
+        let mut headers = HashMap::new();
+        for i in 0..10 {
+            headers.insert(format!("X-Header-{}", i), "value".to_string());
+        }
+        let response = HttpResponse::new(200, headers, Some("body".to_string()));
+
+        let mut unbuffered = CountingWriter { writes: 0, bytes: Vec::new() };
+        response.write_to(&mut unbuffered).unwrap();
+
+        let mut buffered = CountingWriter { writes: 0, bytes: Vec::new() };
+        {
+            let mut writer = BufWriter::with_capacity(WRITE_BUFFER_SIZE, &mut buffered);
+            response.write_to(&mut writer).unwrap();
+            writer.flush().unwrap();
+        }
+
+        assert!(unbuffered.writes > 10);
+        assert_eq!(buffered.writes, 1);
+        assert_eq!(buffered.bytes, unbuffered.bytes);
+    }
+
+
+    #[test]
+    // Verify that a client that never reads the response is given up on within the write timeout
+    fn test_write_timeout_with_stalled_reader() {
+        let mut server = Server::new();
+        server.write_timeout = Some(Duration::from_millis(200));
+        server.route("GET", "/large", |_| {
+            HttpResponse::new(200, HashMap::new(), Some("x".repeat(32 * 1024 * 1024)))
+        });
+        let server = Arc::new(Mutex::new(server));
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (done_tx, done_rx) = std::sync::mpsc::channel::<()>();
+
+        let handle = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            stream.write_all(b"GET /large HTTP/1.1\r\n\r\n").unwrap();
+
+            // Hold the connection open without reading until the server gives up
+            let _ = done_rx.recv();
+        });
+
+        let stream = TcpStream::connect(addr).unwrap();
+        let mut client = Client::new(stream);
+        let started = std::time::Instant::now();
+        client.handle(server, None);
+        let elapsed = started.elapsed();
+
+        done_tx.send(()).unwrap();
+        handle.join().unwrap();
+
+        assert!(elapsed < Duration::from_secs(5), "Server should give up writing, took {:?}", elapsed);
+    }
+
+
+    #[test]
+    // Verify that the static handler serves the configured 404 page with a 404 status
+    fn test_static_not_found_page() {
+        let root = std::env::temp_dir().join(format!("rust-http-client-404-{}", std::process::id()));
+        std::fs::create_dir_all(&root).unwrap();
+        std::fs::write(root.join("404.html"), "<h1>Lost?</h1>").unwrap();
+
+        let mut server = Server::new();
+        server.serve_static("/site", root.to_str().unwrap());
+        server.set_not_found_page("404.html");
+
+        let response = send_request(Arc::new(Mutex::new(server)), b"GET /site/nope.html HTTP/1.1\r\n\r\n");
+        std::fs::remove_dir_all(root).unwrap();
+
+        assert!(response.starts_with("HTTP/1.1 404 Not Found"));
+        assert!(response.ends_with("<h1>Lost?</h1>"));
+    }
+
+
+    #[test]
+    // Verify that SPA fallback serves index.html for app routes but still 404s missing assets
+    fn test_static_spa_fallback() {
+        let root = std::env::temp_dir().join(format!("rust-http-client-spa-{}", std::process::id()));
+        std::fs::create_dir_all(&root).unwrap();
+        std::fs::write(root.join("index.html"), "<main>app</main>").unwrap();
+
+        let mut server = Server::new();
+        server.serve_static("/", root.to_str().unwrap());
+        server.set_spa_fallback(true);
+        let server = Arc::new(Mutex::new(server));
+
+        let route = send_request(Arc::clone(&server), b"GET /some/spa/route HTTP/1.1\r\n\r\n");
+        let asset = send_request(server, b"GET /missing.js HTTP/1.1\r\n\r\n");
+        std::fs::remove_dir_all(root).unwrap();
+
+        assert!(route.starts_with("HTTP/1.1 200 OK"));
+        assert!(route.ends_with("<main>app</main>"));
+        assert!(asset.starts_with("HTTP/1.1 404 Not Found"));
+    }
+
+
+    #[test]
+    // Verify that two different Content-Length headers are rejected
+    fn test_conflicting_content_lengths() {
+        let response = send_request(echo_server(), b"POST /echo HTTP/1.1\r\nContent-Length: 5\r\nContent-Length: 11\r\n\r\nhello world");
+
+        assert!(response.starts_with("HTTP/1.1 400 Bad Request"));
+        assert!(response.contains("Conflicting Content-Length"));
+    }
+
+    #[test]
+    // Verify that repeating the same Content-Length is accepted
+    fn test_repeated_identical_content_length() {
+        let response = send_request(echo_server(), b"POST /echo HTTP/1.1\r\nContent-Length: 5\r\nContent-Length: 5\r\n\r\nhello");
+
+        assert!(response.starts_with("HTTP/1.1 200 OK"));
+        assert!(response.ends_with("\r\n\r\nhello"));
+    }
+
+    #[test]
+    // Verify that chunked encoding wins over Content-Length by default
+    fn test_content_length_with_chunked_prefers_chunked() {
+        let response = send_request(
+            echo_server(),
+            b"POST /echo HTTP/1.1\r\nContent-Length: 3\r\nTransfer-Encoding: chunked\r\n\r\n5\r\nhello\r\n6\r\n world\r\n0\r\n\r\n",
+        );
+
+        assert!(response.starts_with("HTTP/1.1 200 OK"));
+        assert!(response.ends_with("\r\n\r\nhello world"));
+    }
+
+    #[test]
+    // Verify that Content-Length with chunked encoding is rejected when the policy says so
+    fn test_content_length_with_chunked_rejected() {
+        let server = echo_server();
+        server.lock().unwrap().parse_options.reject_length_with_chunked = true;
+
+        let response = send_request(
+            server,
+            b"POST /echo HTTP/1.1\r\nContent-Length: 3\r\nTransfer-Encoding: chunked\r\n\r\n5\r\nhello\r\n0\r\n\r\n",
+        );
+
+        assert!(response.starts_with("HTTP/1.1 400 Bad Request"));
+    }
+
+    #[test]
+    // Verify that a request with both Content-Length and chunked is answered from the chunks and closes the connection
+    fn test_content_length_with_chunked_closes_connection() {
+        let server = echo_server();
+        server.lock().unwrap().enable_keep_alive();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let handle = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            stream.write_all(
+                b"POST /echo HTTP/1.1\r\nContent-Length: 3\r\nTransfer-Encoding: chunked\r\n\r\n5\r\nhello\r\n0\r\n\r\nPOST /echo HTTP/1.1\r\nContent-Length: 8\r\n\r\nsmuggled",
+            ).unwrap();
+            let mut received = String::new();
+            stream.read_to_string(&mut received).unwrap();
+            received
+        });
+
+        Client::new(TcpStream::connect(addr).unwrap()).handle(server, None);
+        let received = handle.join().unwrap();
+
+        assert!(received.starts_with("HTTP/1.1 200 OK"));
+        assert!(received.contains("Connection: close\r\n"));
+        assert!(received.ends_with("\r\n\r\nhello"));
+        assert!(!received.contains("smuggled"));
+        assert_eq!(received.matches("HTTP/1.1 ").count(), 1);
+    }
+
+    #[test]
+    // Verify that Transfer-Encoding: identity is treated as no encoding and Content-Length frames the body
+    fn test_identity_transfer_encoding() {
+        let response = send_request(
+            echo_server(),
+            b"POST /echo HTTP/1.1\r\nTransfer-Encoding: identity\r\nContent-Length: 5\r\n\r\nhello",
+        );
+
+        assert!(response.starts_with("HTTP/1.1 200 OK"));
+        assert!(response.ends_with("\r\n\r\nhello"));
+    }
+
+
+    #[test]
+    // Verify that a body declared as JSON that fails to parse gets a descriptive 400
+    fn test_invalid_json_body() {
+        let response = send_request(
+            Arc::new(Mutex::new(Server::new())),
+            b"POST /test_invalid_body HTTP/1.1\r\nContent-Type: application/json\r\nContent-Length: 13\r\n\r\n{\"key\": value",
+        );
+
+        assert!(response.starts_with("HTTP/1.1 400 Bad Request"));
+        assert!(response.contains("Invalid JSON body: expected value at line 1 column 9"));
+    }
+
+
+    #[test]
+    // Verify that headers larger than one read buffer and split across writes are parsed completely
+    fn test_parse_headers_across_buffer_boundary() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let handle = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let request = format!("GET /get HTTP/1.1\r\nX-Large: {}\r\nX-After: yes\r\n\r\n", "a".repeat(2048));
+            let (first, second) = request.as_bytes().split_at(1000);
+            stream.write_all(first).unwrap();
+            stream.flush().unwrap();
+            std::thread::sleep(Duration::from_millis(50));
+            stream.write_all(second).unwrap();
+            stream.flush().unwrap();
+        });
+
+        let stream = TcpStream::connect(addr).unwrap();
+        let mut client = Client::new(stream);
+        let parsed_request = client.parse_request().unwrap();
+        handle.join().unwrap();
+
+        assert_eq!(parsed_request.path, "/get");
+        assert_eq!(parsed_request.header("X-Large").unwrap().len(), 2048);
+        assert_eq!(parsed_request.header("X-After"), Some("yes"));
+    }
+
+    #[test]
+    // Verify that headers over the size limit get a 431
+    fn test_headers_over_limit() {
+        let server = Arc::new(Mutex::new(Server::new()));
+        server.lock().unwrap().parse_options.max_header_size = 1024;
+
+        let request = format!("GET /get HTTP/1.1\r\nX-Large: {}\r\n\r\n", "a".repeat(2048));
+        let response = send_request(server, request.as_bytes());
+
+        assert!(response.starts_with("HTTP/1.1 431 Request Header Fields Too Large"));
+    }
+
+    #[test]
+    // Verify that one header line over the line limit gets a 431 even when the headers fit overall
+    fn test_header_line_over_limit() {
+        let server = Arc::new(Mutex::new(Server::new()));
+        {
+            let mut server = server.lock().unwrap();
+            server.parse_options.max_header_size = 1024 * 1024;
+            server.set_max_header_line_size(4096);
+        }
+
+        let within = format!("GET /about HTTP/1.1\r\nX-A: {}\r\nX-B: {}\r\n\r\n", "a".repeat(4000), "b".repeat(4000));
+        let gigantic = format!("GET /about HTTP/1.1\r\nX-Large: {}\r\nX-After: yes\r\n\r\n", "a".repeat(512 * 1024));
+        let within = send_request(Arc::clone(&server), within.as_bytes());
+        let response = send_request(server, gigantic.as_bytes());
+
+        assert!(!within.starts_with("HTTP/1.1 431"), "{}", within);
+        assert!(response.starts_with("HTTP/1.1 431 Request Header Fields Too Large"));
+        assert!(response.contains("Request header line too large"));
+    }
+
+
+    #[test]
+    // Verify that routes use the server's handler timeout unless they override it
+    fn test_per_route_timeout_override() {
+        let mut server = Server::new();
+        server.handler_timeout = Some(Duration::from_millis(100));
+        server.route("GET", "/fast", |_| {
+            std::thread::sleep(Duration::from_millis(300));
+            HttpResponse::new(200, HashMap::new(), Some("fast".to_string()))
+        });
+        server.route_with_timeout("GET", "/report", Duration::from_secs(2), |_| {
+            std::thread::sleep(Duration::from_millis(300));
+            HttpResponse::new(200, HashMap::new(), Some("report".to_string()))
+        });
+        let server = Arc::new(Mutex::new(server));
+
+        // The fast route is held to the 100ms default
+        let fast = send_request(Arc::clone(&server), b"GET /fast HTTP/1.1\r\n\r\n");
+        // The report route gets its own 2s budget
+        let report = send_request(server, b"GET /report HTTP/1.1\r\n\r\n");
+
+        assert!(fast.starts_with("HTTP/1.1 504 Gateway Timeout"));
+        assert!(report.starts_with("HTTP/1.1 200 OK"));
+        assert!(report.ends_with("report"));
+    }
+
+
+    #[test]
+    // Verify that a gzip-negotiated response tells caches it varies on Accept-Encoding
+    fn test_gzip_response_sets_vary() {
+        let mut server = Server::new();
+        server.enable_gzip(256);
+        server.route("GET", "/big", |_| HttpResponse::ok(&"x".repeat(4096)));
+        let server = Arc::new(Mutex::new(server));
+
+        let response = send_request(server, b"GET /big HTTP/1.1\r\nAccept-Encoding: gzip\r\n\r\n");
+
+        assert!(response.starts_with("HTTP/1.1 200 OK"));
+        assert!(response.contains("Content-Encoding: gzip\r\n"));
+        assert!(response.contains("Vary: Accept-Encoding\r\n"));
+    }
+
+    #[test]
+    // Verify that the POST file handler answers 201 on success and 422 for a body it can't store
+    fn test_post_status_codes() {
+        let server = Arc::new(Mutex::new(Server::new()));
+
+        let created = send_request(
+            Arc::clone(&server),
+            b"POST /test_post_status HTTP/1.1\r\nContent-Type: application/json\r\nContent-Length: 12\r\n\r\n{\"name\":\"a\"}",
+        );
+        let invalid = send_request(
+            server,
+            b"POST /test_post_status HTTP/1.1\r\nContent-Type: application/json\r\nContent-Length: 7\r\n\r\n[1,2,3]",
+        );
+        std::fs::remove_file("./files/test_post_status.json").unwrap();
+
+        assert!(created.starts_with("HTTP/1.1 201 Created"));
+        assert!(invalid.starts_with("HTTP/1.1 422 Unprocessable Entity"));
+    }
+
+    #[test]
+    // Verify that a POST with X-HTTP-Method-Override runs the DELETE handler only when enabled
+    fn test_method_override() {
+        let build = |enabled: bool| {
+            let mut server = Server::new();
+            if enabled {
+                server.enable_method_override();
+            }
+            server.route("DELETE", "/items/1", |_| HttpResponse::ok("deleted"));
+            server.route("POST", "/items/1", |_| HttpResponse::ok("posted"));
+            Arc::new(Mutex::new(server))
+        };
+        let request = b"POST /items/1 HTTP/1.1\r\nX-HTTP-Method-Override: delete\r\nContent-Length: 0\r\n\r\n";
+
+        let overridden = send_request(build(true), request);
+        let ignored = send_request(build(false), request);
+
+        assert!(overridden.ends_with("deleted"));
+        assert!(ignored.ends_with("posted"));
+    }
+
+    // In-memory connection that counts the reads made against it
+    struct CountingReader {
+        input: std::io::Cursor<Vec<u8>>,
+        reads: usize,
+    }
+
+    impl Read for CountingReader {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            self.reads += 1;
+            self.input.read(buf)
+        }
+    }
+
+    impl Write for CountingReader {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Connection for CountingReader {
+        fn set_read_timeout(&self, _: Option<Duration>) -> std::io::Result<()> {
+            Ok(())
+        }
+
+        fn set_write_timeout(&self, _: Option<Duration>) -> std::io::Result<()> {
+            Ok(())
+        }
+
+        fn peer_addr(&self) -> std::io::Result<SocketAddr> {
+            Err(std::io::ErrorKind::NotConnected.into())
+        }
+
+        fn shutdown(&self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    // Parse a 64KB POST with the given read buffer size, returning the number of reads
+    fn reads_for_big_body(read_buffer_size: usize) -> usize {
+        let mut input = b"POST /upload HTTP/1.1\r\nContent-Length: 65536\r\n\r\n".to_vec();
+        input.extend(std::iter::repeat_n(b'z', 65536));
+        let mut client = Client::new(CountingReader { input: std::io::Cursor::new(input), reads: 0 });
+        let options = ParseOptions { read_buffer_size, ..ParseOptions::default() };
+
+        let request = client.parse_request_with(&options).unwrap();
+
+        assert_eq!(request.body.len(), 65536);
+        client.stream.reads
+    }
+
+    #[test]
+    // Verify that a larger read buffer receives a big body in fewer reads
+    fn test_read_buffer_size_reduces_reads() {
+        let small = reads_for_big_body(1024);
+        let large = reads_for_big_body(16 * 1024);
+
+        assert!(small >= 64);
+        assert!(large <= 5);
+    }
+
+    #[test]
+    // Verify that an absolute-form target is split into the path and the Host it names
+    fn test_absolute_form_target() {
+        let request = b"GET http://example.com:8080/users/42?full=1 HTTP/1.1\r\nHost: proxy.local\r\n\r\n".to_vec();
+        let mut client = Client::new(CountingReader { input: std::io::Cursor::new(request), reads: 0 });
+
+        let parsed = client.parse_request().unwrap();
+
+        assert_eq!(parsed.path, "/users/42?full=1");
+        assert_eq!(parsed.host(), Some("example.com:8080"));
+        assert_eq!(parsed.header_values("Host").len(), 1);
+    }
+
+    #[test]
+    // Verify that an absolute-form target without a path maps to the root
+    fn test_absolute_form_without_path() {
+        assert_eq!(
+            split_absolute_form("http://example.com?q=1"),
+            Some(("example.com".to_string(), "/?q=1".to_string()))
+        );
+        assert_eq!(split_absolute_form("/plain/path"), None);
+    }
+
+    #[test]
+    // Verify that a cooperative handler sees the cancellation flag once its client disconnects
+    fn test_handler_observes_client_disconnect() {
+        let observed = Arc::new(std::sync::atomic::AtomicBool::new(false));
+        let handler_observed = Arc::clone(&observed);
+        let mut server = Server::new();
+        server.enable_disconnect_watch();
+        server.route("GET", "/report", move |request| {
+            for _ in 0..300 {
+                if request.cancellation.is_cancelled() {
+                    handler_observed.store(true, std::sync::atomic::Ordering::SeqCst);
+                    return HttpResponse::no_content();
+                }
+                std::thread::sleep(Duration::from_millis(10));
+            }
+            HttpResponse::ok("done")
+        });
+        let server = Arc::new(Mutex::new(server));
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let handle = std::thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let started = std::time::Instant::now();
+            Client::new(stream).handle(server, None);
+            started.elapsed()
+        });
+
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream.write_all(b"GET /report HTTP/1.1\r\n\r\n").unwrap();
+        std::thread::sleep(Duration::from_millis(100));
+        drop(stream);
+
+        let elapsed = handle.join().unwrap();
+        assert!(observed.load(std::sync::atomic::Ordering::SeqCst));
+        assert!(elapsed < Duration::from_secs(2));
+    }
+
+    #[test]
+    // Verify that a range request against an in-memory file gets a 206 with just those bytes
+    fn test_range_request_on_memory_file() {
+        let mut server = Server::new();
+        server.serve_bytes("/alphabet.txt", "text/plain", b"abcdefghijklmnopqrstuvwxyz".to_vec());
+        let server = Arc::new(Mutex::new(server));
+
+        let partial = send_request(Arc::clone(&server), b"GET /alphabet.txt HTTP/1.1\r\nRange: bytes=3-5\r\n\r\n");
+        let full = send_request(server, b"GET /alphabet.txt HTTP/1.1\r\n\r\n");
+
+        assert!(partial.starts_with("HTTP/1.1 206 Partial Content"));
+        assert!(partial.contains("Content-Range: bytes 3-5/26\r\n"));
+        assert!(partial.ends_with("\r\n\r\ndef"));
+        assert!(full.starts_with("HTTP/1.1 200 OK"));
+        assert!(full.contains("Accept-Ranges: bytes\r\n"));
+    }
+
+    #[test]
+    // Verify that the index shortcut answers GET /
+    fn test_set_index() {
+        let mut server = Server::new();
+        server.set_index(|_| HttpResponse::ok("welcome"));
+
+        let response = send_request(Arc::new(Mutex::new(server)), b"GET / HTTP/1.1\r\n\r\n");
+
+        assert!(response.starts_with("HTTP/1.1 200 OK"));
+        assert!(response.ends_with("welcome"));
+    }
+
+    #[test]
+    // Verify that trailers announced in the Trailer header are merged into the request headers
+    fn test_chunked_body_trailers() {
+        let request = b"POST /upload HTTP/1.1\r\nTransfer-Encoding: chunked\r\nTrailer: X-Checksum\r\n\r\n\
+            5\r\nhello\r\n0\r\nX-Checksum: abc123\r\nX-Unannounced: 1\r\nContent-Length: 99\r\n\r\n".to_vec();
+        let mut client = Client::new(CountingReader { input: std::io::Cursor::new(request), reads: 0 });
+
+        let parsed = client.parse_request().unwrap();
+
+        assert_eq!(parsed.body, b"hello");
+        assert_eq!(parsed.header("X-Checksum"), Some("abc123"));
+        assert_eq!(parsed.header("X-Unannounced"), None);
+        assert_eq!(parsed.header("Content-Length"), None);
+    }
+
+    #[test]
+    // Verify that a large JSON body is parsed straight from the received bytes
+    fn test_large_json_body() {
+        let mut server = Server::new();
+        server.route("POST", "/bulk", |request| {
+            let items: serde_json::Value = serde_json::from_slice(&request.body).unwrap();
+            HttpResponse::ok(&items.as_array().unwrap().len().to_string())
+        });
+        let items: Vec<serde_json::Value> = (0..5000).map(|i| serde_json::json!({"id": i, "name": "ñandú"})).collect();
+        let body = serde_json::to_vec(&items).unwrap();
+        let mut request = format!(
+            "POST /bulk HTTP/1.1\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n",
+            body.len()
+        ).into_bytes();
+        request.extend_from_slice(&body);
+
+        let response = send_request(Arc::new(Mutex::new(server)), &request);
+
+        assert!(response.starts_with("HTTP/1.1 200 OK"));
+        assert!(response.ends_with("5000"));
+    }
+
+    #[test]
+    // Verify that a JSON body with invalid UTF-8 is a 400 instead of being silently replaced
+    fn test_json_body_invalid_utf8() {
+        let response = send_request(
+            Arc::new(Mutex::new(Server::new())),
+            b"POST /echo HTTP/1.1\r\nContent-Type: application/json\r\nContent-Length: 12\r\n\r\n{\"name\":\"\xff\"}",
+        );
+
+        assert!(response.starts_with("HTTP/1.1 400 Bad Request"));
+        assert!(response.contains("Invalid JSON body"));
+    }
+
+    #[test]
+    // Verify that invalid UTF-8 in a header is rejected with a 400 instead of being replaced
+    fn test_invalid_utf8_header() {
+        let response = send_request(echo_server(), b"GET /get HTTP/1.1\r\nX-Name: caf\xe9\r\n\r\n");
+
+        assert!(response.starts_with("HTTP/1.1 400 Bad Request"));
+        assert!(response.contains("Invalid UTF-8 in request headers"));
+    }
+
+    #[test]
+    // Verify that a request line with non-ASCII characters is rejected
+    fn test_non_ascii_request_line() {
+        let response = send_request(echo_server(), "GET /caf\u{e9} HTTP/1.1\r\n\r\n".as_bytes());
+
+        assert!(response.starts_with("HTTP/1.1 400 Bad Request"));
+    }
+
+    #[test]
+    // Verify that paths deeper than the configured limit are rejected with a 400
+    fn test_max_path_depth() {
+        let server = Arc::new(Mutex::new(Server::new()));
+        server.lock().unwrap().max_path_depth = Some(4);
+
+        let deep = send_request(Arc::clone(&server), b"GET /a/b/c/d/e HTTP/1.1\r\n\r\n");
+        let shallow = send_request(server, b"GET /a/b/c/d HTTP/1.1\r\n\r\n");
+
+        assert!(deep.starts_with("HTTP/1.1 400 Bad Request"));
+        assert!(deep.contains("more than 4 segments"));
+        assert!(shallow.starts_with("HTTP/1.1 404 Not Found"));
+    }
+
+    #[test]
+    // Verify that a middleware can answer a request before it reaches the router
+    fn test_middleware_short_circuits() {
+        let mut server = Server::new();
+        server.use_middleware(|request| {
+            request.header("X-Api-Key").is_none().then(HttpResponse::unauthorized)
+        });
+        server.route("GET", "/data", |_| HttpResponse::ok("data"));
+        let server = Arc::new(Mutex::new(server));
+
+        let rejected = send_request(Arc::clone(&server), b"GET /data HTTP/1.1\r\n\r\n");
+        let allowed = send_request(server, b"GET /data HTTP/1.1\r\nX-Api-Key: k\r\n\r\n");
+
+        assert!(rejected.starts_with("HTTP/1.1 401 Unauthorized"));
+        assert!(allowed.ends_with("data"));
+    }
+
+    #[test]
+    // Verify that a value a middleware stores on the request reaches the handler
+    fn test_middleware_sets_request_extension() {
+        let mut server = Server::new();
+        server.use_middleware(|request| {
+            match request.header("Authorization").and_then(|value| value.strip_prefix("Token ")) {
+                Some(token) => {
+                    let user_id = format!("user-{}", token);
+                    request.extensions.insert("user_id".to_string(), user_id);
+                    None
+                }
+                None => Some(HttpResponse::unauthorized()),
+            }
+        });
+        server.route("GET", "/me", |request| HttpResponse::ok(&request.extensions["user_id"]));
+
+        let response = send_request(Arc::new(Mutex::new(server)), b"GET /me HTTP/1.1\r\nAuthorization: Token 42\r\n\r\n");
+
+        assert!(response.starts_with("HTTP/1.1 200 OK"));
+        assert!(response.ends_with("user-42"));
+    }
+
+    // Typed value an auth middleware hands to handlers
+    struct CurrentUser {
+        id: u64,
+        admin: bool,
+    }
+
+    #[test]
+    // Verify that a struct a middleware inserts is retrieved by type in the handler
+    fn test_middleware_sets_typed_extension() {
+        let mut server = Server::new();
+        server.use_middleware(|request| {
+            request.insert_ext(CurrentUser { id: 7, admin: true });
+            None
+        });
+        server.route("GET", "/me", |request| {
+            let user = request.get_ext::<CurrentUser>().unwrap();
+            HttpResponse::ok(&format!("{} {}", user.id, user.admin))
+        });
+
+        let response = send_request(Arc::new(Mutex::new(server)), b"GET /me HTTP/1.1\r\n\r\n");
+
+        assert!(response.ends_with("7 true"));
+    }
+
+    #[test]
+    // Verify that a context handler reads a path parameter and a query value
+    fn test_context_params_and_query() {
+        let mut server = Server::new();
+        server.route_ctx("GET", "/users/:id", |ctx| {
+            HttpResponse::ok(&format!("user {} fields {}", ctx.param("id").unwrap(), ctx.query("fields").unwrap_or("all")))
+        });
+
+        let response = send_request(Arc::new(Mutex::new(server)), b"GET /users/42?fields=name%2Cemail HTTP/1.1\r\n\r\n");
+
+        assert!(response.starts_with("HTTP/1.1 200 OK"));
+        assert!(response.ends_with("user 42 fields name,email"));
+    }
+
+    #[test]
+    // Verify that state given to the server is shared by handlers across requests
+    fn test_shared_state_counter() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let mut server = Server::new();
+        server.set_state(AtomicUsize::new(0));
+        server.route_ctx("POST", "/hits", |ctx| {
+            let hits = ctx.state::<AtomicUsize>().unwrap().fetch_add(1, Ordering::SeqCst) + 1;
+            HttpResponse::ok(&hits.to_string())
+        });
+        let server = Arc::new(Mutex::new(server));
+
+        send_request(Arc::clone(&server), b"POST /hits HTTP/1.1\r\nContent-Length: 0\r\n\r\n");
+        let second = send_request(Arc::clone(&server), b"POST /hits HTTP/1.1\r\nContent-Length: 0\r\n\r\n");
+
+        assert!(second.ends_with("2"));
+        let state = server.lock().unwrap().state.clone().unwrap();
+        assert_eq!(state.downcast_ref::<AtomicUsize>().unwrap().load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    // Verify that a handler returning an HttpError gets the matching error response
+    fn test_handler_error_result() {
+        let mut server = Server::new();
+        server.route_ctx("GET", "/users/:id", |ctx| {
+            let id: u32 = ctx.param("id").unwrap().parse().map_err(|_| HttpError::bad_request("bad"))?;
+            Ok::<_, HttpError>(HttpResponse::ok(&id.to_string()))
+        });
+        let server = Arc::new(Mutex::new(server));
+
+        let failed = send_request(Arc::clone(&server), b"GET /users/abc HTTP/1.1\r\n\r\n");
+        let found = send_request(server, b"GET /users/7 HTTP/1.1\r\n\r\n");
+
+        assert!(failed.starts_with("HTTP/1.1 400 Bad Request"));
+        assert!(failed.contains("\"message\":\"bad\""));
+        assert!(found.ends_with("7"));
+    }
+
+    #[test]
+    // Verify that pipelined requests are answered in order even when earlier handlers are slower
+    fn test_pipelined_responses_keep_request_order() {
+        let mut server = Server::new();
+        server.enable_keep_alive();
+        for (path, delay) in [("/slow", 150), ("/medium", 50), ("/fast", 0)] {
+            server.route("GET", path, move |_| {
+                std::thread::sleep(Duration::from_millis(delay));
+                HttpResponse::ok(path)
+            });
+        }
+        let server = Arc::new(Mutex::new(server));
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let handle = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            stream.write_all(b"GET /slow HTTP/1.1\r\n\r\nGET /medium HTTP/1.1\r\n\r\nGET /fast HTTP/1.1\r\nConnection: close\r\n\r\n").unwrap();
+            let mut received = String::new();
+            stream.read_to_string(&mut received).unwrap();
+            received
+        });
+
+        Client::new(TcpStream::connect(addr).unwrap()).handle(server, None);
+        let received = handle.join().unwrap();
+
+        let positions: Vec<usize> = ["/slow", "/medium", "/fast"]
+            .iter()
+            .map(|body| received.find(&format!("\r\n\r\n{}", body)).expect("Missing response"))
+            .collect();
+        assert_eq!(received.matches("HTTP/1.1 200 OK").count(), 3);
+        assert!(positions[0] < positions[1] && positions[1] < positions[2]);
+        assert_eq!(received.matches("Connection: keep-alive").count(), 2);
+        assert!(received.contains("Connection: close"));
+    }
+
+    #[test]
+    // Verify that creating a resource with POST answers 201 with a Location pointing at it
+    fn test_post_create_returns_location() {
+        let body = "{\"name\":\"widget\"}";
+        let request = format!(
+            "POST /test_created_location HTTP/1.1\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{}",
+            body.len(),
+            body
+        );
+
+        let response = send_request(Arc::new(Mutex::new(Server::new())), request.as_bytes());
+        let _ = std::fs::remove_file("./files/test_created_location.json");
+
+        assert!(response.starts_with("HTTP/1.1 201 Created"));
+        assert!(response.contains("Location: /test_created_location\r\n"));
+        assert!(response.contains("Content-Type: application/json\r\n"));
+    }
+
+    #[test]
+    // Verify that keep-alive limits are advertised and the connection closes after the last allowed request
+    fn test_keep_alive_header_and_request_limit() {
+        let mut server = Server::new();
+        server.set_keep_alive_options(KeepAliveOptions { max_requests: 2, advertise: true, ..Default::default() });
+        server.route("GET", "/ping", |_| HttpResponse::ok("pong"));
+        let server = Arc::new(Mutex::new(server));
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let handle = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            stream.write_all(&b"GET /ping HTTP/1.1\r\n\r\n".repeat(3)).unwrap();
+            // The unanswered third request may make the close a reset, keep what arrived
+            let mut received = Vec::new();
+            let _ = stream.read_to_end(&mut received);
+            String::from_utf8_lossy(&received).into_owned()
+        });
+
+        Client::new(TcpStream::connect(addr).unwrap()).handle(server, None);
+        let received = handle.join().unwrap();
+
+        assert_eq!(received.matches("HTTP/1.1 200 OK").count(), 2);
+        assert_eq!(received.matches("Keep-Alive: timeout=5, max=1\r\n").count(), 1);
+        assert!(received.contains("Connection: close\r\n"));
+    }
+
+    fn data_server() -> Arc<Mutex<Server>> {
+        let mut server = Server::new();
+        server.route_negotiated(
+            "GET",
+            "/data",
+            Representations::new()
+                .on("application/json", |_| HttpResponse::json_array([1, 2]))
+                .on("text/csv", |_| HttpResponse::new(200, HashMap::new(), Some("n\n1\n2\n".to_string()))),
+        );
+        Arc::new(Mutex::new(server))
+    }
+
+    #[test]
+    // Verify that a negotiated route serves JSON to clients preferring it
+    fn test_negotiated_route_selects_json() {
+        let response = send_request(data_server(), b"GET /data HTTP/1.1\r\nAccept: text/csv;q=0.5, application/json\r\n\r\n");
+
+        assert!(response.starts_with("HTTP/1.1 200 OK"));
+        assert!(response.contains("Content-Type: application/json\r\n"));
+        assert!(response.contains("Vary: Accept\r\n"));
+        assert!(response.ends_with("[1,2]"));
+    }
+
+    #[test]
+    // Verify that a negotiated route serves CSV when the client asks for it
+    fn test_negotiated_route_selects_csv() {
+        let response = send_request(data_server(), b"GET /data HTTP/1.1\r\nAccept: text/csv\r\n\r\n");
+
+        assert!(response.starts_with("HTTP/1.1 200 OK"));
+        assert!(response.contains("Content-Type: text/csv\r\n"));
+        assert!(response.ends_with("n\n1\n2\n"));
+    }
+
+    #[test]
+    // Verify that a negotiated route answers 406 when no representation is acceptable
+    fn test_negotiated_route_not_acceptable() {
+        let response = send_request(data_server(), b"GET /data HTTP/1.1\r\nAccept: application/xml\r\n\r\n");
+
+        assert!(response.starts_with("HTTP/1.1 406 Not Acceptable"));
+        assert!(response.contains("application/json, text/csv"));
+    }
+
+    fn deadline_header(offset_millis: i64) -> String {
+        let now = SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_millis() as i64;
+        format!("X-Request-Deadline: {}", now + offset_millis)
+    }
+
+    #[test]
+    // Verify that a request whose deadline already passed gets a 504 without running the handler
+    fn test_expired_deadline_skips_handler() {
+        use std::sync::atomic::{AtomicBool, Ordering};
+        let ran = Arc::new(AtomicBool::new(false));
+        let mut server = Server::new();
+        let handler_ran = Arc::clone(&ran);
+        server.route("GET", "/work", move |_| {
+            handler_ran.store(true, Ordering::SeqCst);
+            HttpResponse::ok("done")
+        });
+        let request = format!("GET /work HTTP/1.1\r\n{}\r\n\r\n", deadline_header(-1000));
+
+        let response = send_request(Arc::new(Mutex::new(server)), request.as_bytes());
+
+        assert!(response.starts_with("HTTP/1.1 504 Gateway Timeout"));
+        assert!(!ran.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    // Verify that a handler sees the time left before a future deadline
+    fn test_future_deadline_reaches_handler() {
+        let mut server = Server::new();
+        server.route_ctx("GET", "/work", |ctx| {
+            let remaining = ctx.remaining().unwrap();
+            HttpResponse::ok(&(remaining > Duration::from_secs(50) && remaining <= Duration::from_secs(60)).to_string())
+        });
+        let request = format!("GET /work HTTP/1.1\r\n{}\r\n\r\n", deadline_header(60_000));
+
+        let response = send_request(Arc::new(Mutex::new(server)), request.as_bytes());
+
+        assert!(response.starts_with("HTTP/1.1 200 OK"));
+        assert!(response.ends_with("true"));
+    }
+
+    #[test]
+    // Verify that with sessions disabled responses carry no session cookie and no session is stored
+    fn test_disabled_sessions_send_no_cookie() {
+        let store = Arc::new(MemorySessionStore::new());
+        let mut server = Server::new();
+        server.set_session_store(store.clone());
+        server.disable_sessions();
+        server.route("GET", "/api", |_| HttpResponse::ok("data"));
+
+        let response = send_request(Arc::new(Mutex::new(server)), b"GET /api HTTP/1.1\r\n\r\n");
+
+        assert!(response.starts_with("HTTP/1.1 200 OK"));
+        assert!(!response.contains("Set-Cookie"));
+        assert!(store.is_empty());
+    }
+
+    #[test]
+    // Verify that a query string over the parameter limit is rejected with a 400
+    fn test_query_param_limit() {
+        let mut server = Server::new();
+        server.set_max_query_params(10);
+        server.route_ctx("GET", "/search", |ctx| HttpResponse::ok(&ctx.query.len().to_string()));
+        let server = Arc::new(Mutex::new(server));
+        let query = |count: usize| (0..count).map(|i| format!("a{}=1", i)).collect::<Vec<_>>().join("&");
+
+        let allowed = send_request(Arc::clone(&server), format!("GET /search?{} HTTP/1.1\r\n\r\n", query(10)).as_bytes());
+        let rejected = send_request(server, format!("GET /search?{} HTTP/1.1\r\n\r\n", query(11)).as_bytes());
+
+        assert!(allowed.starts_with("HTTP/1.1 200 OK"));
+        assert!(allowed.ends_with("10"));
+        assert!(rejected.starts_with("HTTP/1.1 400 Bad Request"));
+        assert!(rejected.contains("More than 10 query parameters"));
+    }
+
+    #[test]
+    // Verify that a panic while holding the server lock doesn't break later requests
+    fn test_poisoned_server_lock_recovers() {
+        let mut server = Server::new();
+        server.route("GET", "/ping", |_| HttpResponse::ok("pong"));
+        let server = Arc::new(Mutex::new(server));
+
+        let poisoner = Arc::clone(&server);
+        let _ = std::thread::spawn(move || {
+            let _guard = poisoner.lock().unwrap();
+            panic!("Handler bug while holding the server");
+        })
+        .join();
+        assert!(server.is_poisoned());
+
+        let first = send_request(Arc::clone(&server), b"GET /ping HTTP/1.1\r\n\r\n");
+        let second = send_request(Arc::clone(&server), b"GET /ping HTTP/1.1\r\n\r\n");
+
+        assert!(first.starts_with("HTTP/1.1 200 OK") && first.ends_with("pong"));
+        assert!(second.starts_with("HTTP/1.1 200 OK"));
+        assert!(!server.is_poisoned());
+    }
+
+    #[test]
+    // Verify that responses carry the default Server header, a custom one, or none when hidden
+    fn test_server_banner() {
+        let ping = |configure: fn(&mut Server)| {
+            let mut server = Server::new();
+            configure(&mut server);
+            server.route("GET", "/ping", |_| HttpResponse::ok("pong"));
+            send_request(Arc::new(Mutex::new(server)), b"GET /ping HTTP/1.1\r\n\r\n")
+        };
+
+        let default = ping(|_| {});
+        let custom = ping(|server| server.set_banner("edge"));
+        let hidden = ping(|server| server.hide_banner());
+
+        assert!(default.contains("Server: rust-http\r\n"));
+        assert!(custom.contains("Server: edge\r\n"));
+        assert!(!hidden.contains("Server:"));
+        assert!(hidden.starts_with("HTTP/1.1 200 OK"));
+    }
+
+    #[test]
+    // Verify that a cached route runs its handler once within the TTL unless the client asks for no-cache
+    fn test_cached_route_skips_handler() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        let calls = Arc::new(AtomicUsize::new(0));
+        let handler_calls = Arc::clone(&calls);
+        let mut server = Server::new();
+        server.route_cached("GET", "/report", Duration::from_secs(60), move |_| {
+            let call = handler_calls.fetch_add(1, Ordering::SeqCst) + 1;
+            HttpResponse::ok(&format!("report {}", call))
+        });
+        let server = Arc::new(Mutex::new(server));
+
+        let first = send_request(Arc::clone(&server), b"GET /report HTTP/1.1\r\n\r\n");
+        let second = send_request(Arc::clone(&server), b"GET /report HTTP/1.1\r\n\r\n");
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+        let refreshed = send_request(server, b"GET /report HTTP/1.1\r\nCache-Control: no-cache\r\n\r\n");
+
+        assert!(first.ends_with("report 1"));
+        assert!(second.ends_with("report 1"));
+        assert!(refreshed.ends_with("report 2"));
+    }
+
+    #[test]
+    // Verify that a no-store request neither gets the cached response nor leaves its own in the cache
+    fn test_cached_route_no_store_bypasses_cache() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        let calls = Arc::new(AtomicUsize::new(0));
+        let handler_calls = Arc::clone(&calls);
+        let mut server = Server::new();
+        server.route_cached("GET", "/report", Duration::from_secs(60), move |_| {
+            let call = handler_calls.fetch_add(1, Ordering::SeqCst) + 1;
+            HttpResponse::ok(&format!("report {}", call))
+        });
+        let server = Arc::new(Mutex::new(server));
+        let no_store = b"GET /report HTTP/1.1\r\nCache-Control: no-store\r\n\r\n";
+
+        let unstored = send_request(Arc::clone(&server), no_store);
+        let first = send_request(Arc::clone(&server), b"GET /report HTTP/1.1\r\n\r\n");
+        let uncached = send_request(Arc::clone(&server), no_store);
+        let second = send_request(server, b"GET /report HTTP/1.1\r\n\r\n");
+
+        assert!(unstored.ends_with("report 1"));
+        assert!(first.ends_with("report 2"));
+        assert!(uncached.ends_with("report 3"));
+        assert!(second.ends_with("report 2"));
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+    }
+
+    #[test]
+    // Verify that a streaming handler can read a chunked body a few bytes at a time
+    fn test_streaming_handler_reads_body_in_chunks() {
+        let mut server = Server::new();
+        server.route_streaming("POST", "/upload", |ctx, body| {
+            assert!(ctx.request.body.is_empty());
+            let mut received = Vec::new();
+            let mut chunk = [0; 3];
+            let mut reads = 0;
+            loop {
+                match body.read(&mut chunk).unwrap() {
+                    0 => break,
+                    read => received.extend_from_slice(&chunk[..read]),
+                }
+                reads += 1;
+            }
+            HttpResponse::ok(&format!("{} bytes in {} reads: {}", received.len(), reads, String::from_utf8_lossy(&received)))
+        });
+        let server = Arc::new(Mutex::new(server));
+
+        let response = send_request(
+            server,
+            b"POST /upload HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n8\r\nstreamed\r\n7\r\n upload\r\n0\r\n\r\n",
+        );
+
+        assert!(response.starts_with("HTTP/1.1 200 OK"));
+        assert!(response.ends_with("15 bytes in 6 reads: streamed upload"), "{}", response);
+    }
+
+    #[test]
+    // Verify that trailers are sent after a chunked body only to clients sending TE: trailers
+    fn test_trailers_sent_when_accepted() {
+        let mut server = Server::new();
+        server.route("GET", "/report", |_| HttpResponse::ok("report").with_trailer("X-Checksum", "sha256=abc"));
+        let server = Arc::new(Mutex::new(server));
+
+        let accepted = send_request(Arc::clone(&server), b"GET /report HTTP/1.1\r\nTE: trailers\r\n\r\n");
+        let plain = send_request(server, b"GET /report HTTP/1.1\r\n\r\n");
+
+        assert!(accepted.contains("Transfer-Encoding: chunked\r\n"));
+        assert!(accepted.contains("Trailer: X-Checksum\r\n"));
+        assert!(accepted.ends_with("6\r\nreport\r\n0\r\nX-Checksum: sha256=abc\r\n\r\n"));
+        assert!(plain.contains("Content-Length: 6\r\n"));
+        assert!(!plain.contains("X-Checksum"));
+        assert!(plain.ends_with("report"));
+    }
+
+    #[test]
+    // Verify that events reach the client framed and one by one while the handler still produces them
+    fn test_server_sent_events() {
+        use crate::sse::{self, Event};
+        let mut server = Server::new();
+        server.route("GET", "/events", |_| {
+            let (sender, response) = sse::channel();
+            std::thread::spawn(move || {
+                sender.send(Event::new("first").event("tick").id("1")).unwrap();
+                std::thread::sleep(Duration::from_millis(50));
+                sender.send(Event::new("second\nline")).unwrap();
+            });
+            response
+        });
+        let server = Arc::new(Mutex::new(server));
+
+        let response = send_request(server, b"GET /events HTTP/1.1\r\n\r\n");
+
+        let (head, events) = response.split_once("\r\n\r\n").unwrap();
+        assert!(head.contains("Content-Type: text/event-stream"));
+        assert!(head.contains("Cache-Control: no-cache"));
+        assert!(!head.contains("Content-Length"));
+        assert_eq!(events, "event: tick\nid: 1\ndata: first\n\ndata: second\ndata: line\n\n");
+    }
+
+    #[test]
+    // Verify that bodies without a Content-Type get the sniffed one only when sniffing is enabled
+    fn test_body_sniffing() {
+        let build = |enabled: bool| {
+            let mut server = Server::new();
+            if enabled {
+                server.enable_body_sniffing();
+            }
+            server.route("POST", "/submit", |request| {
+                HttpResponse::ok(request.header("Content-Type").unwrap_or("none"))
+            });
+            Arc::new(Mutex::new(server))
+        };
+        let post = |server, body: &str| {
+            let request = format!("POST /submit HTTP/1.1\r\nContent-Length: {}\r\n\r\n{}", body.len(), body);
+            send_request(server, request.as_bytes())
+        };
+
+        assert!(post(build(true), "{\"a\": 1}").ends_with("application/json"));
+        assert!(post(build(true), "a=1&b=2").ends_with("application/x-www-form-urlencoded"));
+        assert!(post(build(true), "just text").ends_with("none"));
+        assert!(post(build(false), "a=1&b=2").ends_with("none"));
+    }
+
+    #[test]
+    // Verify that a client expecting 100 Continue gets the final error instead when the body would be rejected
+    fn test_expect_continue_rejected_early() {
+        let mut server = Server::new();
+        server.set_max_body_size(1024);
+        server.protect_basic("/admin", "admin", "admin", "secret");
+        server.route("POST", "/upload", |request| HttpResponse::ok(&format!("{} bytes", request.body.len())));
+        let server = Arc::new(Mutex::new(server));
+
+        let too_large = send_request(
+            Arc::clone(&server),
+            b"POST /upload HTTP/1.1\r\nExpect: 100-continue\r\nContent-Length: 4096\r\n\r\n",
+        );
+        let unauthorized = send_request(
+            Arc::clone(&server),
+            b"POST /admin/upload HTTP/1.1\r\nExpect: 100-continue\r\nContent-Length: 5\r\n\r\n",
+        );
+        let accepted = send_request(
+            server,
+            b"POST /upload HTTP/1.1\r\nExpect: 100-continue\r\nContent-Length: 5\r\n\r\nhello",
+        );
+
+        assert!(too_large.starts_with("HTTP/1.1 413 Payload Too Large"), "{}", too_large);
+        assert!(unauthorized.starts_with("HTTP/1.1 401 Unauthorized"), "{}", unauthorized);
+        assert!(!unauthorized.contains("100 Continue"));
+        assert!(accepted.starts_with("HTTP/1.1 100 Continue\r\n\r\nHTTP/1.1 200 OK"));
+        assert!(accepted.ends_with("5 bytes"));
+    }
+
+    #[test]
+    // Verify that the HTTP/2 connection preface gets a single 505 instead of parse errors
+    fn test_http2_preface_rejected() {
+        let server = Arc::new(Mutex::new(Server::new()));
+
+        let response = send_request(server, b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n");
+
+        assert!(response.starts_with("HTTP/1.1 505 HTTP Version Not Supported"), "{}", response);
+        assert!(response.contains("HTTP/2.0 is not supported"));
+        assert_eq!(response.matches("HTTP/1.1 ").count(), 1);
+    }
+
+    #[test]
+    // Verify that a language-negotiated response names its language and varies on Accept-Language
+    fn test_content_language_negotiated() {
+        let mut server = Server::new();
+        server.route("GET", "/greeting", |request| {
+            let language = request.preferred_language(&["en", "fr"]).unwrap_or("en");
+            let greeting = if language == "fr" { "bonjour" } else { "hello" };
+            HttpResponse::ok(greeting).with_language(language)
+        });
+        let server = Arc::new(Mutex::new(server));
+
+        let french = send_request(Arc::clone(&server), b"GET /greeting HTTP/1.1\r\nAccept-Language: fr-CA, fr;q=0.9, en;q=0.5\r\n\r\n");
+        let default = send_request(server, b"GET /greeting HTTP/1.1\r\n\r\n");
+
+        assert!(french.contains("Content-Language: fr\r\n"));
+        assert!(french.contains("Vary: Accept-Language\r\n"));
+        assert!(french.ends_with("bonjour"));
+        assert!(default.contains("Content-Language: en\r\n"));
+        assert!(default.ends_with("hello"));
+    }
+
+    #[test]
+    // Verify that every response is counted and the stats endpoint reports the counters
+    fn test_stats_count_requests() {
+        let server = about_server(TrailingSlash::Strict);
+        server.lock().unwrap().enable_stats("/stats");
+
+        send_request(Arc::clone(&server), b"GET /about HTTP/1.1\r\n\r\n");
+        send_request(Arc::clone(&server), b"GET /about HTTP/1.1\r\n\r\n");
+        send_request(Arc::clone(&server), b"GET / HTTP/1.1 extra\r\n\r\n");
+        let stats = server.lock().unwrap().stats();
+        let response = send_request(Arc::clone(&server), b"GET /stats HTTP/1.1\r\n\r\n");
+
+        assert_eq!(stats.requests, 3);
+        assert_eq!(stats.client_errors, 1);
+        assert_eq!(stats.server_errors, 0);
+        assert_eq!(stats.open_connections, 0);
+        let body: serde_json::Value = serde_json::from_str(response.split("\r\n\r\n").nth(1).unwrap()).unwrap();
+        assert_eq!(body["requests"], 3);
+        assert_eq!(body["client_errors"], 1);
+        assert_eq!(server.lock().unwrap().stats().requests, 4);
+    }
+
+    #[test]
+    // Verify that a lowercase method is rejected by default and uppercased by lenient parsing
+    fn test_lowercase_method() {
+        let build = |hardened: bool| {
+            let mut server = Server::new();
+            if !hardened {
+                server.disable_hardened_parsing();
+            }
+            server.route("GET", "/about", |_| HttpResponse::ok("about page"));
+            Arc::new(Mutex::new(server))
+        };
+
+        let strict = send_request(build(true), b"get /about HTTP/1.1\r\n\r\n");
+        let lenient = send_request(build(false), b"get /about HTTP/1.1\r\n\r\n");
+
+        assert!(strict.starts_with("HTTP/1.1 400 Bad Request"), "{}", strict);
+        assert!(strict.contains("method must be uppercase"));
+        assert!(lenient.starts_with("HTTP/1.1 200 OK"));
+        assert!(lenient.ends_with("about page"));
+    }
+
+    #[test]
+    // Verify that a validated route answers 422 with the field errors and runs the handler for valid bodies
+    fn test_validated_route() {
+        use crate::validate::require_string;
+        let mut server = Server::new();
+        server.route_validated(
+            "POST",
+            "/users",
+            |body| require_string(body, "name").into_iter().collect(),
+            |ctx| HttpResponse::created("/users/1", ctx.request.body_text().unwrap_or_default()),
+        );
+        let server = Arc::new(Mutex::new(server));
+
+        let missing = send_request(
+            Arc::clone(&server),
+            b"POST /users HTTP/1.1\r\nContent-Type: application/json\r\nContent-Length: 11\r\n\r\n{\"age\": 30}",
+        );
+        let valid = send_request(
+            server,
+            b"POST /users HTTP/1.1\r\nContent-Type: application/json\r\nContent-Length: 12\r\n\r\n{\"name\":\"a\"}",
+        );
+
+        assert!(missing.starts_with("HTTP/1.1 422 Unprocessable Entity"), "{}", missing);
+        let errors: serde_json::Value = serde_json::from_str(missing.split("\r\n\r\n").nth(1).unwrap()).unwrap();
+        assert_eq!(errors, serde_json::json!([{"field": "name", "message": "is required"}]));
+        assert!(valid.starts_with("HTTP/1.1 201 Created"));
+    }
+
+    #[test]
+    // Verify that cookie sessions survive between requests without anything in the session store
+    fn test_cookie_sessions_keep_nothing_on_the_server() {
+        let store = Arc::new(MemorySessionStore::new());
+        let mut server = Server::new();
+        server.set_session_store(store.clone());
+        server.enable_cookie_sessions(b"top secret");
+        let server = Arc::new(Mutex::new(server));
+        let sessions = crate::session::CookieSessions::new(b"top secret");
+        let session_cookie = |response: &str| {
+            response.lines().find_map(|line| line.strip_prefix("Set-Cookie: sessionId=")).unwrap().split(';').next().unwrap().to_string()
+        };
+
+        let first = send_request(Arc::clone(&server), b"GET /get HTTP/1.1\r\n\r\n");
+        let cookie = session_cookie(&first);
+        let request = format!("GET /get HTTP/1.1\r\nCookie: sessionId={}\r\n\r\n", cookie);
+        let second = send_request(Arc::clone(&server), request.as_bytes());
+        let tampered = send_request(server, format!("GET /get HTTP/1.1\r\nCookie: sessionId=x{}\r\n\r\n", cookie).as_bytes());
+
+        let session = sessions.open(&cookie).unwrap();
+        assert_eq!(sessions.open(&session_cookie(&second)).unwrap(), session);
+        assert_ne!(sessions.open(&session_cookie(&tampered)).unwrap().id, session.id);
+        assert!(store.is_empty());
+    }
+
+    #[test]
+    // Verify that requests after a reload hit the new routes while one in flight finishes on the old one
+    fn test_reload_swaps_routes() {
+        let (release, released) = std::sync::mpsc::channel::<()>();
+        let (start, started) = std::sync::mpsc::channel::<()>();
+        let (released, start) = (Mutex::new(released), Mutex::new(start));
+        let mut server = Server::new();
+        server.route("GET", "/version", |_| HttpResponse::ok("v1"));
+        server.route("GET", "/slow", move |_| {
+            let _ = start.lock().unwrap().send(());
+            let _ = released.lock().unwrap().recv();
+            HttpResponse::ok("old slow")
+        });
+        let server = Arc::new(Mutex::new(server));
+
+        let in_flight = {
+            let server = Arc::clone(&server);
+            std::thread::spawn(move || send_request(server, b"GET /slow HTTP/1.1\r\n\r\n"))
+        };
+        let before = send_request(Arc::clone(&server), b"GET /version HTTP/1.1\r\n\r\n");
+        started.recv_timeout(Duration::from_secs(2)).unwrap();
+
+        let mut router = crate::router::Router::new();
+        router.add("GET", "/version", |_| HttpResponse::ok("v2"));
+        Server::reload(&server, router);
+        release.send(()).unwrap();
+        let after = send_request(Arc::clone(&server), b"GET /version HTTP/1.1\r\n\r\n");
+
+        assert!(before.ends_with("\r\n\r\nv1"));
+        assert!(after.ends_with("\r\n\r\nv2"));
+        assert!(in_flight.join().unwrap().ends_with("\r\n\r\nold slow"));
+        assert_eq!(server.lock().unwrap().routes().len(), 1);
+    }
+
+    #[test]
+    // Verify that a reload keeps the trailing slash policy and can carry in-memory files along
+    fn test_reload_keeps_trailing_slash_policy() {
+        let server = about_server(TrailingSlash::Redirect);
+
+        let mut router = crate::router::Router::new();
+        router.add("GET", "/about", |_| HttpResponse::ok("new about"));
+        router.add_bytes("/logo.txt", "text/plain", b"logo".to_vec());
+        Server::reload(&server, router);
+        let redirected = send_request(Arc::clone(&server), b"GET /about/ HTTP/1.1\r\n\r\n");
+        let logo = send_request(server, b"GET /logo.txt HTTP/1.1\r\n\r\n");
+
+        assert!(redirected.starts_with("HTTP/1.1 301 Moved Permanently"));
+        assert!(redirected.contains("Location: /about\r\n"));
+        assert!(logo.ends_with("\r\n\r\nlogo"));
+    }
+
+    #[test]
+    // Verify that a POST handler answering see_other sends 303 with the Location to GET
+    fn test_post_redirects_with_see_other() {
+        let mut server = Server::new();
+        server.route("POST", "/orders", |_| HttpResponse::see_other("/orders/7"));
+
+        let response = send_request(
+            Arc::new(Mutex::new(server)),
+            b"POST /orders HTTP/1.1\r\nContent-Type: text/plain\r\nContent-Length: 4\r\n\r\nitem",
+        );
+
+        assert!(response.starts_with("HTTP/1.1 303 See Other"), "{}", response);
+        assert!(response.contains("Location: /orders/7\r\n"));
+    }
+
+    #[test]
+    // Verify that a metered connection counts exactly the request and response bytes
+    fn test_connection_byte_counters() {
+        use crate::stats::{Metered, Metrics};
+        let request = b"GET /about HTTP/1.1\r\nHost: localhost\r\n\r\n";
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let peer = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            stream.write_all(request).unwrap();
+            let mut response = Vec::new();
+            stream.read_to_end(&mut response).unwrap();
+            response.len() as u64
+        });
+
+        let metrics = Arc::new(Metrics::new());
+        let mut client = Client::new(Metered::new(TcpStream::connect(addr).unwrap(), Arc::clone(&metrics)));
+        client.handle(about_server(TrailingSlash::Strict), None);
+        let counted = (client.stream.bytes_read(), client.stream.bytes_written());
+        drop(client);
+        let response_len = peer.join().unwrap();
+
+        assert_eq!(counted, (request.len() as u64, response_len));
+        let stats = metrics.snapshot();
+        assert_eq!((stats.bytes_received, stats.bytes_sent), counted);
+    }
+
+    #[test]
+    // Verify that OPTIONS * answers 204 listing every method the server handles
+    fn test_server_wide_options() {
+        let mut server = Server::new();
+        server.route("PURGE", "/cache", |_| HttpResponse::no_content());
+
+        let response = send_request(Arc::new(Mutex::new(server)), b"OPTIONS * HTTP/1.1\r\nHost: localhost\r\n\r\n");
+
+        assert!(response.starts_with("HTTP/1.1 204 No Content"), "{}", response);
+        assert!(response.contains("Allow: GET, POST, PUT, PATCH, DELETE, OPTIONS, PURGE\r\n"));
+    }
+
+    #[test]
+    // Verify that OPTIONS on a path answers 204 with the methods of that path only
+    fn test_path_options() {
+        let mut server = Server::new();
+        server.route("PURGE", "/cache", |_| HttpResponse::no_content());
+        server.route("OPTIONS", "/custom", |_| HttpResponse::ok("custom"));
+        let server = Arc::new(Mutex::new(server));
+
+        let cache = send_request(Arc::clone(&server), b"OPTIONS /cache HTTP/1.1\r\nHost: localhost\r\n\r\n");
+        let other = send_request(Arc::clone(&server), b"OPTIONS /users/1 HTTP/1.1\r\nHost: localhost\r\n\r\n");
+        let custom = send_request(server, b"OPTIONS /custom HTTP/1.1\r\nHost: localhost\r\n\r\n");
+
+        assert!(cache.starts_with("HTTP/1.1 204 No Content"), "{}", cache);
+        assert!(cache.contains("Allow: GET, POST, PUT, PATCH, DELETE, OPTIONS, PURGE\r\n"));
+        assert!(other.starts_with("HTTP/1.1 204 No Content"));
+        assert!(other.contains("Allow: GET, POST, PUT, PATCH, DELETE, OPTIONS\r\n"));
+        assert!(custom.ends_with("\r\n\r\ncustom"));
+    }
+
+    #[test]
+    // Verify that a panicking handler gets a generic 500 unless error details are exposed
+    fn test_panic_detail_exposure() {
+        let failing = |expose: bool, timeout: Option<Duration>| {
+            let mut server = Server::new();
+            if expose {
+                server.enable_error_details();
+            }
+            server.handler_timeout = timeout;
+            server.route("GET", "/boom", |_| -> HttpResponse { panic!("connection to db-internal:5432 refused") });
+            send_request(Arc::new(Mutex::new(server)), b"GET /boom HTTP/1.1\r\n\r\n")
+        };
+
+        let production = failing(false, None);
+        let development = failing(true, None);
+        let development_timed = failing(true, Some(Duration::from_secs(2)));
+
+        assert!(production.starts_with("HTTP/1.1 500 Internal Server Error"));
+        assert!(development.starts_with("HTTP/1.1 500 Internal Server Error"));
+        assert!(!production.contains("db-internal"));
+        assert!(development.contains("connection to db-internal:5432 refused"));
+        assert!(development_timed.contains("connection to db-internal:5432 refused"));
+    }
+
+    #[test]
+    // Verify that a PUT with an If-Unmodified-Since before the file's last change gets a 412
+    fn test_if_unmodified_since_on_put() {
+        let server = Arc::new(Mutex::new(Server::new()));
+        crate::methods::handle_post("test_unmodified", Some(&serde_json::json!({"v": 1})));
+        let put = |since: &str| {
+            let request = format!(
+                "PUT /test_unmodified HTTP/1.1\r\nIf-Unmodified-Since: {}\r\nContent-Type: application/json\r\nContent-Length: 7\r\n\r\n{{\"v\":2}}",
+                since
+            );
+            send_request(Arc::clone(&server), request.as_bytes())
+        };
+        let tomorrow = crate::date::DateTime::from_system_time(SystemTime::now() + Duration::from_secs(86_400));
+
+        let stale = put("Sun, 06 Nov 1994 08:49:37 GMT");
+        let current = put(&tomorrow.to_http_date());
+        let stored = std::fs::read_to_string("files/test_unmodified.json").unwrap();
+        std::fs::remove_file("files/test_unmodified.json").unwrap();
+
+        assert!(stale.starts_with("HTTP/1.1 412 Precondition Failed"), "{}", stale);
+        assert!(current.starts_with("HTTP/1.1 200 OK"), "{}", current);
+        assert!(stored.contains("2"));
+    }
 }
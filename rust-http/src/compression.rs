@@ -0,0 +1,297 @@
+use std::io::Write;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use crate::request::HttpRequest;
+use crate::response::HttpResponse;
+
+// Gzip response bodies for clients that accept it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GzipOptions {
+    // Bodies shorter than this are sent as they are
+    pub min_size: usize,
+}
+
+impl Default for GzipOptions {
+    fn default() -> Self {
+        Self { min_size: 1024 }
+    }
+}
+
+// Content codings a response body can be sent in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+    Gzip,
+    Brotli,
+    Identity,
+}
+
+impl Encoding {
+    pub fn name(self) -> &'static str {
+        match self {
+            Encoding::Gzip => "gzip",
+            Encoding::Brotli => "br",
+            Encoding::Identity => "identity",
+        }
+    }
+}
+
+// Codings responses get compressed with, most preferred first
+#[cfg(feature = "brotli")]
+const COMPRESSIONS: &[Encoding] = &[Encoding::Brotli, Encoding::Gzip, Encoding::Identity];
+#[cfg(not(feature = "brotli"))]
+const COMPRESSIONS: &[Encoding] = &[Encoding::Gzip, Encoding::Identity];
+
+// Weight an Accept-Encoding value gives `encoding`, from its own entry or else from `*`.
+// Identity is acceptable when neither names it, but below anything listed.
+fn encoding_weight(header: &str, encoding: Encoding) -> f32 {
+    let mut wildcard = None;
+    for entry in header.split(',') {
+        let mut parts = entry.split(';');
+        let name = parts.next().unwrap_or_default().trim();
+        let weight = parts
+            .find_map(|param| match param.trim().split_once('=') {
+                Some((q, value)) if q.trim() == "q" => Some(value.trim().parse::<f32>().unwrap_or(0.0)),
+                _ => None,
+            })
+            .unwrap_or(1.0);
+        if name.eq_ignore_ascii_case(encoding.name()) {
+            return weight;
+        }
+        if name == "*" {
+            wildcard = Some(weight);
+        }
+    }
+    match (wildcard, encoding) {
+        (Some(weight), _) => weight,
+        (None, Encoding::Identity) => f32::MIN_POSITIVE,
+        (None, _) => 0.0,
+    }
+}
+
+// Pick the one of `supported` an Accept-Encoding value weighs highest, earlier ones winning
+// ties. A q=0 weight rules an encoding out; None means none of them is acceptable.
+pub fn negotiate_encoding(header: &str, supported: &[Encoding]) -> Option<Encoding> {
+    let mut best = None;
+    let mut best_weight = 0.0;
+    for &encoding in supported {
+        let weight = encoding_weight(header, encoding);
+        if weight > best_weight {
+            best = Some(encoding);
+            best_weight = weight;
+        }
+    }
+    best
+}
+
+// Check whether the Accept-Encoding header allows gzip
+pub fn accepts_gzip(request: &HttpRequest) -> bool {
+    encoding_weight(&request.header_values("Accept-Encoding").join(","), Encoding::Gzip) > 0.0
+}
+
+// Compression the client weighs highest, Brotli winning a tie with gzip
+pub fn preferred_compression(request: &HttpRequest) -> Option<Encoding> {
+    negotiate_encoding(&request.header_values("Accept-Encoding").join(","), COMPRESSIONS)
+        .filter(|encoding| *encoding != Encoding::Identity)
+}
+
+fn compress(body: &[u8], encoding: Encoding) -> std::io::Result<Vec<u8>> {
+    match encoding {
+        Encoding::Gzip => {
+            let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+            encoder.write_all(body)?;
+            encoder.finish()
+        }
+        // Quality 5 keeps compression close to gzip's speed, the default 11 is meant for static assets
+        #[cfg(feature = "brotli")]
+        Encoding::Brotli => {
+            let mut encoder = brotli::CompressorWriter::new(Vec::new(), 4096, 5, 22);
+            encoder.write_all(body)?;
+            Ok(encoder.into_inner())
+        }
+        // Never negotiated for compression
+        _ => Ok(body.to_vec()),
+    }
+}
+
+// Add a value to the Vary header, keeping the ones already there
+pub fn add_vary(response: &mut HttpResponse, header: &str) {
+    let key = response
+        .headers
+        .keys()
+        .find(|key| key.eq_ignore_ascii_case("Vary"))
+        .cloned()
+        .unwrap_or_else(|| "Vary".to_string());
+    let vary = response.headers.entry(key).or_default();
+    if vary.split(',').any(|value| value.trim().eq_ignore_ascii_case(header) || value.trim() == "*") {
+        return;
+    }
+    if !vary.is_empty() {
+        vary.push_str(", ");
+    }
+    vary.push_str(header);
+}
+
+// Compress the response body when the request allows it, with Brotli when the
+// `brotli` feature is on and the client prefers it. Responses that could be
+// compressed always get `Vary: Accept-Encoding` so caches keep the variants apart.
+pub fn gzip_response(request: &HttpRequest, response: &mut HttpResponse, options: GzipOptions) {
+    let body_len = response.body.as_ref().map_or(0, Vec::len);
+    if body_len == 0
+        || body_len < options.min_size
+        || matches!(response.status_code, 204 | 206 | 304)
+        || has_header(response, "Content-Encoding")
+        || !compressible(response)
+    {
+        return;
+    }
+
+    add_vary(response, "Accept-Encoding");
+    let Some(encoding) = preferred_compression(request) else {
+        return;
+    };
+
+    let compressed = match compress(response.body.as_deref().unwrap_or_default(), encoding) {
+        Ok(compressed) => compressed,
+        Err(_) => return,
+    };
+
+    response.headers.retain(|key, _| !key.eq_ignore_ascii_case("Content-Length"));
+    response.headers.insert("Content-Encoding".to_string(), encoding.name().to_string());
+    response.body = Some(compressed);
+}
+
+fn has_header(response: &HttpResponse, name: &str) -> bool {
+    response.headers.keys().any(|key| key.eq_ignore_ascii_case(name))
+}
+
+// Only text-like types shrink; images, archives and other binary formats are
+// usually compressed already, and a body without a type is left alone
+fn compressible(response: &HttpResponse) -> bool {
+    let content_type = response
+        .headers
+        .iter()
+        .find(|(key, _)| key.eq_ignore_ascii_case("Content-Type"))
+        .map(|(_, value)| value.to_ascii_lowercase())
+        .unwrap_or_default();
+    let media = content_type.split(';').next().unwrap_or_default().trim();
+
+    media.starts_with("text/")
+        || media.ends_with("+json")
+        || media.ends_with("+xml")
+        || matches!(media, "application/json" | "application/javascript" | "application/xml" | "image/svg+xml")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+    use flate2::read::GzDecoder;
+
+    fn request_with_encoding(accept_encoding: &str) -> HttpRequest {
+        HttpRequest {
+            method: "GET".to_string(),
+            path: "/".to_string(),
+            headers: vec![format!("Accept-Encoding: {}", accept_encoding)],
+            body: Vec::new(),
+            cookie: None,
+            ..Default::default()
+        }
+    }
+
+    fn text_response(len: usize) -> HttpResponse {
+        HttpResponse::ok(&"a".repeat(len))
+    }
+
+    #[test]
+    fn test_compressed_response_varies_on_accept_encoding() {
+        let mut response = text_response(2048);
+
+        gzip_response(&request_with_encoding("deflate, gzip"), &mut response, GzipOptions::default());
+
+        assert_eq!(response.headers.get("Content-Encoding").unwrap(), "gzip");
+        assert_eq!(response.headers.get("Vary").unwrap(), "Accept-Encoding");
+        let mut body = String::new();
+        GzDecoder::new(response.body.unwrap().as_slice()).read_to_string(&mut body).unwrap();
+        assert_eq!(body, "a".repeat(2048));
+    }
+
+    #[test]
+    fn test_identity_response_still_varies() {
+        let mut response = text_response(2048);
+        response.headers.insert("Vary".to_string(), "Accept".to_string());
+
+        gzip_response(&request_with_encoding("gzip;q=0"), &mut response, GzipOptions::default());
+
+        assert!(!response.headers.contains_key("Content-Encoding"));
+        assert_eq!(response.headers.get("Vary").unwrap(), "Accept, Accept-Encoding");
+    }
+
+    #[test]
+    fn test_only_text_like_types_are_compressed() {
+        let png = "x".repeat(2048);
+        let mut image = HttpResponse::ok(&png).with_header("Content-Type", "image/png");
+        let mut json = HttpResponse::ok(&format!("{{\"data\":\"{}\"}}", png)).with_header("Content-Type", "application/json");
+
+        gzip_response(&request_with_encoding("gzip"), &mut image, GzipOptions::default());
+        gzip_response(&request_with_encoding("gzip"), &mut json, GzipOptions::default());
+
+        assert!(!image.headers.contains_key("Content-Encoding"));
+        assert_eq!(image.body, Some(png.into_bytes()));
+        assert_eq!(json.headers.get("Content-Encoding").unwrap(), "gzip");
+    }
+
+    #[test]
+    fn test_small_bodies_are_left_alone() {
+        let mut response = text_response(10);
+
+        gzip_response(&request_with_encoding("gzip"), &mut response, GzipOptions::default());
+
+        assert!(!response.headers.contains_key("Vary"));
+        assert_eq!(response.body, Some(b"aaaaaaaaaa".to_vec()));
+    }
+
+    #[test]
+    fn test_weights_pick_the_compression() {
+        assert_eq!(preferred_compression(&request_with_encoding("gzip;q=0.5, *;q=0.1")), Some(Encoding::Gzip));
+        assert_eq!(preferred_compression(&request_with_encoding("gzip;q=0, br;q=0, *")), None);
+        assert_eq!(preferred_compression(&request_with_encoding("identity")), None);
+    }
+
+    #[test]
+    fn test_negotiate_encoding() {
+        let all = [Encoding::Gzip, Encoding::Brotli, Encoding::Identity];
+
+        assert_eq!(negotiate_encoding("gzip;q=0.5, br;q=1.0, *;q=0", &all), Some(Encoding::Brotli));
+        assert_eq!(negotiate_encoding("gzip;q=0.5, br;q=1.0", &[Encoding::Gzip, Encoding::Identity]), Some(Encoding::Gzip));
+        assert_eq!(negotiate_encoding("br, gzip", &all), Some(Encoding::Gzip));
+        assert_eq!(negotiate_encoding("*;q=0.3, gzip;q=0.2", &all), Some(Encoding::Brotli));
+        assert_eq!(negotiate_encoding("deflate", &all), Some(Encoding::Identity));
+        assert_eq!(negotiate_encoding("", &all), Some(Encoding::Identity));
+        assert_eq!(negotiate_encoding("identity;q=1, gzip;q=0.9", &all), Some(Encoding::Identity));
+    }
+
+    #[test]
+    fn test_negotiate_encoding_honors_rejections() {
+        let all = [Encoding::Gzip, Encoding::Brotli, Encoding::Identity];
+
+        assert_eq!(negotiate_encoding("gzip;q=0, br;q=0", &[Encoding::Gzip, Encoding::Brotli]), None);
+        assert_eq!(negotiate_encoding("gzip;q=0, *;q=0", &all), None);
+        assert_eq!(negotiate_encoding("identity;q=0, deflate", &all), None);
+        assert_eq!(negotiate_encoding("gzip;q=0, *", &all), Some(Encoding::Brotli));
+    }
+
+    #[cfg(feature = "brotli")]
+    #[test]
+    fn test_brotli_is_preferred_when_accepted() {
+        let mut response = text_response(2048);
+
+        gzip_response(&request_with_encoding("br, gzip"), &mut response, GzipOptions::default());
+
+        assert_eq!(response.headers.get("Content-Encoding").unwrap(), "br");
+        let mut body = String::new();
+        brotli::Decompressor::new(response.body.unwrap().as_slice(), 4096).read_to_string(&mut body).unwrap();
+        assert_eq!(body, "a".repeat(2048));
+        assert_eq!(preferred_compression(&request_with_encoding("br;q=0.5, gzip")), Some(Encoding::Gzip));
+        assert_eq!(preferred_compression(&request_with_encoding("br;q=0.8, gzip;q=0.8")), Some(Encoding::Brotli));
+    }
+}
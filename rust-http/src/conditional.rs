@@ -0,0 +1,49 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+use crate::request::HttpRequest;
+use crate::response::HttpResponse;
+
+// Check the request's If-Unmodified-Since against when the resource last changed.
+// True without the header; HTTP dates have whole seconds, so the modification time
+// is truncated before comparing.
+pub fn unmodified_since(request: &HttpRequest, last_modified: SystemTime) -> bool {
+    let Some(since) = request.if_unmodified_since() else {
+        return true;
+    };
+    let whole_seconds = |time: SystemTime| time.duration_since(UNIX_EPOCH).map_or(0, |elapsed| elapsed.as_secs());
+    whole_seconds(last_modified) <= whole_seconds(since)
+}
+
+// Hook for handlers of writes: a 412 to return when the resource changed after the
+// client's If-Unmodified-Since date, None to go ahead
+pub fn check_unmodified_since(request: &HttpRequest, last_modified: SystemTime) -> Option<HttpResponse> {
+    (!unmodified_since(request, last_modified)).then(precondition_failed)
+}
+
+pub fn precondition_failed() -> HttpResponse {
+    HttpResponse::error(412, "Precondition Failed")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn request_since(date: &str) -> HttpRequest {
+        HttpRequest::builder().method("PUT").header("If-Unmodified-Since", date).build()
+    }
+
+    #[test]
+    fn test_check_unmodified_since() {
+        // Thu, 29 Feb 2024 12:30:45 GMT
+        let modified = UNIX_EPOCH + Duration::from_millis(1_709_209_845_500);
+
+        let older = check_unmodified_since(&request_since("Thu, 29 Feb 2024 12:30:44 GMT"), modified);
+        let same = check_unmodified_since(&request_since("Thu, 29 Feb 2024 12:30:45 GMT"), modified);
+        let invalid = check_unmodified_since(&request_since("yesterday"), modified);
+
+        assert_eq!(older.map(|response| response.status_code), Some(412));
+        assert!(same.is_none());
+        assert!(invalid.is_none());
+        assert!(check_unmodified_since(&HttpRequest::default(), modified).is_none());
+    }
+}
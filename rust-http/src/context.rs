@@ -0,0 +1,63 @@
+use std::any::Any;
+use std::cell::{RefCell, RefMut};
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use crate::body::BodyReader;
+use crate::request::HttpRequest;
+
+// Application state shared by every handler of a server
+pub type State = Arc<dyn Any + Send + Sync>;
+
+// Everything a route handler gets for one request
+pub struct Context<'a> {
+    pub request: &'a HttpRequest,
+    // Values captured by `:name` segments of the route pattern
+    pub params: HashMap<String, String>,
+    // Decoded query string parameters
+    pub query: HashMap<String, String>,
+    // State the server was given with `Server::set_state`
+    pub state: Option<State>,
+    // When the caller stops waiting, from X-Request-Deadline
+    pub deadline: Option<SystemTime>,
+    // Unread body of a request to a streaming route, `request.body` is empty then
+    pub body_stream: Option<&'a RefCell<BodyReader<'a>>>,
+}
+
+impl<'a> Context<'a> {
+    pub fn new(request: &'a HttpRequest, params: HashMap<String, String>, state: Option<State>) -> Self {
+        Self { request, params, query: request.query_params(), state, deadline: request.deadline(), body_stream: None }
+    }
+
+    // Hand the handler a body still arriving on the connection
+    pub fn with_body_stream(mut self, body_stream: Option<&'a RefCell<BodyReader<'a>>>) -> Self {
+        self.body_stream = body_stream;
+        self
+    }
+
+    // Borrow the streamed body, if the request has one
+    pub fn body_reader(&self) -> Option<RefMut<'_, BodyReader<'a>>> {
+        self.body_stream.map(RefCell::borrow_mut)
+    }
+
+    // Get the server state if it has type `T`
+    pub fn state<T: Any + Send + Sync>(&self) -> Option<&T> {
+        self.state.as_ref()?.downcast_ref()
+    }
+
+    // Time left before the caller's deadline, zero once it passed
+    pub fn remaining(&self) -> Option<Duration> {
+        let deadline = self.deadline?;
+        Some(deadline.duration_since(SystemTime::now()).unwrap_or_default())
+    }
+
+    // Get a path parameter captured by the route pattern
+    pub fn param(&self, name: &str) -> Option<&str> {
+        self.params.get(name).map(String::as_str)
+    }
+
+    // Get a query string parameter
+    pub fn query(&self, name: &str) -> Option<&str> {
+        self.query.get(name).map(String::as_str)
+    }
+}
@@ -0,0 +1,103 @@
+use std::time::{Duration, SystemTime};
+use crate::date::DateTime;
+
+// Cookie to send with Set-Cookie, built up with its attributes
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cookie {
+    pub name: String,
+    pub value: String,
+    pub path: Option<String>,
+    pub max_age: Option<Duration>,
+    // Send an Expires date matching Max-Age for clients that ignore Max-Age
+    pub with_expires: bool,
+    pub http_only: bool,
+    pub secure: bool,
+}
+
+impl Cookie {
+    pub fn new(name: &str, value: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            value: value.to_string(),
+            path: None,
+            max_age: None,
+            with_expires: false,
+            http_only: false,
+            secure: false,
+        }
+    }
+
+    pub fn path(mut self, path: &str) -> Self {
+        self.path = Some(path.to_string());
+        self
+    }
+
+    // Keep the cookie for `max_age`; with `with_expires` an Expires date is added too
+    pub fn max_age(mut self, max_age: Duration, with_expires: bool) -> Self {
+        self.max_age = Some(max_age);
+        self.with_expires = with_expires;
+        self
+    }
+
+    pub fn http_only(mut self) -> Self {
+        self.http_only = true;
+        self
+    }
+
+    pub fn secure(mut self) -> Self {
+        self.secure = true;
+        self
+    }
+
+    // Set-Cookie value, with Expires counted from now
+    pub fn to_header_value(&self) -> String {
+        self.to_header_value_at(SystemTime::now())
+    }
+
+    // Set-Cookie value, with Expires counted from `now`
+    pub fn to_header_value_at(&self, now: SystemTime) -> String {
+        let mut value = format!("{}={}", self.name, self.value);
+        if let Some(path) = &self.path {
+            value.push_str(&format!("; Path={}", path));
+        }
+        if let Some(max_age) = self.max_age {
+            value.push_str(&format!("; Max-Age={}", max_age.as_secs()));
+            if self.with_expires {
+                let expires = DateTime::from_system_time(now + max_age);
+                value.push_str(&format!("; Expires={}", expires.to_http_date()));
+            }
+        }
+        if self.http_only {
+            value.push_str("; HttpOnly");
+        }
+        if self.secure {
+            value.push_str("; Secure");
+        }
+        value
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::UNIX_EPOCH;
+
+    #[test]
+    fn test_max_age_with_matching_expires() {
+        // 2024-02-29 12:30:45 UTC
+        let now = UNIX_EPOCH + Duration::from_secs(1_709_209_845);
+        let cookie = Cookie::new("theme", "dark").path("/").max_age(Duration::from_secs(86_400), true).http_only();
+
+        assert_eq!(
+            cookie.to_header_value_at(now),
+            "theme=dark; Path=/; Max-Age=86400; Expires=Fri, 01 Mar 2024 12:30:45 GMT; HttpOnly"
+        );
+    }
+
+    #[test]
+    fn test_max_age_alone_by_default() {
+        let cookie = Cookie::new("theme", "dark").max_age(Duration::from_secs(60), false);
+
+        assert_eq!(cookie.to_header_value(), "theme=dark; Max-Age=60");
+    }
+}
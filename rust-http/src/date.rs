@@ -0,0 +1,167 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const WEEKDAYS: [&str; 7] = ["Sun", "Mon", "Tue", "Wed", "Thu", "Fri", "Sat"];
+const MONTHS: [&str; 12] = ["Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec"];
+
+// Struct to represent a UTC date and time broken into its fields
+#[derive(Debug, PartialEq, Eq)]
+pub struct DateTime {
+    pub year: i64,
+    pub month: u32,
+    pub day: u32,
+    pub hour: u32,
+    pub minute: u32,
+    pub second: u32,
+    // 0 is Sunday
+    pub weekday: u32,
+}
+
+impl DateTime {
+    pub fn from_system_time(time: SystemTime) -> Self {
+        let secs = time.duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0) as i64;
+        Self::from_unix(secs)
+    }
+
+    // Convert seconds since the epoch into calendar fields (Howard Hinnant's civil_from_days)
+    pub fn from_unix(secs: i64) -> Self {
+        let days = secs.div_euclid(86_400);
+        let time_of_day = secs.rem_euclid(86_400);
+
+        let z = days + 719_468;
+        let era = z.div_euclid(146_097);
+        let doe = z.rem_euclid(146_097);
+        let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+        let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+        let mp = (5 * doy + 2) / 153;
+        let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+        let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+        let year = yoe + era * 400 + i64::from(month <= 2);
+
+        DateTime {
+            year,
+            month,
+            day,
+            hour: (time_of_day / 3_600) as u32,
+            minute: (time_of_day % 3_600 / 60) as u32,
+            second: (time_of_day % 60) as u32,
+            // The epoch was a Thursday
+            weekday: (days + 4).rem_euclid(7) as u32,
+        }
+    }
+
+    // Parse an HTTP date (IMF-fixdate), e.g. `Sun, 06 Nov 1994 08:49:37 GMT`
+    pub fn parse_http_date(value: &str) -> Option<Self> {
+        let [weekday, day, month, year, time, "GMT"] = value.split_whitespace().collect::<Vec<_>>()[..] else {
+            return None;
+        };
+        let weekday = WEEKDAYS.iter().position(|name| weekday.strip_suffix(',') == Some(*name))? as u32;
+        let month = MONTHS.iter().position(|name| *name == month)? as u32 + 1;
+        let [hour, minute, second] = time.split(':').map(str::parse::<u32>).collect::<Result<Vec<_>, _>>().ok()?[..] else {
+            return None;
+        };
+        let day: u32 = day.parse().ok()?;
+        // Bound every field first so converting to seconds can't overflow
+        if year.len() != 4 || !year.bytes().all(|b| b.is_ascii_digit()) || !(1..=31).contains(&day) || hour >= 24 || minute >= 60 || second > 60 {
+            return None;
+        }
+        let date = DateTime { year: year.parse().ok()?, month, day, hour, minute, second, weekday };
+
+        // Round trip to reject dates like 31 Feb or a weekday that doesn't fit
+        (DateTime::from_unix(date.to_unix()) == date).then_some(date)
+    }
+
+    // Seconds since the epoch (Howard Hinnant's days_from_civil)
+    pub fn to_unix(&self) -> i64 {
+        let (month, day) = (i64::from(self.month), i64::from(self.day));
+        let year = if month <= 2 { self.year - 1 } else { self.year };
+        let era = year.div_euclid(400);
+        let yoe = year - era * 400;
+        let doy = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+        let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+        let days = era * 146_097 + doe - 719_468;
+        days * 86_400 + i64::from(self.hour * 3_600 + self.minute * 60 + self.second)
+    }
+
+    pub fn to_system_time(&self) -> SystemTime {
+        let secs = self.to_unix();
+        if secs >= 0 {
+            UNIX_EPOCH + Duration::from_secs(secs as u64)
+        } else {
+            UNIX_EPOCH - Duration::from_secs(secs.unsigned_abs())
+        }
+    }
+
+    // Format as used by the Common Log Format, e.g. `10/Oct/2000:13:55:36 +0000`
+    pub fn to_clf(&self) -> String {
+        format!(
+            "{:02}/{}/{}:{:02}:{:02}:{:02} +0000",
+            self.day, MONTHS[self.month as usize - 1], self.year, self.hour, self.minute, self.second
+        )
+    }
+
+    // Format as an HTTP date (IMF-fixdate), e.g. `Sun, 06 Nov 1994 08:49:37 GMT`
+    pub fn to_http_date(&self) -> String {
+        format!(
+            "{}, {:02} {} {} {:02}:{:02}:{:02} GMT",
+            WEEKDAYS[self.weekday as usize], self.day, MONTHS[self.month as usize - 1], self.year, self.hour, self.minute, self.second
+        )
+    }
+
+    // Format as ISO 8601 in UTC, e.g. `2000-10-10T13:55:36Z`
+    pub fn to_iso8601(&self) -> String {
+        format!(
+            "{}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+            self.year, self.month, self.day, self.hour, self.minute, self.second
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_unix_epoch() {
+        let date = DateTime::from_unix(0);
+
+        assert_eq!(date.to_iso8601(), "1970-01-01T00:00:00Z");
+    }
+
+    #[test]
+    fn test_from_unix_leap_day() {
+        // 2024-02-29 12:30:45 UTC
+        let date = DateTime::from_unix(1_709_209_845);
+
+        assert_eq!(date.to_iso8601(), "2024-02-29T12:30:45Z");
+        assert_eq!(date.to_clf(), "29/Feb/2024:12:30:45 +0000");
+        assert_eq!(date.to_http_date(), "Thu, 29 Feb 2024 12:30:45 GMT");
+    }
+
+    #[test]
+    fn test_parse_http_date() {
+        let date = DateTime::parse_http_date("Thu, 29 Feb 2024 12:30:45 GMT").unwrap();
+
+        assert_eq!(date.to_unix(), 1_709_209_845);
+        assert_eq!(DateTime::parse_http_date("Sun, 06 Nov 1994 08:49:37 GMT").unwrap().to_unix(), 784_111_777);
+        assert_eq!(DateTime::parse_http_date("Fri, 29 Feb 2024 12:30:45 GMT"), None);
+        assert_eq!(DateTime::parse_http_date("Thu, 30 Feb 2024 12:30:45 GMT"), None);
+        assert_eq!(DateTime::parse_http_date("Sunday, 06-Nov-94 08:49:37 GMT"), None);
+    }
+
+    #[test]
+    fn test_parse_http_date_rejects_out_of_range_fields() {
+        for value in [
+            "Thu, 29 Feb 2024 4294967295:00:00 GMT",
+            "Thu, 29 Feb 2024 24:00:00 GMT",
+            "Thu, 29 Feb 2024 12:60:00 GMT",
+            "Thu, 29 Feb 2024 12:30:61 GMT",
+            "Thu, 4294967295 Feb 2024 12:30:45 GMT",
+            "Thu, 00 Feb 2024 12:30:45 GMT",
+            "Thu, 29 Feb 9223372036854775807 12:30:45 GMT",
+            "Thu, 29 Feb -2024 12:30:45 GMT",
+            "Thu, 29 Feb 24 12:30:45 GMT",
+        ] {
+            assert_eq!(DateTime::parse_http_date(value), None, "{}", value);
+        }
+    }
+}
@@ -0,0 +1,96 @@
+use std::fmt;
+use crate::request::HttpRequest;
+use crate::response::HttpResponse;
+
+// Error a handler can return instead of building the error response itself
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HttpError {
+    pub status_code: u16,
+    pub message: String,
+}
+
+impl HttpError {
+    pub fn new(status_code: u16, message: &str) -> Self {
+        Self { status_code, message: message.to_string() }
+    }
+
+    pub fn bad_request(message: &str) -> Self {
+        Self::new(400, message)
+    }
+
+    pub fn unauthorized(message: &str) -> Self {
+        Self::new(401, message)
+    }
+
+    pub fn forbidden(message: &str) -> Self {
+        Self::new(403, message)
+    }
+
+    pub fn not_found(message: &str) -> Self {
+        Self::new(404, message)
+    }
+
+    pub fn internal(message: &str) -> Self {
+        Self::new(500, message)
+    }
+
+    // JSON error body unless the client only accepts something else, then plain text
+    pub fn to_response(&self, request: &HttpRequest) -> HttpResponse {
+        if request.header("Accept").is_some() && !request.wants_json() {
+            return HttpResponse::new(self.status_code, Default::default(), Some(self.message.clone()))
+                .with_header("Content-Type", "text/plain; charset=utf-8");
+        }
+        HttpResponse::error(self.status_code, &self.message)
+            .with_header("Content-Type", "application/json")
+    }
+}
+
+impl fmt::Display for HttpError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.status_code, self.message)
+    }
+}
+
+impl std::error::Error for HttpError {}
+
+// What a route handler may return
+pub trait IntoResponse {
+    fn into_response(self, request: &HttpRequest) -> HttpResponse;
+}
+
+impl IntoResponse for HttpResponse {
+    fn into_response(self, _: &HttpRequest) -> HttpResponse {
+        self
+    }
+}
+
+impl<E: Into<HttpError>> IntoResponse for Result<HttpResponse, E> {
+    fn into_response(self, request: &HttpRequest) -> HttpResponse {
+        self.unwrap_or_else(|e| e.into().to_response(request))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request_accepting(accept: &str) -> HttpRequest {
+        HttpRequest {
+            headers: vec![format!("Accept: {}", accept)],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_error_negotiates_body() {
+        let error = HttpError::not_found("No such user");
+
+        let json = error.to_response(&HttpRequest::default());
+        let text = error.to_response(&request_accepting("text/html"));
+
+        assert_eq!(json.status_code, 404);
+        assert!(String::from_utf8(json.body.unwrap()).unwrap().contains("\"message\":\"No such user\""));
+        assert_eq!(text.headers.get("Content-Type").unwrap(), "text/plain; charset=utf-8");
+        assert_eq!(text.body, Some(b"No such user".to_vec()));
+    }
+}
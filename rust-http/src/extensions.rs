@@ -0,0 +1,66 @@
+use std::any::{Any, TypeId};
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
+
+// Values keyed by their type, so middleware and handlers can share data without
+// agreeing on string keys
+#[derive(Clone, Default)]
+pub struct Extensions {
+    map: HashMap<TypeId, Arc<dyn Any + Send + Sync>>,
+}
+
+impl Extensions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Store a value, replacing any earlier value of the same type
+    pub fn insert<T: Any + Send + Sync>(&mut self, value: T) {
+        self.map.insert(TypeId::of::<T>(), Arc::new(value));
+    }
+
+    pub fn get<T: Any + Send + Sync>(&self) -> Option<&T> {
+        self.map.get(&TypeId::of::<T>()).and_then(|value| value.downcast_ref())
+    }
+
+    pub fn remove<T: Any + Send + Sync>(&mut self) -> bool {
+        self.map.remove(&TypeId::of::<T>()).is_some()
+    }
+
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+}
+
+impl fmt::Debug for Extensions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Extensions").field("len", &self.map.len()).finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, PartialEq)]
+    struct UserId(u64);
+
+    #[test]
+    fn test_insert_get_by_type() {
+        let mut extensions = Extensions::new();
+        extensions.insert(UserId(7));
+        extensions.insert("label");
+        extensions.insert(UserId(8));
+
+        assert_eq!(extensions.get::<UserId>(), Some(&UserId(8)));
+        assert_eq!(extensions.get::<&str>(), Some(&"label"));
+        assert_eq!(extensions.get::<u32>(), None);
+        assert!(extensions.remove::<UserId>());
+        assert_eq!(extensions.len(), 1);
+    }
+}
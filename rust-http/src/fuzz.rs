@@ -0,0 +1,178 @@
+// Fuzz-style tests feeding the request parser generated and mutated input. Inputs
+// come from a fixed-seed generator, so a failure reproduces on every run.
+
+use std::io::{self, Cursor, Read, Write};
+use std::net::SocketAddr;
+use std::time::Duration;
+use crate::client::{Client, Connection};
+use crate::request::{HttpRequest, ParseOptions, RequestError};
+
+// Requests the mutations start from
+const SEEDS: &[&[u8]] = &[
+    b"GET / HTTP/1.1\r\nHost: example.com\r\n\r\n",
+    b"POST /items?id=1&name=a HTTP/1.1\r\nContent-Type: application/json\r\nContent-Length: 13\r\n\r\n{\"name\":\"a\"}",
+    b"PUT /upload HTTP/1.1\r\nTransfer-Encoding: chunked\r\nTrailer: X-Checksum\r\n\r\n5\r\nhello\r\n0\r\nX-Checksum: 1\r\n\r\n",
+    b"GET http://example.com/path HTTP/1.0\r\nCookie: sessionId=abc\r\nRange: bytes=0-10\r\n\r\n",
+];
+
+// Bytes that tend to matter to the parser
+const INTERESTING: &[u8] = b"\0\r\n \t:;,?&=/%-0123456789aAfFxX\x7f\xff";
+
+// Small xorshift generator, enough to spread inputs around
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn below(&mut self, limit: usize) -> usize {
+        (self.next() % limit.max(1) as u64) as usize
+    }
+
+    fn byte(&mut self) -> u8 {
+        if self.below(2) == 0 {
+            INTERESTING[self.below(INTERESTING.len())]
+        } else {
+            self.next() as u8
+        }
+    }
+
+    // Change a seed with a few inserted, replaced, removed or duplicated bytes
+    fn mutate(&mut self, seed: &[u8]) -> Vec<u8> {
+        let mut input = seed.to_vec();
+        for _ in 0..=self.below(8) {
+            let at = self.below(input.len() + 1);
+            match self.below(4) {
+                0 => input.insert(at, self.byte()),
+                1 if at < input.len() => input[at] = self.byte(),
+                2 if at < input.len() => {
+                    let end = (at + self.below(16)).min(input.len());
+                    input.drain(at..end);
+                }
+                _ => {
+                    let end = (at + self.below(32)).min(input.len());
+                    let copy = input[at..end].to_vec();
+                    input.splice(at..at, copy);
+                }
+            }
+        }
+        input
+    }
+}
+
+// In-memory connection serving `input` and swallowing writes
+struct Input(Cursor<Vec<u8>>);
+
+impl Read for Input {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.0.read(buf)
+    }
+}
+
+impl Write for Input {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Connection for Input {
+    fn set_read_timeout(&self, _: Option<Duration>) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn set_write_timeout(&self, _: Option<Duration>) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn peer_addr(&self) -> io::Result<SocketAddr> {
+        Ok(SocketAddr::from(([127, 0, 0, 1], 0)))
+    }
+
+    fn shutdown(&self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+fn parse(input: &[u8], options: &ParseOptions) -> Result<HttpRequest, RequestError> {
+    Client::new(Input(Cursor::new(input.to_vec()))).parse_request_with(options)
+}
+
+// Parse the input and check the outcome is a request or a clean error status
+fn check(input: &[u8], options: &ParseOptions) {
+    match parse(input, options) {
+        Ok(request) => assert!(!request.method.is_empty(), "empty method from {:?}", input),
+        Err(RequestError::Rejected(status_code, _)) => {
+            assert!((400..=599).contains(&status_code), "status {} from {:?}", status_code, input)
+        }
+        Err(RequestError::Closed) => {}
+    }
+}
+
+fn small_buffers() -> ParseOptions {
+    ParseOptions { read_buffer_size: 7, max_header_size: 256, ..ParseOptions::default() }
+}
+
+#[test]
+fn test_random_bytes_never_panic() {
+    let mut rng = Rng(0x9e37_79b9_7f4a_7c15);
+    for _ in 0..2000 {
+        let input: Vec<u8> = (0..rng.below(200)).map(|_| rng.byte()).collect();
+        check(&input, &ParseOptions::default());
+        check(&input, &small_buffers());
+    }
+}
+
+#[test]
+fn test_mutated_requests_never_panic() {
+    let mut rng = Rng(0x2545_f491_4f6c_dd1d);
+    for round in 0..4000 {
+        let input = rng.mutate(SEEDS[round % SEEDS.len()]);
+        check(&input, &ParseOptions::default());
+        check(&input, &small_buffers());
+        check(&input, &ParseOptions { hardened: false, strict_line_endings: true, ..ParseOptions::default() });
+    }
+}
+
+#[test]
+fn test_hardened_mode_rejects_malformed_request_lines() {
+    let malformed: &[&[u8]] = &[
+        b"GET /\0 HTTP/1.1\r\n\r\n",
+        b"GET\0 / HTTP/1.1\r\n\r\n",
+        b"GET /\r\n\r\n",
+        b"GET / \r\n\r\n",
+        b"GET  /  HTTP/1.1\r\n\r\n",
+        b"GET\t/\tHTTP/1.1\r\n\r\n",
+        b"   GET / HTTP/1.1\r\n\r\n",
+        b"G(E)T / HTTP/1.1\r\n\r\n",
+        b"GET / HTTP/1.1 extra\r\n\r\n",
+        b"GET / HTTX/1.1\r\n\r\n",
+        b"GET / HTTP/11\r\n\r\n",
+        b"GET / HTTP/1.1\r\nHost: a\0b\r\n\r\n",
+        b"get / HTTP/1.1\r\n\r\n",
+    ];
+
+    for input in malformed {
+        match parse(input, &ParseOptions::default()) {
+            Err(RequestError::Rejected(400, _)) => {}
+            other => panic!("{:?} gave {:?}", String::from_utf8_lossy(input), other.map(|request| request.path)),
+        }
+    }
+}
+
+#[test]
+fn test_lenient_mode_accepts_missing_version() {
+    let options = ParseOptions { hardened: false, ..ParseOptions::default() };
+
+    let request = parse(b"GET  /legacy\r\n\r\n", &options).unwrap();
+
+    assert_eq!(request.path, "/legacy");
+    assert_eq!(request.version, "HTTP/1.1");
+}
@@ -1,5 +1,35 @@
 pub mod methods;
+pub mod extensions;
 pub mod request;
+pub mod body;
 pub mod response;
+pub mod error;
+pub mod context;
+pub mod router;
+pub mod negotiate;
+pub mod cache;
+pub mod validate;
+pub mod auth;
+pub mod cookie;
+pub mod signing;
+pub mod session;
+pub mod middleware;
+pub mod static_files;
+pub mod trace;
+pub mod date;
+pub mod conditional;
+pub mod cancel;
+pub mod logging;
+pub mod stats;
+pub mod compression;
+pub mod range;
+pub mod sse;
 pub mod client;
-pub mod server;
\ No newline at end of file
+pub mod lifecycle;
+pub mod server;
+#[cfg(feature = "tls")]
+pub mod tls;
+#[cfg(test)]
+mod test_util;
+#[cfg(test)]
+mod fuzz;
@@ -0,0 +1,189 @@
+use std::collections::HashMap;
+use std::net::{Shutdown, SocketAddr, TcpStream};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+use crate::trace::info;
+
+// How often and after how long the reaper closes connections waiting for a request
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReaperOptions {
+    pub idle_limit: Duration,
+    pub interval: Duration,
+}
+
+// An open connection and since when it has been waiting for a request
+struct Tracked {
+    stream: TcpStream,
+    idle_since: Option<Instant>,
+}
+
+// Tracks the listener and the open connections so the server can be shut down
+#[derive(Default)]
+pub struct Lifecycle {
+    stopping: AtomicBool,
+    next_id: AtomicU64,
+    local_addr: Mutex<Option<SocketAddr>>,
+    // Clones of the open connections, used to force them closed
+    connections: Mutex<HashMap<u64, Tracked>>,
+    drained: Condvar,
+}
+
+impl Lifecycle {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn is_stopping(&self) -> bool {
+        self.stopping.load(Ordering::SeqCst)
+    }
+
+    // Address the listener is bound to, once it is running
+    pub fn local_addr(&self) -> Option<SocketAddr> {
+        *self.local_addr.lock().unwrap()
+    }
+
+    pub fn set_local_addr(&self, addr: SocketAddr) {
+        *self.local_addr.lock().unwrap() = Some(addr);
+    }
+
+    // Start tracking an accepted connection, returning its id. It only counts as idle once
+    // a worker picks it up and waits for a request, so queued connections aren't reaped.
+    pub fn register(&self, stream: &TcpStream) -> u64 {
+        let id = self.next_id.fetch_add(1, Ordering::SeqCst);
+        if let Ok(clone) = stream.try_clone() {
+            let tracked = Tracked { stream: clone, idle_since: None };
+            self.connections.lock().unwrap().insert(id, tracked);
+        }
+        id
+    }
+
+    // Note that connection `id` waits for its next request, or got one
+    pub fn set_idle(&self, id: u64, idle: bool) {
+        if let Some(tracked) = self.connections.lock().unwrap().get_mut(&id) {
+            tracked.idle_since = idle.then(Instant::now);
+        }
+    }
+
+    // Close the connections that have waited for a request for `idle_limit` or longer.
+    // Their workers see the connection end and unregister it. Returns how many were closed.
+    pub fn reap_idle(&self, idle_limit: Duration) -> usize {
+        let connections = self.connections.lock().unwrap();
+        let idle = connections
+            .values()
+            .filter(|tracked| tracked.idle_since.is_some_and(|since| since.elapsed() >= idle_limit));
+        let mut reaped = 0;
+        for tracked in idle {
+            let _ = tracked.stream.shutdown(Shutdown::Both);
+            reaped += 1;
+        }
+        reaped
+    }
+
+    // Reap idle connections every `interval` until the server stops
+    pub fn spawn_reaper(self: &Arc<Self>, options: ReaperOptions) {
+        let lifecycle = Arc::clone(self);
+        thread::spawn(move || {
+            while !lifecycle.is_stopping() {
+                thread::sleep(options.interval);
+                let reaped = lifecycle.reap_idle(options.idle_limit);
+                if reaped > 0 {
+                    info!("Closed {} idle connections", reaped);
+                }
+            }
+        });
+    }
+
+    // Stop tracking a finished connection
+    pub fn unregister(&self, id: u64) {
+        let mut connections = self.connections.lock().unwrap();
+        connections.remove(&id);
+        if connections.is_empty() {
+            self.drained.notify_all();
+        }
+    }
+
+    pub fn open_connections(&self) -> usize {
+        self.connections.lock().unwrap().len()
+    }
+
+    // Stop accepting, wait up to `timeout` for open connections, then close the rest.
+    // Returns true if every connection finished before the deadline.
+    pub fn shutdown(&self, timeout: Duration) -> bool {
+        self.stopping.store(true, Ordering::SeqCst);
+
+        // Wake the accept loop so it sees the flag
+        if let Some(addr) = self.local_addr() {
+            let _ = TcpStream::connect_timeout(&addr, Duration::from_millis(100));
+        }
+
+        let deadline = Instant::now() + timeout;
+        let mut connections = self.connections.lock().unwrap();
+        while !connections.is_empty() {
+            let now = Instant::now();
+            if now >= deadline {
+                break;
+            }
+            connections = self.drained.wait_timeout(connections, deadline - now).unwrap().0;
+        }
+
+        let drained = connections.is_empty();
+        for (_, tracked) in connections.drain() {
+            let _ = tracked.stream.shutdown(Shutdown::Both);
+        }
+        drained
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+
+    #[test]
+    fn test_shutdown_without_connections_drains_immediately() {
+        let lifecycle = Lifecycle::new();
+
+        assert!(lifecycle.shutdown(Duration::from_secs(5)));
+        assert!(lifecycle.is_stopping());
+    }
+
+    #[test]
+    fn test_shutdown_closes_remaining_connections() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (accepted, _) = listener.accept().unwrap();
+
+        let lifecycle = Lifecycle::new();
+        lifecycle.register(&accepted);
+
+        assert!(!lifecycle.shutdown(Duration::from_millis(50)));
+        assert_eq!(lifecycle.open_connections(), 0);
+
+        // The client sees the connection closed
+        let mut buffer = [0; 1];
+        assert_eq!(std::io::Read::read(&mut &client, &mut buffer).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_reap_only_idle_connections() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let idle_client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (idle, _) = listener.accept().unwrap();
+        let _busy_client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (busy, _) = listener.accept().unwrap();
+
+        // The busy connection is still queued, only the other one waits for a request
+        let lifecycle = Lifecycle::new();
+        let idle_id = lifecycle.register(&idle);
+        lifecycle.register(&busy);
+        lifecycle.set_idle(idle_id, true);
+        thread::sleep(Duration::from_millis(60));
+
+        assert_eq!(lifecycle.reap_idle(Duration::from_secs(5)), 0);
+        assert_eq!(lifecycle.reap_idle(Duration::from_millis(50)), 1);
+        let mut buffer = [0; 1];
+        assert_eq!(std::io::Read::read(&mut &idle_client, &mut buffer).unwrap(), 0);
+    }
+}
@@ -0,0 +1,185 @@
+use std::net::IpAddr;
+use std::time::SystemTime;
+use crate::date::DateTime;
+use crate::request::HttpRequest;
+use crate::response::HttpResponse;
+use crate::trace::{error, info, warn};
+
+// Layout of the access log line written for every response
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum AccessLogFormat {
+    // Apache Common Log Format
+    #[default]
+    Common,
+    // Common Log Format plus Referer and User-Agent
+    Combined,
+    // One JSON object per line
+    Json,
+}
+
+// Struct to represent everything an access log line reports
+pub struct AccessLogEntry<'a> {
+    // Client address, taken from proxy headers when the server trusts them
+    pub client_ip: Option<IpAddr>,
+    pub time: SystemTime,
+    pub request: &'a HttpRequest,
+    pub response: &'a HttpResponse,
+}
+
+impl AccessLogEntry<'_> {
+    pub fn format(&self, format: AccessLogFormat) -> String {
+        let host = self.client_ip.map(|ip| ip.to_string());
+        let user = self.request.basic_auth().map(|(username, _)| username);
+        let bytes = self.response.body.as_ref().map(|body| body.len());
+        let referer = self.request.referer();
+        let user_agent = self.request.user_agent();
+        let date = DateTime::from_system_time(self.time);
+
+        match format {
+            AccessLogFormat::Common | AccessLogFormat::Combined => {
+                let mut line = format!(
+                    "{} - {} [{}] \"{} {} {}\" {} {}",
+                    host.as_deref().unwrap_or("-"),
+                    user.as_deref().unwrap_or("-"),
+                    date.to_clf(),
+                    self.request.method,
+                    self.request.path,
+                    self.request.version,
+                    self.response.status_code,
+                    bytes.map(|b| b.to_string()).unwrap_or_else(|| "-".to_string()),
+                );
+                if format == AccessLogFormat::Combined {
+                    line.push_str(&format!(
+                        " \"{}\" \"{}\"",
+                        referer.unwrap_or("-").replace('"', "\\\""),
+                        user_agent.unwrap_or("-").replace('"', "\\\""),
+                    ));
+                }
+                line
+            }
+            AccessLogFormat::Json => serde_json::json!({
+                "remote_addr": host,
+                "user": user,
+                "time": date.to_iso8601(),
+                "method": self.request.method,
+                "path": self.request.path,
+                "status": self.response.status_code,
+                "bytes": bytes,
+                "request_bytes": self.request.body.len(),
+                "referer": referer,
+                "user_agent": user_agent,
+            }).to_string(),
+        }
+    }
+}
+
+// Debug line for a sent response: its status and body sizes, or the whole response
+// when `log_bodies` is set
+pub fn sent_response_line(request: Option<&HttpRequest>, response: &HttpResponse, log_bodies: bool) -> String {
+    if log_bodies {
+        return format!("Sent Response: {}", response);
+    }
+    format!(
+        "Sent response {} with {} body bytes for {} request body bytes",
+        response.status_code,
+        response.body.as_ref().map_or(0, Vec::len),
+        request.map_or(0, |request| request.body.len()),
+    )
+}
+
+// Write a log line about a response, at warn level for client errors and error level
+// for server errors, so they show up even when the access log is filtered out
+pub fn log_response(status_code: u16, line: &str) {
+    match status_code {
+        500..=599 => error!("{}", line),
+        400..=499 => warn!("{}", line),
+        _ => info!("{}", line),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    fn log_line(format: AccessLogFormat) -> String {
+        log_line_for("HTTP/1.1", format)
+    }
+
+    fn log_line_for(version: &str, format: AccessLogFormat) -> String {
+        let request = HttpRequest {
+            method: "GET".to_string(),
+            path: "/get".to_string(),
+            version: version.to_string(),
+            headers: vec![
+                "Referer: http://example.com/".to_string(),
+                "User-Agent: curl/8.0".to_string(),
+            ],
+            body: Vec::new(),
+            cookie: None,
+            ..Default::default()
+        };
+        let response = HttpResponse::new(200, HashMap::new(), Some("hello".to_string()));
+
+        AccessLogEntry {
+            client_ip: Some("127.0.0.1".parse().unwrap()),
+            time: UNIX_EPOCH + Duration::from_secs(1_709_209_845),
+            request: &request,
+            response: &response,
+        }.format(format)
+    }
+
+    #[test]
+    fn test_common_log_format() {
+        assert_eq!(
+            log_line(AccessLogFormat::Common),
+            "127.0.0.1 - - [29/Feb/2024:12:30:45 +0000] \"GET /get HTTP/1.1\" 200 5"
+        );
+    }
+
+    #[test]
+    fn test_common_log_format_keeps_request_version() {
+        assert_eq!(
+            log_line_for("HTTP/1.0", AccessLogFormat::Common),
+            "127.0.0.1 - - [29/Feb/2024:12:30:45 +0000] \"GET /get HTTP/1.0\" 200 5"
+        );
+    }
+
+    #[test]
+    fn test_combined_log_format() {
+        assert_eq!(
+            log_line(AccessLogFormat::Combined),
+            "127.0.0.1 - - [29/Feb/2024:12:30:45 +0000] \"GET /get HTTP/1.1\" 200 5 \"http://example.com/\" \"curl/8.0\""
+        );
+    }
+
+    #[test]
+    fn test_json_log_format() {
+        let line: serde_json::Value = serde_json::from_str(&log_line(AccessLogFormat::Json)).unwrap();
+
+        assert_eq!(line["remote_addr"], "127.0.0.1");
+        assert_eq!(line["time"], "2024-02-29T12:30:45Z");
+        assert_eq!(line["method"], "GET");
+        assert_eq!(line["path"], "/get");
+        assert_eq!(line["status"], 200);
+        assert_eq!(line["bytes"], 5);
+        assert_eq!(line["referer"], "http://example.com/");
+        assert_eq!(line["user_agent"], "curl/8.0");
+        assert!(line["user"].is_null());
+        assert_eq!(line["request_bytes"], 0);
+    }
+
+    #[test]
+    fn test_sent_response_line_reports_sizes() {
+        let request = HttpRequest { body: b"{\"id\":1}".to_vec(), ..Default::default() };
+        let response = HttpResponse::new(200, HashMap::new(), Some("secret body".to_string()));
+
+        let line = sent_response_line(Some(&request), &response, false);
+        let full = sent_response_line(Some(&request), &response, true);
+
+        assert_eq!(line, "Sent response 200 with 11 body bytes for 8 request body bytes");
+        assert!(!line.contains("secret body"));
+        assert!(full.ends_with("secret body"));
+    }
+}
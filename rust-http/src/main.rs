@@ -1,7 +1,6 @@
 use std::sync::{Arc, Mutex};
 use std::env;
 use log::error;
-use env_logger;
 use rust_http::server::Server;
 
 fn main() {
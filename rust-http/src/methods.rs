@@ -2,11 +2,13 @@ use std::fs;
 use std::path::Path;
 use serde_json::Value;
 use std::collections::HashMap;
+use std::time::SystemTime;
 use crate::response::HttpResponse;
+use crate::trace::{debug, error};
 
 // Function to handle GET requests
 pub fn handle_get(id: &str) -> HttpResponse {
-    println!("Handling GET request for user with ID: {}", id);
+    debug!("Handling GET request for user with ID: {}", id);
     
     // Construir la ruta del archivo dentro de la carpeta `files`
     let file_path = format!("./files/{}.json", id);
@@ -21,7 +23,7 @@ pub fn handle_get(id: &str) -> HttpResponse {
                 HttpResponse::new(200, headers, Some(contents))
             },
             Err(e) => {
-                println!("Failed to read file: {}", e);
+                error!("Failed to read file: {}", e);
                 HttpResponse::new(500, HashMap::new(), Some(serde_json::json!({
                     "status_code": 500,
                     "message": "Failed to read file"
@@ -36,15 +38,24 @@ pub fn handle_get(id: &str) -> HttpResponse {
     }
 }
 
+// Path a stored id is served from, the request path itself for server requests
+fn resource_location(id: &str) -> String {
+    if id.starts_with('/') {
+        id.to_string()
+    } else {
+        format!("/{}", id)
+    }
+}
+
 // Function to handle POST requests
 pub fn handle_post(id: &str, json_body: Option<&serde_json::Value>) -> HttpResponse {
-    println!("Handling POST request for user with ID: {}", id);
+    debug!("Handling POST request for user with ID: {}", id);
 
     if let Some(data) = json_body {
-        // Check if the JSON body is a valid object
+        // Well-formed JSON that isn't an object can't be stored
         if !data.is_object() {
-            return HttpResponse::new(400, HashMap::new(), Some(serde_json::json!({
-                "status_code": 400,
+            return HttpResponse::new(422, HashMap::new(), Some(serde_json::json!({
+                "status_code": 422,
                 "message": "Invalid JSON data: must be an object"
             }).to_string()));
         }
@@ -56,7 +67,7 @@ pub fn handle_post(id: &str, json_body: Option<&serde_json::Value>) -> HttpRespo
         // Crear el directorio padre si no existe
         if let Some(parent) = path_parent {
             if let Err(e) = fs::create_dir_all(parent) {
-                println!("Failed to create directory: {}", e);
+                error!("Failed to create directory: {}", e);
                 return HttpResponse::new(500, HashMap::new(), Some(serde_json::json!({
                     "status_code": 500,
                     "message": "Failed to create directory"
@@ -67,12 +78,14 @@ pub fn handle_post(id: &str, json_body: Option<&serde_json::Value>) -> HttpRespo
         // Convertir el cuerpo JSON a un string formateado y escribirlo en el archivo
         match serde_json::to_string_pretty(data) {
             Ok(json_string) => match fs::write(&file_path, json_string) {
-                Ok(_) => HttpResponse::new(201, HashMap::new(), Some(serde_json::json!({
+                // Point the client at the stored resource
+                Ok(_) => HttpResponse::created(&resource_location(id), &serde_json::json!({
                     "status_code": 201,
                     "message": "File created successfully"
-                }).to_string())),
+                }).to_string())
+                .with_header("Content-Type", "application/json"),
                 Err(e) => {
-                    println!("Failed to create file: {}", e);
+                    error!("Failed to create file: {}", e);
                     HttpResponse::new(500, HashMap::new(), Some(serde_json::json!({
                         "status_code": 500,
                         "message": format!("Failed to create file: {}", e)
@@ -80,7 +93,7 @@ pub fn handle_post(id: &str, json_body: Option<&serde_json::Value>) -> HttpRespo
                 },
             },
             Err(e) => {
-                println!("Failed to serialize JSON: {}", e);
+                error!("Failed to serialize JSON: {}", e);
                 HttpResponse::new(500, HashMap::new(), Some(serde_json::json!({
                     "status_code": 500,
                     "message": "Failed to serialize JSON"
@@ -95,9 +108,14 @@ pub fn handle_post(id: &str, json_body: Option<&serde_json::Value>) -> HttpRespo
     }
 }
 
+// When the file stored for `id` last changed, None if there is none
+pub fn resource_modified(id: &str) -> Option<SystemTime> {
+    fs::metadata(format!("./files/{}.json", id)).and_then(|metadata| metadata.modified()).ok()
+}
+
 // Function to handle PUT requests
 pub fn handle_put(id: &str, json_body: Option<&serde_json::Value>) -> HttpResponse {
-    println!("Handling PUT request for user with ID: {}", id);
+    debug!("Handling PUT request for user with ID: {}", id);
     
     // Construir la ruta del archivo dentro de la carpeta `files`
     let file_path = format!("./files/{}.json", id);
@@ -122,7 +140,7 @@ pub fn handle_put(id: &str, json_body: Option<&serde_json::Value>) -> HttpRespon
                         "message": "File updated successfully"
                     }).to_string())),
                     Err(e) => {
-                        println!("Failed to update file: {}", e);
+                        error!("Failed to update file: {}", e);
                         HttpResponse::new(500, HashMap::new(), Some(serde_json::json!({
                             "status_code": 500,
                             "message": format!("Failed to update file: {}", e)
@@ -130,7 +148,7 @@ pub fn handle_put(id: &str, json_body: Option<&serde_json::Value>) -> HttpRespon
                     },
                 },
                 Err(e) => {
-                    println!("Failed to serialize JSON: {}", e);
+                    error!("Failed to serialize JSON: {}", e);
                     HttpResponse::new(500, HashMap::new(), Some(serde_json::json!({
                         "status_code": 500,
                         "message": "Failed to serialize JSON"
@@ -153,7 +171,7 @@ pub fn handle_put(id: &str, json_body: Option<&serde_json::Value>) -> HttpRespon
 
 // Function to handle DELETE requests
 pub fn handle_delete(id: &str) -> HttpResponse {
-    println!("Handling DELETE request for user with ID: {}", id);
+    debug!("Handling DELETE request for user with ID: {}", id);
     
     // Construye la ruta del archivo dentro de la carpeta `files`
     let file_path = format!("./files/{}.json", id);
@@ -162,12 +180,9 @@ pub fn handle_delete(id: &str) -> HttpResponse {
     if Path::new(&file_path).exists() {
         // Intenta eliminar el archivo
         match fs::remove_file(&file_path) {
-            Ok(_) => HttpResponse::new(200, HashMap::new(), Some(serde_json::json!({
-                "status_code": 200,
-                "message": "File deleted successfully"
-            }).to_string())),
+            Ok(_) => HttpResponse::no_content(),
             Err(e) => {
-                println!("Failed to delete file: {}", e);
+                error!("Failed to delete file: {}", e);
                 HttpResponse::new(500, HashMap::new(), Some(serde_json::json!({
                     "status_code": 500,
                     "message": "Failed to delete file"
@@ -184,7 +199,7 @@ pub fn handle_delete(id: &str) -> HttpResponse {
 
 // Function to handle PATCH requests
 pub fn handle_patch(id: &str, json_body: Option<&Value>) -> HttpResponse {
-    println!("Handling PATCH request for user with ID: {}", id);
+    debug!("Handling PATCH request for user with ID: {}", id);
 
     // Construir la ruta del archivo dentro de la carpeta `files`
     let file_path = format!("./files/{}.json", id);
@@ -201,7 +216,7 @@ pub fn handle_patch(id: &str, json_body: Option<&Value>) -> HttpResponse {
                     let mut existing_json: Value = match serde_json::from_str(&existing_content) {
                         Ok(json) => json,
                         Err(e) => {
-                            println!("Failed to parse existing JSON: {}", e);
+                            error!("Failed to parse existing JSON: {}", e);
                             return HttpResponse::new(500, HashMap::new(), Some(serde_json::json!({
                                 "status_code": 500,
                                 "message": "Failed to parse existing file"
@@ -221,8 +236,8 @@ pub fn handle_patch(id: &str, json_body: Option<&Value>) -> HttpResponse {
                             }
                         }
 
-                        // Extender el JSON original solo si todas las claves existen
-                        obj.extend(patch.clone());
+                        // Aplicar el patch solo si todas las claves existen
+                        existing_json = merge_patch(&existing_json, data);
 
                         // Convertir el JSON actualizado a string y escribirlo en el archivo
                         match serde_json::to_string_pretty(&existing_json) {
@@ -232,7 +247,7 @@ pub fn handle_patch(id: &str, json_body: Option<&Value>) -> HttpResponse {
                                     "message": "File patched successfully"
                                 }).to_string())),
                                 Err(e) => {
-                                    println!("Failed to write updated file: {}", e);
+                                    error!("Failed to write updated file: {}", e);
                                     HttpResponse::new(500, HashMap::new(), Some(serde_json::json!({
                                         "status_code": 500,
                                         "message": "Failed to patch file"
@@ -240,7 +255,7 @@ pub fn handle_patch(id: &str, json_body: Option<&Value>) -> HttpResponse {
                                 },
                             },
                             Err(e) => {
-                                println!("Failed to serialize updated JSON: {}", e);
+                                error!("Failed to serialize updated JSON: {}", e);
                                 HttpResponse::new(400, HashMap::new(), Some(serde_json::json!({
                                     "status_code": 400,
                                     "message": "Invalid JSON data"
@@ -255,7 +270,7 @@ pub fn handle_patch(id: &str, json_body: Option<&Value>) -> HttpResponse {
                     }
                 },
                 Err(e) => {
-                    println!("Failed to read file: {}", e);
+                    error!("Failed to read file: {}", e);
                     HttpResponse::new(500, HashMap::new(), Some(serde_json::json!({
                         "status_code": 500,
                         "message": "Failed to read file"
@@ -276,16 +291,55 @@ pub fn handle_patch(id: &str, json_body: Option<&Value>) -> HttpResponse {
     }
 }
 
+// Function to apply an RFC 7396 JSON Merge Patch to a resource
+pub fn merge_patch(target: &Value, patch: &Value) -> Value {
+    let Value::Object(patch_obj) = patch else {
+        // Scalars and arrays replace the target entirely
+        return patch.clone();
+    };
+
+    let mut merged = match target {
+        Value::Object(obj) => obj.clone(),
+        _ => serde_json::Map::new(),
+    };
+
+    for (key, value) in patch_obj {
+        if value.is_null() {
+            merged.remove(key);
+        } else {
+            let current = merged.get(key).unwrap_or(&Value::Null);
+            let merged_value = merge_patch(current, value);
+            merged.insert(key.clone(), merged_value);
+        }
+    }
+
+    Value::Object(merged)
+}
+
 // Function to handle unsupported methods
 pub fn handle_method_not_allowed() -> HttpResponse {
     HttpResponse::new(405, HashMap::new(), Some("Method not allowed".to_string()))
 }
 
+// Function to answer OPTIONS with the methods allowed, for `*` those of the whole server
+pub fn server_options_response(allowed: &[String]) -> HttpResponse {
+    HttpResponse::no_content().with_header("Allow", &allowed.join(", "))
+}
+
+// Function to answer the built-in health check
+pub fn health_check_response() -> HttpResponse {
+    let mut headers = HashMap::new();
+    headers.insert("Content-Type".to_string(), "application/json".to_string());
+    HttpResponse::new(200, headers, Some(serde_json::json!({
+        "status": "ok"
+    }).to_string()))
+}
+
 #[cfg(test)]
 mod tests {
     // Import everything out of scope form tests
     use super::*;
-    
+    use crate::request::HttpRequest;
 
     #[test]
     fn test_handle_get_successfully() {
@@ -299,7 +353,7 @@ mod tests {
         let file_contents = fs::read_to_string(&file_path).expect("Failed to read file");
 
         // Assert the file returned is the same
-        assert_eq!(response.body, Some(file_contents), "File contents should be the same");
+        assert_eq!(response.body, Some(file_contents.into_bytes()), "File contents should be the same");
 
     }
 
@@ -324,6 +378,7 @@ mod tests {
         let response = handle_post(id, Some(&json_body));
 
         assert_eq!(response.status_code, 201, "Status code should be 201");
+        assert_eq!(response.headers.get("Location").unwrap(), "/test_post");
         
         let file_path = format!("files/{}.json", id);
         assert!(Path::new(&file_path).exists(), "File should be created");
@@ -336,6 +391,22 @@ mod tests {
         fs::remove_file(file_path).expect("Failed to remove test file");
     }
 
+    #[test]
+    fn test_handle_post_with_built_request() {
+        let request = HttpRequest::builder()
+            .method("POST")
+            .path("/test_post_built")
+            .json(&serde_json::json!({"name": "built"}))
+            .build();
+        let json_body: Value = serde_json::from_slice(&request.body).unwrap();
+
+        let response = handle_post(request.path.trim_start_matches('/'), Some(&json_body));
+
+        assert_eq!(response.status_code, 201);
+        assert_eq!(response.headers.get("Location").unwrap(), "/test_post_built");
+        fs::remove_file("files/test_post_built.json").expect("Failed to remove test file");
+    }
+
     #[test]
     fn test_handle_post_invalid_json_data() {
         let id = "test_invalid_json";
@@ -343,7 +414,7 @@ mod tests {
 
         let response = handle_post(id, Some(&invalid_json));
 
-        assert_eq!(response.status_code, 400, "Status code should be 400");
+        assert_eq!(response.status_code, 422, "Status code should be 422");
     }
 
     #[test]
@@ -424,7 +495,7 @@ mod tests {
         let response = handle_put(id, None);
 
         assert_eq!(response.status_code, 400, "Status code should be 400");
-        assert!(response.body.unwrap().contains("Missing JSON body"), "Response should mention missing JSON body");
+        assert!(String::from_utf8(response.body.unwrap()).unwrap().contains("Missing JSON body"), "Response should mention missing JSON body");
     }
 
     #[test]
@@ -462,8 +533,8 @@ mod tests {
         // Delete the file
         let response = handle_delete(id);
 
-        assert_eq!(response.status_code, 200, "Status code should be 200");
-
+        assert_eq!(response.status_code, 204, "Status code should be 204");
+        assert_eq!(response.body, None, "A 204 has no body");
 
         let file_path = format!("./files/{}.json", id);
         assert!(!Path::new(&file_path).exists(), "File should not exist after deletion");
@@ -536,4 +607,35 @@ mod tests {
         
         assert_eq!(response.status_code, 405, "Status code should be 405");
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_merge_patch_null_deletes_key() {
+        let target = serde_json::json!({"name": "Ana", "email": "ana@example.com"});
+        let patch = serde_json::json!({"email": null});
+
+        assert_eq!(merge_patch(&target, &patch), serde_json::json!({"name": "Ana"}));
+    }
+
+    #[test]
+    fn test_merge_patch_nested_objects() {
+        let target = serde_json::json!({"address": {"city": "Cartago", "zip": "30101"}});
+        let patch = serde_json::json!({"address": {"city": "San José", "country": "CR"}});
+
+        assert_eq!(
+            merge_patch(&target, &patch),
+            serde_json::json!({"address": {"city": "San José", "zip": "30101", "country": "CR"}})
+        );
+    }
+
+    #[test]
+    fn test_merge_patch_replaces_scalars_and_arrays() {
+        let target = serde_json::json!({"count": 1, "tags": ["a", "b"], "meta": {"x": 1}});
+        let patch = serde_json::json!({"count": 2, "tags": ["c"], "meta": "none"});
+
+        assert_eq!(
+            merge_patch(&target, &patch),
+            serde_json::json!({"count": 2, "tags": ["c"], "meta": "none"})
+        );
+        assert_eq!(merge_patch(&target, &serde_json::json!([1, 2])), serde_json::json!([1, 2]));
+    }
+}
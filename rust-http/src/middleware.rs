@@ -0,0 +1,56 @@
+use std::sync::Arc;
+use crate::request::HttpRequest;
+use crate::response::HttpResponse;
+
+// Runs before routing and may modify the request; returning a response answers
+// the request right away
+pub type Middleware = Arc<dyn Fn(&mut HttpRequest) -> Option<HttpResponse> + Send + Sync>;
+
+// Segment limit applied unless the server sets another one
+pub const DEFAULT_MAX_PATH_DEPTH: usize = 32;
+
+// Reject paths with more than `limit` segments with a 400
+pub fn check_path_depth(request: &HttpRequest, limit: usize) -> Option<HttpResponse> {
+    let path = request.path.split('?').next().unwrap_or_default();
+    let depth = path.split('/').filter(|segment| !segment.is_empty()).count();
+    if depth > limit {
+        return Some(HttpResponse::error(400, &format!("Path has more than {} segments", limit)));
+    }
+    None
+}
+
+// Run each middleware in order, stopping at the first that answers
+pub fn run(middleware: &[Middleware], request: &mut HttpRequest) -> Option<HttpResponse> {
+    middleware.iter().find_map(|middleware| middleware(request))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn get(path: &str) -> HttpRequest {
+        HttpRequest {
+            method: "GET".to_string(),
+            path: path.to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_path_depth_limit() {
+        assert!(check_path_depth(&get("/a/b/c"), 3).is_none());
+        assert!(check_path_depth(&get("/a/b/c/?x=/y/z"), 3).is_none());
+        assert_eq!(check_path_depth(&get("/a/b/c/d"), 3).unwrap().status_code, 400);
+    }
+
+    #[test]
+    fn test_run_stops_at_first_response() {
+        let middleware: Vec<Middleware> = vec![
+            Arc::new(|_| None),
+            Arc::new(|_| Some(HttpResponse::forbidden())),
+            Arc::new(|_| panic!("Should not run")),
+        ];
+
+        assert_eq!(run(&middleware, &mut get("/")).unwrap().status_code, 403);
+    }
+}
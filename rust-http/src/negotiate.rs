@@ -0,0 +1,169 @@
+use std::sync::Arc;
+use crate::compression::add_vary;
+use crate::context::Context;
+use crate::error::IntoResponse;
+use crate::response::HttpResponse;
+use crate::router::Handler;
+
+// Media ranges of an Accept header with their quality, e.g. `text/*;q=0.5`
+pub fn parse_accept(accept: &str) -> Vec<(String, f32)> {
+    accept
+        .split(',')
+        .filter_map(|range| {
+            let mut params = range.split(';');
+            let media = params.next()?.trim().to_ascii_lowercase();
+            if media.is_empty() {
+                return None;
+            }
+            let quality = params
+                .find_map(|param| param.trim().strip_prefix("q=")?.trim().parse::<f32>().ok())
+                .unwrap_or(1.0);
+            Some((media, quality))
+        })
+        .collect()
+}
+
+// Quality the Accept ranges give `media`, taken from the most specific matching range
+fn quality(ranges: &[(String, f32)], media: &str) -> f32 {
+    let media = media.to_ascii_lowercase();
+    let main_type = media.split('/').next().unwrap_or_default();
+    ranges
+        .iter()
+        .filter_map(|(range, quality)| {
+            let specificity = if *range == media {
+                2
+            } else if range.strip_suffix("/*") == Some(main_type) {
+                1
+            } else if range == "*/*" {
+                0
+            } else {
+                return None;
+            };
+            Some((specificity, *quality))
+        })
+        .max_by_key(|(specificity, _)| *specificity)
+        .map_or(0.0, |(_, quality)| quality)
+}
+
+// Pick the offered media type the client likes best. Without an Accept header the
+// first offer wins, and equal qualities go to the earlier offer.
+pub fn select<'a>(accept: Option<&str>, offered: &[&'a str]) -> Option<&'a str> {
+    let Some(accept) = accept else {
+        return offered.first().copied();
+    };
+    let ranges = parse_accept(accept);
+
+    let mut best: Option<(&str, f32)> = None;
+    for media in offered {
+        let quality = quality(&ranges, media);
+        if quality > 0.0 && best.is_none_or(|(_, best_quality)| quality > best_quality) {
+            best = Some((media, quality));
+        }
+    }
+    best.map(|(media, _)| media)
+}
+
+// Pick the offered language tag the client likes best from an Accept-Language header.
+// A range like `en` also matches `en-GB`; without the header the first offer wins.
+pub fn select_language<'a>(accept_language: Option<&str>, offered: &[&'a str]) -> Option<&'a str> {
+    let Some(accept_language) = accept_language else {
+        return offered.first().copied();
+    };
+    let ranges = parse_accept(accept_language);
+
+    let mut best: Option<(&str, f32)> = None;
+    for language in offered {
+        let tag = language.to_ascii_lowercase();
+        let quality = ranges
+            .iter()
+            .filter_map(|(range, quality)| {
+                let specificity = if *range == tag {
+                    2
+                } else if tag.strip_prefix(range.as_str()).is_some_and(|rest| rest.starts_with('-')) {
+                    1
+                } else if range == "*" {
+                    0
+                } else {
+                    return None;
+                };
+                Some((specificity, *quality))
+            })
+            .max_by_key(|(specificity, _)| *specificity)
+            .map_or(0.0, |(_, quality)| quality);
+        if quality > 0.0 && best.is_none_or(|(_, best_quality)| quality > best_quality) {
+            best = Some((language, quality));
+        }
+    }
+    best.map(|(language, _)| language)
+}
+
+// Handlers for the representations a route offers, keyed by media type
+#[derive(Default)]
+pub struct Representations {
+    variants: Vec<(String, Handler)>,
+}
+
+impl Representations {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Offer `media_type`, produced by `handler`. Earlier offers win ties.
+    pub fn on<F, R>(mut self, media_type: &str, handler: F) -> Self
+    where
+        F: Fn(&Context) -> R + Send + Sync + 'static,
+        R: IntoResponse,
+    {
+        let handler: Handler = Arc::new(move |ctx: &Context| handler(ctx).into_response(ctx.request));
+        self.variants.push((media_type.to_string(), handler));
+        self
+    }
+
+    // Single handler that serves the best representation, or 406 when none is acceptable
+    pub fn into_handler(self) -> Handler {
+        Arc::new(move |ctx: &Context| {
+            let offered: Vec<&str> = self.variants.iter().map(|(media, _)| media.as_str()).collect();
+            let mut response = match select(ctx.request.header("Accept"), &offered) {
+                Some(media) => {
+                    let (_, handler) = self.variants.iter().find(|(offer, _)| offer == media).unwrap();
+                    let mut response = handler(ctx);
+                    if !response.headers.keys().any(|key| key.eq_ignore_ascii_case("Content-Type")) {
+                        response.headers.insert("Content-Type".to_string(), media.to_string());
+                    }
+                    response
+                }
+                None => HttpResponse::error(406, &format!("Available representations: {}", offered.join(", "))),
+            };
+            add_vary(&mut response, "Accept");
+            response
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_select_by_quality_and_specificity() {
+        let offered = ["application/json", "text/csv"];
+
+        assert_eq!(select(None, &offered), Some("application/json"));
+        assert_eq!(select(Some("text/csv"), &offered), Some("text/csv"));
+        assert_eq!(select(Some("application/json;q=0.4, text/*;q=0.8"), &offered), Some("text/csv"));
+        assert_eq!(select(Some("*/*;q=0.1, text/csv;q=0"), &offered), Some("application/json"));
+        assert_eq!(select(Some("*/*"), &offered), Some("application/json"));
+        assert_eq!(select(Some("image/png"), &offered), None);
+    }
+
+    #[test]
+    fn test_select_language_by_quality_and_prefix() {
+        let offered = ["en-US", "fr", "de"];
+
+        assert_eq!(select_language(None, &offered), Some("en-US"));
+        assert_eq!(select_language(Some("fr;q=0.9, en;q=0.5"), &offered), Some("fr"));
+        assert_eq!(select_language(Some("en"), &offered), Some("en-US"));
+        assert_eq!(select_language(Some("*;q=0.1, de"), &offered), Some("de"));
+        assert_eq!(select_language(Some("ja"), &offered), None);
+    }
+}
@@ -0,0 +1,175 @@
+use std::collections::HashMap;
+use std::ops::Range;
+use crate::request::HttpRequest;
+use crate::response::HttpResponse;
+
+// Outcome of matching a Range header against a body
+#[derive(Debug, PartialEq, Eq)]
+pub enum ByteRange {
+    // Serve these bytes with a 206
+    Partial(Range<usize>),
+    // Nothing in the body matches, answer 416
+    Unsatisfiable,
+    // No usable single range, serve the whole body
+    Full,
+}
+
+// Resolve a `Range: bytes=...` header against a body of `len` bytes. Only single
+// ranges are served; anything else falls back to the full body.
+pub fn parse_range(header: &str, len: usize) -> ByteRange {
+    let Some(spec) = header.trim().strip_prefix("bytes=") else {
+        return ByteRange::Full;
+    };
+    if spec.contains(',') {
+        return ByteRange::Full;
+    }
+    let Some((start, end)) = spec.trim().split_once('-') else {
+        return ByteRange::Full;
+    };
+
+    let range = match (start.trim(), end.trim()) {
+        // Suffix range: the last `n` bytes
+        ("", suffix) => match suffix.parse::<usize>() {
+            Ok(0) => return ByteRange::Unsatisfiable,
+            Ok(n) => len.saturating_sub(n)..len,
+            Err(_) => return ByteRange::Full,
+        },
+        (start, end) => {
+            let Ok(start) = start.parse::<usize>() else {
+                return ByteRange::Full;
+            };
+            let end = match end {
+                "" => len,
+                end => match end.parse::<usize>() {
+                    Ok(end) if end >= start => end.saturating_add(1).min(len),
+                    _ => return ByteRange::Full,
+                },
+            };
+            start..end
+        }
+    };
+
+    if range.start >= len {
+        ByteRange::Unsatisfiable
+    } else {
+        ByteRange::Partial(range)
+    }
+}
+
+// Build the response for a range request over `body`, copying only the bytes sent
+pub fn range_response(body: &[u8], range_header: &str, headers: HashMap<String, String>) -> HttpResponse {
+    let (status_code, headers, range) = range_parts(body.len(), range_header, headers);
+    HttpResponse::from_bytes(status_code, headers, body[range].to_vec())
+}
+
+// Status, headers and the bytes to send for a range request over a body of `len` bytes
+fn range_parts(len: usize, range_header: &str, mut headers: HashMap<String, String>) -> (u16, HashMap<String, String>, Range<usize>) {
+    headers.retain(|key, _| !key.eq_ignore_ascii_case("Content-Length"));
+    headers.insert("Accept-Ranges".to_string(), "bytes".to_string());
+
+    match parse_range(range_header, len) {
+        ByteRange::Partial(range) => {
+            headers.insert(
+                "Content-Range".to_string(),
+                format!("bytes {}-{}/{}", range.start, range.end - 1, len),
+            );
+            (206, headers, range)
+        }
+        ByteRange::Unsatisfiable => {
+            headers.retain(|key, _| !key.eq_ignore_ascii_case("Content-Type"));
+            headers.insert("Content-Range".to_string(), format!("bytes */{}", len));
+            (416, headers, 0..0)
+        }
+        ByteRange::Full => (200, headers, 0..len),
+    }
+}
+
+// Narrow a full 200 response to the range a GET asked for, whatever produced the body.
+// The body is cut down in place rather than copied.
+pub fn apply_range(request: &HttpRequest, response: &mut HttpResponse) {
+    if request.method != "GET" || response.status_code != 200 {
+        return;
+    }
+    // Ranges would apply to the encoded bytes, which isn't supported
+    if response.headers.keys().any(|key| key.eq_ignore_ascii_case("Content-Encoding")) {
+        if let Some(accept_ranges) = response.headers.iter_mut().find(|(key, _)| key.eq_ignore_ascii_case("Accept-Ranges")) {
+            *accept_ranges.1 = "none".to_string();
+        }
+        return;
+    }
+    let Some(range_header) = request.header("Range") else {
+        return;
+    };
+    let Some(body) = response.body.as_mut() else {
+        return;
+    };
+
+    let (status_code, headers, range) = range_parts(body.len(), range_header, std::mem::take(&mut response.headers));
+    body.truncate(range.end);
+    body.drain(..range.start);
+    response.status_code = status_code;
+    response.headers = headers;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_range_forms() {
+        assert_eq!(parse_range("bytes=0-3", 10), ByteRange::Partial(0..4));
+        assert_eq!(parse_range("bytes=6-", 10), ByteRange::Partial(6..10));
+        assert_eq!(parse_range("bytes=-3", 10), ByteRange::Partial(7..10));
+        assert_eq!(parse_range("bytes=5-100", 10), ByteRange::Partial(5..10));
+        assert_eq!(parse_range("bytes=10-12", 10), ByteRange::Unsatisfiable);
+        assert_eq!(parse_range("bytes=0-1,4-5", 10), ByteRange::Full);
+        assert_eq!(parse_range("items=0-1", 10), ByteRange::Full);
+    }
+
+    #[test]
+    fn test_range_response_over_bytes() {
+        let response = range_response(b"0123456789", "bytes=2-4", HashMap::new());
+
+        assert_eq!(response.status_code, 206);
+        assert_eq!(response.headers.get("Content-Range").unwrap(), "bytes 2-4/10");
+        assert_eq!(response.body, Some(b"234".to_vec()));
+    }
+
+    #[test]
+    fn test_encoded_bodies_refuse_ranges() {
+        let request = HttpRequest { method: "GET".to_string(), ..Default::default() };
+        let mut response = HttpResponse::ok("compressed")
+            .with_header("Content-Encoding", "gzip")
+            .with_header("Accept-Ranges", "bytes");
+
+        apply_range(&request, &mut response);
+
+        assert_eq!(response.headers.get("Accept-Ranges").unwrap(), "none");
+    }
+
+    #[test]
+    fn test_unsatisfiable_range() {
+        let response = range_response(b"0123456789", "bytes=20-", HashMap::new());
+
+        assert_eq!(response.status_code, 416);
+        assert_eq!(response.headers.get("Content-Range").unwrap(), "bytes */10");
+    }
+
+    #[test]
+    fn test_apply_range_narrows_body_in_place() {
+        let request = HttpRequest {
+            method: "GET".to_string(),
+            headers: vec!["Range: bytes=2-4".to_string()],
+            ..Default::default()
+        };
+        let mut response = HttpResponse::ok("0123456789");
+        let buffer = response.body.as_ref().unwrap().as_ptr();
+
+        apply_range(&request, &mut response);
+
+        assert_eq!(response.status_code, 206);
+        assert_eq!(response.body, Some(b"234".to_vec()));
+        assert_eq!(response.body.as_ref().unwrap().as_ptr(), buffer);
+        assert_eq!(response.headers.get("Content-Range").unwrap(), "bytes 2-4/10");
+    }
+}
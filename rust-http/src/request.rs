@@ -1,9 +1,559 @@
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use std::any::Any;
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use crate::extensions::Extensions;
+use crate::cancel::CancellationToken;
+use crate::negotiate::select_language;
+use crate::date::DateTime;
+
 // Struct to represent an HTTP request
-#[derive(Debug)]
+#[derive(Debug, Clone, Default)]
 pub struct HttpRequest {
     pub method: String,
     pub path: String,
-    pub _headers: Vec<String>,
-    pub body: String,
+    // Protocol from the request line, e.g. `HTTP/1.1`
+    pub version: String,
+    pub headers: Vec<String>,
+    // Raw body bytes, exactly as received
+    pub body: Vec<u8>,
     pub cookie: Option<String>,
+    // Values middleware attaches for later middleware and handlers, e.g. `user_id`
+    pub extensions: HashMap<String, String>,
+    // Values keyed by type, see `insert_ext` and `get_ext`
+    pub typed_extensions: Extensions,
+    // Set when the client disconnects or the handler times out
+    pub cancellation: CancellationToken,
+    // Address of the socket the request came in on
+    pub peer: Option<SocketAddr>,
+}
+
+// What a body sent without a Content-Type looks like
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SniffedBody {
+    // An object or array that parses as JSON
+    Json,
+    // `key=value` pairs joined with `&`
+    Form,
+    // Anything else, left to the handler as is
+    Raw,
+}
+
+// Settings controlling how strictly requests are parsed
+#[derive(Debug, Clone)]
+pub struct ParseOptions {
+    // Reject bare LF line endings instead of accepting them like CRLF
+    pub strict_line_endings: bool,
+    // Reject Content-Length combined with chunked encoding instead of ignoring the length
+    pub reject_length_with_chunked: bool,
+    // Largest request line plus headers accepted before answering 431
+    pub max_header_size: usize,
+    // Longest single request or header line accepted before answering 431
+    pub max_header_line_size: usize,
+    // Bytes asked from the stream per read while receiving headers and body
+    pub read_buffer_size: usize,
+    // Most query string parameters a request may carry before answering 400
+    pub max_query_params: usize,
+    // Largest body accepted before answering 413, None for no limit
+    pub max_body_size: Option<usize>,
+    // Answer 400 to request lines with control characters, stray whitespace, a
+    // method that isn't an uppercase token or a missing version instead of guessing
+    pub hardened: bool,
+}
+
+impl Default for ParseOptions {
+    fn default() -> Self {
+        Self {
+            strict_line_endings: false,
+            reject_length_with_chunked: false,
+            max_header_size: 8 * 1024,
+            max_header_line_size: 8 * 1024,
+            read_buffer_size: 8 * 1024,
+            max_query_params: 256,
+            max_body_size: None,
+            hardened: true,
+        }
+    }
+}
+
+// Reasons why a request could not be parsed
+#[derive(Debug)]
+pub enum RequestError {
+    // Nothing usable was received, the connection is closed without a response
+    Closed,
+    // The request is answered with an error status and message
+    Rejected(u16, String),
+}
+
+// Builds a request directly, for testing handlers without a socket
+#[derive(Debug, Clone)]
+pub struct HttpRequestBuilder {
+    request: HttpRequest,
+}
+
+impl HttpRequestBuilder {
+    pub fn method(mut self, method: &str) -> Self {
+        self.request.method = method.to_string();
+        self
+    }
+
+    pub fn path(mut self, path: &str) -> Self {
+        self.request.path = path.to_string();
+        self
+    }
+
+    // Add a header; a Cookie header also sets the session cookie like a parsed request would
+    pub fn header(mut self, name: &str, value: &str) -> Self {
+        if name.eq_ignore_ascii_case("Cookie") {
+            self.request.cookie = value.split('=').nth(1).map(|cookie| cookie.trim().to_string());
+        }
+        self.request.headers.push(format!("{}: {}", name, value));
+        self
+    }
+
+    pub fn body(mut self, body: impl Into<Vec<u8>>) -> Self {
+        self.request.body = body.into();
+        self
+    }
+
+    // Serialize `value` as the body, labelled application/json
+    pub fn json<T: serde::Serialize>(self, value: &T) -> Self {
+        let body = serde_json::to_vec(value).unwrap_or_default();
+        self.header("Content-Type", "application/json").body(body)
+    }
+
+    pub fn build(self) -> HttpRequest {
+        self.request
+    }
+}
+
+impl HttpRequest {
+    // Start building a `GET /` HTTP/1.1 request
+    pub fn builder() -> HttpRequestBuilder {
+        HttpRequestBuilder {
+            request: HttpRequest {
+                method: "GET".to_string(),
+                path: "/".to_string(),
+                version: "HTTP/1.1".to_string(),
+                ..Default::default()
+            },
+        }
+    }
+
+    // Date of the If-Unmodified-Since header, None when missing or not a valid HTTP date
+    pub fn if_unmodified_since(&self) -> Option<SystemTime> {
+        DateTime::parse_http_date(self.header("If-Unmodified-Since")?).map(|date| date.to_system_time())
+    }
+
+    // Get the value of a header, ignoring the case of its name
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.iter().find_map(|line| {
+            let (key, value) = line.split_once(':')?;
+            if key.trim().eq_ignore_ascii_case(name) {
+                Some(value.trim())
+            } else {
+                None
+            }
+        })
+    }
+
+    // Get every value of a header that may appear more than once
+    pub fn header_values(&self, name: &str) -> Vec<&str> {
+        self.headers
+            .iter()
+            .filter_map(|line| {
+                let (key, value) = line.split_once(':')?;
+                key.trim().eq_ignore_ascii_case(name).then(|| value.trim())
+            })
+            .collect()
+    }
+
+    // Attach a typed value for later middleware and handlers
+    pub fn insert_ext<T: Any + Send + Sync>(&mut self, value: T) {
+        self.typed_extensions.insert(value);
+    }
+
+    pub fn get_ext<T: Any + Send + Sync>(&self) -> Option<&T> {
+        self.typed_extensions.get()
+    }
+
+    // Get the body as text, if it is valid UTF-8
+    pub fn body_text(&self) -> Option<&str> {
+        std::str::from_utf8(&self.body).ok()
+    }
+
+    // Get the address of the client. With `trust_proxy` the first address named by a
+    // Forwarded or X-Forwarded-For header wins over the direct peer.
+    pub fn client_ip(&self, trust_proxy: bool) -> Option<IpAddr> {
+        if trust_proxy {
+            let forwarded = self
+                .header("Forwarded")
+                .and_then(forwarded_for)
+                .or_else(|| self.header("X-Forwarded-For").and_then(|value| value.split(',').next()))
+                .and_then(parse_forwarded_ip);
+            if forwarded.is_some() {
+                return forwarded;
+            }
+        }
+        self.peer.map(|peer| peer.ip())
+    }
+
+    // Check whether the client wants the connection kept open after this request:
+    // HTTP/1.1 unless it sent `Connection: close`, HTTP/1.0 only with `Connection: keep-alive`
+    pub fn keep_alive(&self) -> bool {
+        let has_token = |token: &str| {
+            self.header_values("Connection")
+                .iter()
+                .flat_map(|value| value.split(','))
+                .any(|option| option.trim().eq_ignore_ascii_case(token))
+        };
+        if self.version.eq_ignore_ascii_case("HTTP/1.0") {
+            has_token("keep-alive")
+        } else {
+            !has_token("close")
+        }
+    }
+
+    // Check whether the client waits for a 100 Continue before sending the body
+    pub fn expects_continue(&self) -> bool {
+        !self.version.eq_ignore_ascii_case("HTTP/1.0")
+            && self.header("Expect").is_some_and(|value| value.trim().eq_ignore_ascii_case("100-continue"))
+    }
+
+    // Deadline set by the caller in X-Request-Deadline, as unix milliseconds
+    pub fn deadline(&self) -> Option<SystemTime> {
+        let millis = self.header("X-Request-Deadline")?.parse::<u64>().ok()?;
+        UNIX_EPOCH.checked_add(Duration::from_millis(millis))
+    }
+
+    // Get the Host header, which holds the authority of absolute-form targets too
+    pub fn host(&self) -> Option<&str> {
+        self.header("Host")
+    }
+
+    // Get the User-Agent header, if the client sent one
+    pub fn user_agent(&self) -> Option<&str> {
+        self.header("User-Agent")
+    }
+
+    // Get the Referer header, if the client sent one
+    pub fn referer(&self) -> Option<&str> {
+        self.header("Referer")
+    }
+
+    // Check whether the body is declared as JSON by the Content-Type header
+    pub fn is_json(&self) -> bool {
+        self.header("Content-Type").is_some_and(|value| is_json_media_type(media_type(value)))
+    }
+
+    // Pick the offered language the Accept-Language header prefers, see `select_language`
+    pub fn preferred_language<'a>(&self, offered: &[&'a str]) -> Option<&'a str> {
+        select_language(self.header("Accept-Language"), offered)
+    }
+
+    // Check whether the Accept header asks for a JSON response
+    pub fn wants_json(&self) -> bool {
+        let Some(accept) = self.header("Accept") else {
+            return false;
+        };
+
+        accept.split(',').any(|range| {
+            let mut params = range.split(';');
+            let media = params.next().unwrap_or_default().trim();
+            let rejected = params.any(|param| {
+                param.trim().strip_prefix("q=").and_then(|q| q.parse::<f32>().ok()) == Some(0.0)
+            });
+            !rejected && (media.eq_ignore_ascii_case("application/*") || is_json_media_type(media))
+        })
+    }
+
+    // Decode the query string into a map, the last value of a repeated key wins
+    pub fn query_params(&self) -> HashMap<String, String> {
+        let Some((_, query)) = self.path.split_once('?') else {
+            return HashMap::new();
+        };
+        parse_urlencoded(query)
+    }
+
+    // Decode a form-encoded body into a map, the last value of a repeated key wins
+    pub fn form_params(&self) -> HashMap<String, String> {
+        self.body_text().map(parse_urlencoded).unwrap_or_default()
+    }
+
+    // Guess what the body holds from its content: JSON if it starts like an object or
+    // array and parses, a form if it is made of `key=value` pairs, raw otherwise
+    pub fn sniff_body(&self) -> SniffedBody {
+        let text = self.body_text().unwrap_or_default().trim();
+        if text.starts_with(['{', '[']) && serde_json::from_str::<serde::de::IgnoredAny>(text).is_ok() {
+            return SniffedBody::Json;
+        }
+        let is_form = !text.is_empty()
+            && text.split('&').all(|pair| {
+                pair.split_once('=').is_some_and(|(key, value)| {
+                    !key.is_empty() && !key.contains(char::is_whitespace) && !value.contains(char::is_whitespace)
+                })
+            });
+        if is_form {
+            SniffedBody::Form
+        } else {
+            SniffedBody::Raw
+        }
+    }
+
+    // Extract the username and password from a Basic Authorization header
+    pub fn basic_auth(&self) -> Option<(String, String)> {
+        let authorization = self.header("Authorization")?;
+        let (scheme, encoded) = authorization.split_once(' ')?;
+        if !scheme.eq_ignore_ascii_case("Basic") {
+            return None;
+        }
+
+        let decoded = STANDARD.decode(encoded.trim()).ok()?;
+        let credentials = String::from_utf8(decoded).ok()?;
+        let (username, password) = credentials.split_once(':')?;
+        Some((username.to_string(), password.to_string()))
+    }
+}
+
+// Decode `key=value` pairs joined with `&`, as in query strings and form bodies
+fn parse_urlencoded(input: &str) -> HashMap<String, String> {
+    input
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+            (percent_decode(&key.replace('+', " ")), percent_decode(&value.replace('+', " ")))
+        })
+        .collect()
+}
+
+// Get the media type of a header value without its parameters
+fn media_type(value: &str) -> &str {
+    value.split(';').next().unwrap_or_default().trim()
+}
+
+// Check for application/json or a structured +json type
+fn is_json_media_type(media: &str) -> bool {
+    let media = media.to_ascii_lowercase();
+    media == "application/json" || (media.starts_with("application/") && media.ends_with("+json"))
+}
+
+// Get the `for=` value of the first element of a Forwarded header
+fn forwarded_for(value: &str) -> Option<&str> {
+    let first = value.split(',').next()?;
+    first.split(';').find_map(|pair| {
+        let (key, value) = pair.split_once('=')?;
+        key.trim().eq_ignore_ascii_case("for").then(|| value.trim())
+    })
+}
+
+// Parse a forwarded node such as `192.0.2.60`, `"[2001:db8::1]:4711"` or `203.0.113.9:80`
+fn parse_forwarded_ip(node: &str) -> Option<IpAddr> {
+    let node = node.trim().trim_matches('"');
+    if let Ok(ip) = node.parse() {
+        return Some(ip);
+    }
+    if let Ok(addr) = node.parse::<SocketAddr>() {
+        return Some(addr.ip());
+    }
+    node.strip_prefix('[')?.split(']').next()?.parse().ok()
+}
+
+// Decode %XX escapes, leaving malformed ones as they are
+pub fn percent_decode(input: &str) -> String {
+    let bytes = input.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+
+    while i < bytes.len() {
+        let escaped = (bytes[i] == b'%')
+            .then(|| bytes.get(i + 1..i + 3))
+            .flatten()
+            .and_then(|hex| u8::from_str_radix(std::str::from_utf8(hex).ok()?, 16).ok());
+        match escaped {
+            Some(byte) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            None => {
+                decoded.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request_with_headers(headers: &[&str]) -> HttpRequest {
+        HttpRequest {
+            method: "GET".to_string(),
+            path: "/".to_string(),
+            headers: headers.iter().map(|h| h.to_string()).collect(),
+            body: Vec::new(),
+            cookie: None,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_builder_sets_request_fields() {
+        let request = HttpRequest::builder()
+            .method("PUT")
+            .path("/items/1")
+            .header("Cookie", "sessionId=abc")
+            .json(&serde_json::json!({"name": "a"}))
+            .build();
+
+        assert_eq!(request.method, "PUT");
+        assert_eq!(request.path, "/items/1");
+        assert_eq!(request.version, "HTTP/1.1");
+        assert_eq!(request.cookie.as_deref(), Some("abc"));
+        assert!(request.is_json());
+        assert_eq!(request.body_text(), Some("{\"name\":\"a\"}"));
+    }
+
+    #[test]
+    fn test_header_is_case_insensitive() {
+        let request = request_with_headers(&["content-type: application/json"]);
+
+        assert_eq!(request.header("Content-Type"), Some("application/json"));
+        assert_eq!(request.header("Accept"), None);
+    }
+
+    #[test]
+    fn test_basic_auth_decodes_credentials() {
+        // "admin:secret"
+        let request = request_with_headers(&["Authorization: Basic YWRtaW46c2VjcmV0"]);
+
+        assert_eq!(request.basic_auth(), Some(("admin".to_string(), "secret".to_string())));
+    }
+
+    #[test]
+    fn test_basic_auth_rejects_other_schemes() {
+        let request = request_with_headers(&["Authorization: Bearer YWRtaW46c2VjcmV0"]);
+
+        assert_eq!(request.basic_auth(), None);
+    }
+
+    #[test]
+    fn test_user_agent_and_referer() {
+        let request = request_with_headers(&["User-Agent: Mozilla/5.0", "Referer: http://localhost/index"]);
+
+        assert_eq!(request.user_agent(), Some("Mozilla/5.0"));
+        assert_eq!(request.referer(), Some("http://localhost/index"));
+    }
+
+    #[test]
+    fn test_user_agent_and_referer_missing() {
+        let request = request_with_headers(&["Host: localhost"]);
+
+        assert_eq!(request.user_agent(), None);
+        assert_eq!(request.referer(), None);
+    }
+
+    #[test]
+    fn test_is_json() {
+        assert!(request_with_headers(&["Content-Type: application/json"]).is_json());
+        assert!(request_with_headers(&["Content-Type: application/json; charset=utf-8"]).is_json());
+        assert!(request_with_headers(&["Content-Type: application/merge-patch+json"]).is_json());
+        assert!(!request_with_headers(&["Content-Type: text/plain"]).is_json());
+        assert!(!request_with_headers(&["Content-Type: application/jsonp"]).is_json());
+        assert!(!request_with_headers(&[]).is_json());
+    }
+
+    #[test]
+    fn test_wants_json() {
+        assert!(request_with_headers(&["Accept: application/json"]).wants_json());
+        assert!(request_with_headers(&["Accept: text/html, application/json;q=0.9"]).wants_json());
+        assert!(request_with_headers(&["Accept: application/*"]).wants_json());
+        assert!(!request_with_headers(&["Accept: application/json;q=0"]).wants_json());
+        assert!(!request_with_headers(&["Accept: text/html"]).wants_json());
+        assert!(!request_with_headers(&[]).wants_json());
+    }
+
+    #[test]
+    fn test_query_params_are_decoded() {
+        let request = HttpRequest {
+            path: "/search?q=caf%C3%A9+au+lait&page=2&flag&bad=%zz".to_string(),
+            ..Default::default()
+        };
+
+        let query = request.query_params();
+
+        assert_eq!(query["q"], "café au lait");
+        assert_eq!(query["page"], "2");
+        assert_eq!(query["flag"], "");
+        assert_eq!(query["bad"], "%zz");
+    }
+
+    #[test]
+    fn test_client_ip_from_forwarded_headers() {
+        let mut request = request_with_headers(&["X-Forwarded-For: 203.0.113.9, 10.0.0.2"]);
+        request.peer = Some("10.0.0.1:4000".parse().unwrap());
+        let mut standard = request_with_headers(&["Forwarded: for=\"[2001:db8::1]:4711\";proto=https, for=10.0.0.2"]);
+        standard.peer = request.peer;
+
+        assert_eq!(request.client_ip(true), Some("203.0.113.9".parse().unwrap()));
+        assert_eq!(standard.client_ip(true), Some("2001:db8::1".parse().unwrap()));
+    }
+
+    #[test]
+    fn test_client_ip_uses_peer_without_proxy_trust() {
+        let mut request = request_with_headers(&["X-Forwarded-For: 203.0.113.9"]);
+        request.peer = Some("10.0.0.1:4000".parse().unwrap());
+        let mut garbage = request_with_headers(&["X-Forwarded-For: unknown"]);
+        garbage.peer = request.peer;
+
+        assert_eq!(request.client_ip(false), Some("10.0.0.1".parse().unwrap()));
+        assert_eq!(garbage.client_ip(true), Some("10.0.0.1".parse().unwrap()));
+    }
+
+    #[test]
+    fn test_keep_alive_depends_on_version_and_connection() {
+        let mut request = request_with_headers(&[]);
+        request.version = "HTTP/1.1".to_string();
+        let mut closing = request_with_headers(&["Connection: Close"]);
+        closing.version = "HTTP/1.1".to_string();
+        let mut old = request_with_headers(&["Connection: keep-alive"]);
+        old.version = "HTTP/1.0".to_string();
+
+        assert!(request.keep_alive());
+        assert!(!closing.keep_alive());
+        assert!(old.keep_alive());
+        old.headers.clear();
+        assert!(!old.keep_alive());
+    }
+
+    fn request_with_body(body: &str) -> HttpRequest {
+        HttpRequest { method: "POST".to_string(), body: body.as_bytes().to_vec(), ..Default::default() }
+    }
+
+    #[test]
+    fn test_sniff_json_body() {
+        assert_eq!(request_with_body(" {\"name\": \"a\"}").sniff_body(), SniffedBody::Json);
+        assert_eq!(request_with_body("[1, 2]").sniff_body(), SniffedBody::Json);
+    }
+
+    #[test]
+    fn test_sniff_form_body() {
+        let request = request_with_body("name=a+b&tags=x%2Cy");
+
+        assert_eq!(request.sniff_body(), SniffedBody::Form);
+        assert_eq!(request.form_params().get("name").map(String::as_str), Some("a b"));
+        assert_eq!(request.form_params().get("tags").map(String::as_str), Some("x,y"));
+    }
+
+    #[test]
+    fn test_sniff_raw_body() {
+        assert_eq!(request_with_body("plain text, no pairs").sniff_body(), SniffedBody::Raw);
+        assert_eq!(request_with_body("{not json").sniff_body(), SniffedBody::Raw);
+        assert_eq!(request_with_body("a=1&b").sniff_body(), SniffedBody::Raw);
+    }
 }
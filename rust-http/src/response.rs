@@ -1,61 +1,616 @@
+use std::any::Any;
 use std::collections::HashMap;
+use std::fmt;
+use std::io::{self, Write};
+use std::path::Path;
+use std::time::Duration;
+use serde::Serialize;
+use crate::compression::add_vary;
+use crate::cookie::Cookie;
+use crate::extensions::Extensions;
+use crate::request::HttpRequest;
+use crate::static_files::content_type;
+use crate::trace::warn;
+
+// Most header lines `with_header` and `append_header` add to one response
+pub const MAX_RESPONSE_HEADERS: usize = 100;
 
 // Struct ro represent an HTTP response
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct HttpResponse {
     pub status_code: u16,
     pub headers: HashMap<String, String>,
-    pub body: Option<String>,
+    pub body: Option<Vec<u8>>,
+    // Repeated headers that can't be joined into one line, like a second Set-Cookie
+    pub extra_headers: Vec<(String, String)>,
+    // Fields sent after a chunked body, only to clients that accept them with `TE: trailers`
+    pub trailers: Vec<(String, String)>,
+    // Typed values for code that runs after the handler; never sent to the client
+    pub extensions: Extensions,
 }
 
 impl HttpResponse {
     pub fn new(status_code: u16, headers: HashMap<String, String>, body: Option<String>) -> Self {
-        HttpResponse { status_code, headers, body }
+        HttpResponse::from_parts(status_code, headers, body.map(String::into_bytes))
+    }
+
+    // Build a response with a raw byte body, e.g. for binary files
+    pub fn from_bytes(status_code: u16, headers: HashMap<String, String>, body: Vec<u8>) -> Self {
+        HttpResponse::from_parts(status_code, headers, Some(body))
+    }
+
+    fn from_parts(status_code: u16, headers: HashMap<String, String>, body: Option<Vec<u8>>) -> Self {
+        HttpResponse { status_code, headers, body, extra_headers: Vec::new(), trailers: Vec::new(), extensions: Extensions::new() }
+    }
+
+    // Build a JSON error response with the status code and a message
+    pub fn error(status_code: u16, message: &str) -> Self {
+        HttpResponse::new(status_code, HashMap::new(), Some(serde_json::json!({
+            "status_code": status_code,
+            "message": message
+        }).to_string()))
+    }
+
+    // 200 with a plain text body
+    pub fn ok(body: &str) -> Self {
+        HttpResponse::new(200, HashMap::new(), Some(body.to_string()))
+            .with_header("Content-Type", "text/plain; charset=utf-8")
+    }
+
+    // 200 with the items serialized as a JSON array, written straight into the body
+    // without collecting them first
+    pub fn json_array<T, I>(items: I) -> Self
+    where
+        T: Serialize,
+        I: IntoIterator<Item = T>,
+    {
+        let mut body = vec![b'['];
+        for (i, item) in items.into_iter().enumerate() {
+            if i > 0 {
+                body.push(b',');
+            }
+            if let Err(e) = serde_json::to_writer(&mut body, &item) {
+                warn!("Failed to serialize JSON array item: {}", e);
+                return HttpResponse::error(500, "Failed to serialize response");
+            }
+        }
+        body.push(b']');
+
+        HttpResponse::from_bytes(200, HashMap::new(), body).with_header("Content-Type", "application/json")
+    }
+
+    // 200 with the rows as RFC 4180 CSV, lines ending in CRLF
+    pub fn csv(rows: &[Vec<String>]) -> Self {
+        let mut body = String::new();
+        for row in rows {
+            let fields: Vec<String> = row.iter().map(|field| csv_field(field)).collect();
+            body.push_str(&fields.join(","));
+            body.push_str("\r\n");
+        }
+
+        HttpResponse::new(200, HashMap::new(), Some(body)).with_header("Content-Type", "text/csv; charset=utf-8")
+    }
+
+    // 204 without a body
+    pub fn no_content() -> Self {
+        HttpResponse::new(204, HashMap::new(), None)
+    }
+
+    // 201 pointing at the created resource
+    pub fn created(location: &str, body: &str) -> Self {
+        HttpResponse::new(201, HashMap::new(), Some(body.to_string()))
+            .with_header("Location", location)
+            .with_header("Content-Type", "text/plain; charset=utf-8")
+    }
+
+    // 303 sending the client to GET `location` whatever method it used, e.g. after a
+    // form POST so reloading the result page doesn't submit the form again
+    pub fn see_other(location: &str) -> Self {
+        HttpResponse::new(303, HashMap::new(), None).with_header("Location", location)
+    }
+
+    pub fn bad_request(message: &str) -> Self {
+        HttpResponse::error(400, message)
+    }
+
+    pub fn unauthorized() -> Self {
+        HttpResponse::error(401, "Unauthorized")
+    }
+
+    pub fn forbidden() -> Self {
+        HttpResponse::error(403, "Forbidden")
+    }
+
+    pub fn not_found() -> Self {
+        HttpResponse::error(404, "Not Found")
+    }
+
+    // Change the status code and hand the response back
+    pub fn with_status(mut self, status_code: u16) -> Self {
+        self.status_code = status_code;
+        self
+    }
+
+    // 426 asking the client to switch to `protocol`, e.g. "TLS/1.2" or "websocket"
+    pub fn upgrade_required(protocol: &str) -> Self {
+        HttpResponse::error(426, &format!("Upgrade to {} required", protocol))
+            .with_header("Upgrade", protocol)
+            .with_header("Connection", "Upgrade")
+    }
+
+    // Set a header and hand the response back, for chaining after a constructor.
+    // CR/LF are stripped so a value can't start a header of its own.
+    pub fn with_header(mut self, name: &str, value: &str) -> Self {
+        let name = sanitize_header(name);
+        if self.is_full() && !self.headers.contains_key(&name) {
+            warn!("Dropping header {}, response already has {} headers", name, MAX_RESPONSE_HEADERS);
+            return self;
+        }
+        self.headers.insert(name, sanitize_header(value));
+        self
+    }
+
+    // Whether the response already carries as many header lines as it may
+    fn is_full(&self) -> bool {
+        self.headers.len() + self.extra_headers.len() >= MAX_RESPONSE_HEADERS
+    }
+
+    // Add a value to a header instead of replacing it. List headers like Vary or Link
+    // get the values joined with commas, Set-Cookie gets a line per value.
+    pub fn append_header(mut self, name: &str, value: &str) -> Self {
+        let existing = self.headers.keys().find(|key| key.eq_ignore_ascii_case(name)).cloned();
+        match existing {
+            None => self.with_header(name, value),
+            Some(_) if name.eq_ignore_ascii_case("Set-Cookie") => {
+                if self.is_full() {
+                    warn!("Dropping header {}, response already has {} headers", name, MAX_RESPONSE_HEADERS);
+                    return self;
+                }
+                self.extra_headers.push((sanitize_header(name), sanitize_header(value)));
+                self
+            }
+            Some(key) => {
+                let joined = format!("{}, {}", self.headers[&key], value);
+                self.headers.insert(key, sanitize_header(&joined));
+                self
+            }
+        }
+    }
+
+    // Tag the response with the language it was written in, for responses picked by
+    // Accept-Language, so caches keep one copy per language
+    pub fn with_language(self, language: &str) -> Self {
+        let mut response = self.with_header("Content-Language", language);
+        add_vary(&mut response, "Accept-Language");
+        response
+    }
+
+    // Set the Cache-Control header to a raw directive list, e.g. "no-store" or "max-age=60, must-revalidate"
+    pub fn cache_control(self, directive: &str) -> Self {
+        self.with_header("Cache-Control", directive)
+    }
+
+    // Make clients revalidate before reusing the response
+    pub fn no_cache(self) -> Self {
+        self.cache_control("no-cache")
+    }
+
+    // Let browsers and shared caches keep the response for `max_age`
+    pub fn public(self, max_age: Duration) -> Self {
+        self.cache_control(&format!("public, max-age={}", max_age.as_secs()))
+    }
+
+    // Let only the client's own cache keep the response for `max_age`
+    pub fn private(self, max_age: Duration) -> Self {
+        self.cache_control(&format!("private, max-age={}", max_age.as_secs()))
     }
 
-    pub fn to_string(&self) -> String {
-        let status_text = match self.status_code {
+    // Add a trailer field, e.g. a checksum of the body. The body is then sent chunked
+    // with the trailers after it, or without them to clients that don't accept trailers.
+    pub fn with_trailer(mut self, name: &str, value: &str) -> Self {
+        self.trailers.push((sanitize_header(name), sanitize_header(value)));
+        self
+    }
+
+    // Set a cookie with a Set-Cookie header
+    pub fn set_cookie(self, cookie: &Cookie) -> Self {
+        self.append_header("Set-Cookie", &cookie.to_header_value())
+    }
+
+    // Attach a typed value to the response
+    pub fn insert_ext<T: Any + Send + Sync>(&mut self, value: T) {
+        self.extensions.insert(value);
+    }
+
+    pub fn get_ext<T: Any + Send + Sync>(&self) -> Option<&T> {
+        self.extensions.get()
+    }
+
+    // Build a response that makes browsers download the bytes as `filename`
+    pub fn download(filename: &str, bytes: Vec<u8>) -> Self {
+        let mut headers = HashMap::new();
+        headers.insert("Content-Type".to_string(), content_type(Path::new(filename)).to_string());
+        headers.insert("Content-Length".to_string(), bytes.len().to_string());
+        headers.insert(
+            "Content-Disposition".to_string(),
+            format!("attachment; filename=\"{}\"", quote_filename(filename)),
+        );
+        HttpResponse::from_bytes(200, headers, bytes)
+    }
+
+    // Get the reason phrase for the status code
+    pub fn status_text(&self) -> &'static str {
+        match self.status_code {
             100 => "Continue",
             101 => "Switching Protocols",
             200 => "OK",
             201 => "Created",
             202 => "Accepted",
             204 => "No Content",
+            206 => "Partial Content",
             300 => "Multiple Choices",
             301 => "Moved Permanently",
             302 => "Found",
+            303 => "See Other",
             304 => "Not Modified",
             400 => "Bad Request",
             401 => "Unauthorized",
             403 => "Forbidden",
             404 => "Not Found",
             405 => "Method Not Allowed",
+            406 => "Not Acceptable",
+            408 => "Request Timeout",
+            411 => "Length Required",
+            412 => "Precondition Failed",
+            413 => "Payload Too Large",
+            416 => "Range Not Satisfiable",
+            422 => "Unprocessable Entity",
+            426 => "Upgrade Required",
+            431 => "Request Header Fields Too Large",
             500 => "Internal Server Error",
             501 => "Not Implemented",
             502 => "Bad Gateway",
             503 => "Service Unavailable",
+            504 => "Gateway Timeout",
+            505 => "HTTP Version Not Supported",
             _ => "Unknown Status",
-        };
-        let mut response = format!("HTTP/1.1 {} {}\r\n", self.status_code, status_text);
+        }
+    }
+
+    // 204 and 304 responses never carry a body or Content-Length
+    pub fn forbids_body(&self) -> bool {
+        matches!(self.status_code, 204 | 304)
+    }
+
+    // Write the status line, each header and the body to a writer
+    pub fn write_to<W: Write + ?Sized>(&self, writer: &mut W) -> io::Result<()> {
+        writer.write_all(format!("HTTP/1.1 {} {}\r\n", self.status_code, self.status_text()).as_bytes())?;
         
         // Create a mutable copy of headers
         let mut headers = self.headers.clone();
-        
-        // Add Content-Length header if there's a body
-        if let Some(body) = &self.body {
+        // `identity` isn't a transfer coding HTTP/1.1 sends, Content-Length frames the body
+        headers.retain(|key, value| {
+            !(key.eq_ignore_ascii_case("Transfer-Encoding") && value.trim().eq_ignore_ascii_case("identity"))
+        });
+        let body = if self.forbids_body() {
+            headers.retain(|key, _| !key.eq_ignore_ascii_case("Content-Length"));
+            None
+        } else {
+            self.body.as_ref()
+        };
+
+        // Trailers can only follow a chunked body, which has no Content-Length
+        let chunked = body.is_some() && !self.trailers.is_empty();
+        if chunked {
+            headers.retain(|key, _| {
+                !key.eq_ignore_ascii_case("Content-Length") && !key.eq_ignore_ascii_case("Transfer-Encoding")
+            });
+            headers.insert("Transfer-Encoding".to_string(), "chunked".to_string());
+            let names: Vec<&str> = self.trailers.iter().map(|(name, _)| name.as_str()).collect();
+            headers.insert("Trailer".to_string(), names.join(", "));
+        } else if let Some(body) = body {
+            // Add Content-Length header if there's a body
             headers.entry("Content-Length".to_string())
                 .or_insert_with(|| body.len().to_string());
         }
         
-        // Add headers to the response
-        for (key, value) in headers {
-            response.push_str(&format!("{}: {}\r\n", key, value));
+        // Add headers to the response, also cleaning ones inserted into the map directly
+        let extra = self.extra_headers.iter().map(|(key, value)| (key.clone(), value.clone()));
+        for (key, value) in headers.into_iter().chain(extra) {
+            writer.write_all(format!("{}: {}\r\n", sanitize_header(&key), sanitize_header(&value)).as_bytes())?;
         }
         
-        response.push_str("\r\n");
-        if let Some(body) = &self.body {
-            response.push_str(body);
+        writer.write_all(b"\r\n")?;
+        match body {
+            Some(body) if chunked => {
+                if !body.is_empty() {
+                    writer.write_all(format!("{:X}\r\n", body.len()).as_bytes())?;
+                    writer.write_all(body)?;
+                    writer.write_all(b"\r\n")?;
+                }
+                writer.write_all(b"0\r\n")?;
+                for (name, value) in &self.trailers {
+                    writer.write_all(format!("{}: {}\r\n", name, value).as_bytes())?;
+                }
+                writer.write_all(b"\r\n")?;
+            }
+            Some(body) => writer.write_all(body)?,
+            None => {}
         }
-        response
+        Ok(())
+    }
+}
+
+// Drop the response trailers unless the client announced it accepts them with
+// `TE: trailers`; chunked responses also need an HTTP/1.1 client
+pub fn negotiate_trailers(request: &HttpRequest, response: &mut HttpResponse) {
+    let accepted = request.version != "HTTP/1.0"
+        && request
+            .header_values("TE")
+            .iter()
+            .flat_map(|value| value.split(','))
+            .any(|coding| coding.split(';').next().unwrap_or_default().trim().eq_ignore_ascii_case("trailers"));
+    if !accepted {
+        response.trailers.clear();
+    }
+}
+
+// Quote a CSV field holding a separator, quote or line break, doubling its quotes
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\r', '\n']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+// Remove CR and LF from a header name or value
+fn sanitize_header(text: &str) -> String {
+    text.chars().filter(|c| *c != '\r' && *c != '\n').collect()
+}
+
+// Escape a filename for a quoted header parameter, dropping control characters like CR/LF
+fn quote_filename(filename: &str) -> String {
+    let mut quoted = String::with_capacity(filename.len());
+    for c in filename.chars().filter(|c| !c.is_control()) {
+        if c == '"' || c == '\\' {
+            quoted.push('\\');
+        }
+        quoted.push(c);
+    }
+    quoted
+}
+
+impl fmt::Display for HttpResponse {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut response = Vec::new();
+        self.write_to(&mut response).map_err(|_| fmt::Error)?;
+        f.write_str(&String::from_utf8_lossy(&response))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_display_matches_written_bytes() {
+        let mut headers = HashMap::new();
+        headers.insert("Content-Type".to_string(), "text/plain".to_string());
+        let response = HttpResponse::new(200, headers, Some("hello".to_string()));
+
+        let mut written = Vec::new();
+        response.write_to(&mut written).unwrap();
+
+        assert_eq!(response.to_string().as_bytes(), written.as_slice());
+        assert!(response.to_string().starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.to_string().ends_with("\r\n\r\nhello"));
+    }
+
+    #[test]
+    fn test_download_headers() {
+        let response = HttpResponse::download("report.csv", b"a,b\n1,2\n".to_vec());
+
+        assert_eq!(response.status_code, 200);
+        assert_eq!(response.headers.get("Content-Disposition").unwrap(), "attachment; filename=\"report.csv\"");
+        assert_eq!(response.headers.get("Content-Type").unwrap(), "text/csv");
+        assert_eq!(response.headers.get("Content-Length").unwrap(), "8");
+    }
+
+    #[test]
+    fn test_download_filename_is_escaped() {
+        let response = HttpResponse::download("evil\".txt\r\nSet-Cookie: x=1", Vec::new());
+
+        assert_eq!(
+            response.headers.get("Content-Disposition").unwrap(),
+            "attachment; filename=\"evil\\\".txtSet-Cookie: x=1\""
+        );
+        assert!(!response.to_string().contains("\r\nSet-Cookie"));
+    }
+
+    #[test]
+    fn test_status_constructors() {
+        let ok = HttpResponse::ok("hi").to_string();
+        let no_content = HttpResponse::no_content().to_string();
+        let created = HttpResponse::created("/users/7", "made");
+
+        assert!(ok.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(ok.contains("Content-Type: text/plain; charset=utf-8\r\n"));
+        assert_eq!(no_content, "HTTP/1.1 204 No Content\r\n\r\n");
+        assert!(created.to_string().starts_with("HTTP/1.1 201 Created\r\n"));
+        assert_eq!(created.headers.get("Location").unwrap(), "/users/7");
+        assert!(HttpResponse::bad_request("Missing name").to_string().starts_with("HTTP/1.1 400 Bad Request\r\n"));
+        assert!(HttpResponse::unauthorized().to_string().starts_with("HTTP/1.1 401 Unauthorized\r\n"));
+        assert!(HttpResponse::forbidden().to_string().starts_with("HTTP/1.1 403 Forbidden\r\n"));
+        assert!(HttpResponse::not_found().to_string().starts_with("HTTP/1.1 404 Not Found\r\n"));
+    }
+
+    #[test]
+    fn test_no_content_and_not_modified_omit_body() {
+        for status_code in [204, 304] {
+            let response = HttpResponse::new(status_code, HashMap::new(), Some("ignored".to_string()))
+                .with_header("Content-Length", "7")
+                .with_header("ETag", "\"v1\"");
+
+            let written = response.to_string();
+
+            assert!(written.ends_with("\r\n\r\n"), "{}", written);
+            assert!(!written.contains("Content-Length"));
+            assert!(!written.contains("ignored"));
+            assert!(written.contains("ETag: \"v1\"\r\n"));
+        }
+    }
+
+    #[test]
+    fn test_header_injection_is_stripped() {
+        let mut response = HttpResponse::ok("hi").with_header("X-Name", "value\r\nInjected: 1");
+        response.headers.insert("X-Direct".to_string(), "a\r\nInjected: 2".to_string());
+
+        let written = response.to_string();
+
+        assert_eq!(response.headers.get("X-Name").unwrap(), "valueInjected: 1");
+        assert!(!written.contains("\r\nInjected"));
+        assert!(written.contains("X-Direct: aInjected: 2\r\n"));
+    }
+
+    #[test]
+    fn test_identity_transfer_encoding_is_not_sent() {
+        let response = HttpResponse::ok("hello").with_header("Transfer-Encoding", "identity").to_string();
+
+        assert!(!response.contains("Transfer-Encoding"));
+        assert!(response.contains("Content-Length: 5\r\n"));
+    }
+
+    #[test]
+    fn test_append_header_accumulates() {
+        let response = HttpResponse::ok("page")
+            .append_header("Link", "</page/1>; rel=\"prev\"")
+            .append_header("link", "</page/3>; rel=\"next\"")
+            .set_cookie(&Cookie::new("a", "1"))
+            .set_cookie(&Cookie::new("b", "2"));
+
+        let written = response.to_string();
+
+        assert!(written.contains("Link: </page/1>; rel=\"prev\", </page/3>; rel=\"next\"\r\n"));
+        assert!(written.contains("Set-Cookie: a=1\r\n"));
+        assert!(written.contains("Set-Cookie: b=2\r\n"));
+    }
+
+    #[test]
+    fn test_header_count_is_bounded() {
+        let response = (0..MAX_RESPONSE_HEADERS + 10)
+            .fold(HttpResponse::no_content(), |response, i| response.with_header(&format!("X-{}", i), "v"));
+
+        assert_eq!(response.headers.len(), MAX_RESPONSE_HEADERS);
+        assert_eq!(response.with_header("X-0", "replaced").headers.get("X-0").unwrap(), "replaced");
+    }
+
+    #[test]
+    fn test_appended_header_lines_count_toward_the_bound() {
+        let response = (0..MAX_RESPONSE_HEADERS + 10)
+            .fold(HttpResponse::no_content(), |response, i| response.append_header("Set-Cookie", &format!("c{}=1", i)))
+            .with_header("X-Late", "v");
+
+        assert_eq!(response.headers.len() + response.extra_headers.len(), MAX_RESPONSE_HEADERS);
+        assert!(!response.headers.contains_key("X-Late"));
+    }
+
+    #[test]
+    fn test_json_array_from_iterator() {
+        #[derive(Serialize)]
+        struct Item {
+            id: u32,
+            name: String,
+        }
+
+        let response = HttpResponse::json_array((1..=3).map(|id| Item { id, name: format!("item {}", id) }));
+        let empty = HttpResponse::json_array(std::iter::empty::<Item>());
+
+        let parsed: serde_json::Value = serde_json::from_slice(response.body.as_ref().unwrap()).unwrap();
+        assert_eq!(response.headers.get("Content-Type").unwrap(), "application/json");
+        assert_eq!(parsed.as_array().unwrap().len(), 3);
+        assert_eq!(parsed[2], serde_json::json!({"id": 3, "name": "item 3"}));
+        assert_eq!(empty.body, Some(b"[]".to_vec()));
+    }
+
+    #[test]
+    fn test_csv_rows() {
+        let rows = vec![
+            vec!["id".to_string(), "name".to_string()],
+            vec!["1".to_string(), "widget".to_string()],
+        ];
+
+        let response = HttpResponse::csv(&rows);
+
+        assert_eq!(response.headers.get("Content-Type").unwrap(), "text/csv; charset=utf-8");
+        assert_eq!(response.body, Some(b"id,name\r\n1,widget\r\n".to_vec()));
+    }
+
+    #[test]
+    fn test_csv_quotes_special_fields() {
+        let rows = vec![vec![
+            "a,b".to_string(),
+            "say \"hi\"".to_string(),
+            "two\nlines".to_string(),
+            String::new(),
+        ]];
+
+        let response = HttpResponse::csv(&rows);
+
+        assert_eq!(response.body, Some(b"\"a,b\",\"say \"\"hi\"\"\",\"two\nlines\",\r\n".to_vec()));
+    }
+
+    #[test]
+    fn test_constructors_chain_with_headers() {
+        let response = HttpResponse::no_content().with_header("Cache-Control", "no-store");
+
+        assert_eq!(response.status_code, 204);
+        assert_eq!(response.headers.get("Cache-Control").unwrap(), "no-store");
+    }
+
+    #[test]
+    fn test_upgrade_required() {
+        let response = HttpResponse::upgrade_required("websocket");
+
+        assert!(response.to_string().starts_with("HTTP/1.1 426 Upgrade Required\r\n"));
+        assert_eq!(response.headers.get("Upgrade").unwrap(), "websocket");
+        assert_eq!(response.headers.get("Connection").unwrap(), "Upgrade");
+    }
+
+    #[test]
+    fn test_cache_control_directives() {
+        let public = HttpResponse::ok("logo").public(Duration::from_secs(3600));
+        let private = HttpResponse::ok("profile").private(Duration::from_secs(60));
+        let no_cache = HttpResponse::ok("feed").no_cache();
+        let raw = HttpResponse::ok("secret").cache_control("no-store");
+
+        assert_eq!(public.headers.get("Cache-Control").unwrap(), "public, max-age=3600");
+        assert_eq!(private.headers.get("Cache-Control").unwrap(), "private, max-age=60");
+        assert_eq!(no_cache.headers.get("Cache-Control").unwrap(), "no-cache");
+        assert_eq!(raw.headers.get("Cache-Control").unwrap(), "no-store");
+    }
+
+    #[test]
+    fn test_trailers_follow_chunked_body() {
+        let response = HttpResponse::ok("data").with_header("Content-Length", "4").with_trailer("X-Checksum", "abc");
+
+        let text = response.to_string();
+
+        assert!(text.contains("Transfer-Encoding: chunked\r\n"));
+        assert!(text.contains("Trailer: X-Checksum\r\n"));
+        assert!(!text.contains("Content-Length"));
+        assert!(text.ends_with("\r\n\r\n4\r\ndata\r\n0\r\nX-Checksum: abc\r\n\r\n"));
+    }
+
+    #[test]
+    fn test_trailers_dropped_without_te() {
+        let request = HttpRequest { version: "HTTP/1.1".to_string(), ..Default::default() };
+        let mut response = HttpResponse::ok("data").with_trailer("X-Checksum", "abc");
+
+        negotiate_trailers(&request, &mut response);
+
+        let text = response.to_string();
+        assert!(text.contains("Content-Length: 4\r\n"));
+        assert!(!text.contains("X-Checksum"));
     }
 }
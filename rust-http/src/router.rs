@@ -0,0 +1,514 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+use regex::Regex;
+use crate::body::BodyReader;
+use crate::context::Context;
+use crate::cache;
+use crate::error::IntoResponse;
+use crate::negotiate::Representations;
+use crate::range;
+use crate::request::{percent_decode, HttpRequest};
+use crate::response::HttpResponse;
+use crate::validate::{self, FieldError};
+
+// Handler registered for a route
+pub type Handler = Arc<dyn Fn(&Context) -> HttpResponse + Send + Sync>;
+
+// HTTP request method of a route
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum HttpMethod {
+    Get,
+    Head,
+    Post,
+    Put,
+    Patch,
+    Delete,
+    Options,
+    // Any method without its own variant, kept as sent
+    Other(String),
+}
+
+impl HttpMethod {
+    pub fn as_str(&self) -> &str {
+        match self {
+            HttpMethod::Get => "GET",
+            HttpMethod::Head => "HEAD",
+            HttpMethod::Post => "POST",
+            HttpMethod::Put => "PUT",
+            HttpMethod::Patch => "PATCH",
+            HttpMethod::Delete => "DELETE",
+            HttpMethod::Options => "OPTIONS",
+            HttpMethod::Other(method) => method,
+        }
+    }
+}
+
+impl FromStr for HttpMethod {
+    type Err = std::convert::Infallible;
+
+    fn from_str(method: &str) -> Result<Self, Self::Err> {
+        Ok(match method {
+            "GET" => HttpMethod::Get,
+            "HEAD" => HttpMethod::Head,
+            "POST" => HttpMethod::Post,
+            "PUT" => HttpMethod::Put,
+            "PATCH" => HttpMethod::Patch,
+            "DELETE" => HttpMethod::Delete,
+            "OPTIONS" => HttpMethod::Options,
+            other => HttpMethod::Other(other.to_string()),
+        })
+    }
+}
+
+impl fmt::Display for HttpMethod {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+// Policy for paths that only differ from a route by a trailing slash
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TrailingSlash {
+    // `/about/` and `/about` are different routes
+    #[default]
+    Strict,
+    // Answer with a 301 pointing at the registered form
+    Redirect,
+    // Serve both forms with the same handler
+    Merge,
+}
+
+// Route a request matched, with the values of its `:name` segments
+pub struct Matched {
+    pub route: Route,
+    pub params: HashMap<String, String>,
+}
+
+// Result of looking up a request in the router
+pub enum RouteMatch {
+    Found(Matched),
+    Redirect(String),
+    NotFound,
+}
+
+// Struct to represent a single registered route
+#[derive(Clone)]
+pub struct Route {
+    pub method: String,
+    pub path: String,
+    pub handler: Handler,
+    // Overrides the server's handler timeout for this route
+    pub timeout: Option<Duration>,
+    // Patterns a `:name(regex)` segment must match for the route to apply
+    pub constraints: HashMap<String, Regex>,
+    // The handler reads the body itself instead of getting it buffered
+    pub streaming: bool,
+}
+
+// Route table with its trailing slash policy
+#[derive(Default)]
+pub struct Router {
+    routes: Vec<Route>,
+    pub trailing_slash: TrailingSlash,
+}
+
+impl Router {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Register a handler for a method and path
+    pub fn add<F, R>(&mut self, method: &str, path: &str, handler: F)
+    where
+        F: Fn(&HttpRequest) -> R + Send + Sync + 'static,
+        R: IntoResponse,
+    {
+        self.add_with_timeout(method, path, None, handler);
+    }
+
+    // Register a handler with its own timeout instead of the server default
+    pub fn add_with_timeout<F, R>(&mut self, method: &str, path: &str, timeout: Option<Duration>, handler: F)
+    where
+        F: Fn(&HttpRequest) -> R + Send + Sync + 'static,
+        R: IntoResponse,
+    {
+        self.push(method, path, timeout, Arc::new(move |ctx: &Context| handler(ctx.request).into_response(ctx.request)));
+    }
+
+    // Register a handler that takes the request context, with path parameters
+    // for `:name` segments, e.g. `/users/:id`
+    pub fn add_ctx<F, R>(&mut self, method: &str, path: &str, handler: F)
+    where
+        F: Fn(&Context) -> R + Send + Sync + 'static,
+        R: IntoResponse,
+    {
+        self.push(method, path, None, Arc::new(move |ctx: &Context| handler(ctx).into_response(ctx.request)));
+    }
+
+    // Register a handler whose responses are cached in memory for `ttl`
+    pub fn add_cached<F, R>(&mut self, method: &str, path: &str, ttl: Duration, handler: F)
+    where
+        F: Fn(&Context) -> R + Send + Sync + 'static,
+        R: IntoResponse,
+    {
+        let handler: Handler = Arc::new(move |ctx: &Context| handler(ctx).into_response(ctx.request));
+        self.push(method, path, None, cache::cached(ttl, handler));
+    }
+
+    // Register a handler that only runs for JSON bodies `validator` accepts
+    pub fn add_validated<V, F, R>(&mut self, method: &str, path: &str, validator: V, handler: F)
+    where
+        V: Fn(&serde_json::Value) -> Vec<FieldError> + Send + Sync + 'static,
+        F: Fn(&Context) -> R + Send + Sync + 'static,
+        R: IntoResponse,
+    {
+        let handler: Handler = Arc::new(move |ctx: &Context| handler(ctx).into_response(ctx.request));
+        self.push(method, path, None, validate::validated(validator, handler));
+    }
+
+    // Register a handler that reads the request body as it arrives. Routes with a
+    // timeout get the body buffered, since the handler runs on its own thread.
+    pub fn add_streaming<F, R>(&mut self, method: &str, path: &str, handler: F)
+    where
+        F: Fn(&Context, &mut BodyReader) -> R + Send + Sync + 'static,
+        R: IntoResponse,
+    {
+        let handler: Handler = Arc::new(move |ctx: &Context| {
+            let response = match ctx.body_reader() {
+                Some(mut body) => handler(ctx, &mut body),
+                None => handler(ctx, &mut BodyReader::from_bytes(&ctx.request.body)),
+            };
+            response.into_response(ctx.request)
+        });
+        self.push(method, path, None, handler);
+        if let Some(route) = self.routes.last_mut() {
+            route.streaming = true;
+        }
+    }
+
+    // Register a route offering several representations, picked by the Accept header
+    pub fn add_negotiated(&mut self, method: &str, path: &str, representations: Representations) {
+        self.push(method, path, None, representations.into_handler());
+    }
+
+    // Register a GET route serving `bytes` kept in memory, with byte range support.
+    // A range request copies only the bytes it asked for out of the shared buffer.
+    pub fn add_bytes(&mut self, path: &str, content_type: &str, bytes: Vec<u8>) {
+        let bytes = Arc::new(bytes);
+        let content_type = content_type.to_string();
+        self.add("GET", path, move |request| {
+            let mut headers = HashMap::new();
+            headers.insert("Content-Type".to_string(), content_type.clone());
+            headers.insert("Accept-Ranges".to_string(), "bytes".to_string());
+            match request.header("Range").filter(|_| request.method == "GET") {
+                Some(range) => range::range_response(&bytes, range, headers),
+                None => HttpResponse::from_bytes(200, headers, bytes.to_vec()),
+            }
+        });
+    }
+
+    fn push(&mut self, method: &str, path: &str, timeout: Option<Duration>, handler: Handler) {
+        self.routes.push(Route {
+            method: method.to_string(),
+            path: path.to_string(),
+            handler,
+            timeout,
+            constraints: parse_constraints(path),
+            streaming: false,
+        });
+    }
+
+    // Add every route of `router` under `prefix`
+    pub fn mount(&mut self, prefix: &str, router: Router) {
+        let prefix = prefix.trim_end_matches('/');
+        for mut route in router.routes {
+            route.path = match route.path.as_str() {
+                "/" if !prefix.is_empty() => prefix.to_string(),
+                path => format!("{}{}", prefix, path),
+            };
+            self.routes.push(route);
+        }
+    }
+
+    // Method and path of every registered route, in registration order
+    pub fn routes(&self) -> Vec<(HttpMethod, String)> {
+        self.routes
+            .iter()
+            .map(|route| (route.method.parse().unwrap(), route.path.clone()))
+            .collect()
+    }
+
+    // Look up the handler for a request target, applying the trailing slash policy
+    pub fn find(&self, method: &str, target: &str) -> RouteMatch {
+        let (path, query) = match target.split_once('?') {
+            Some((path, query)) => (path, Some(query)),
+            None => (target, None),
+        };
+
+        if let Some(matched) = self.lookup(method, path) {
+            return RouteMatch::Found(matched);
+        }
+
+        if self.trailing_slash == TrailingSlash::Strict || path == "/" {
+            return RouteMatch::NotFound;
+        }
+
+        // Try the other form of the path
+        let alternate = match path.strip_suffix('/') {
+            Some(stripped) => stripped.to_string(),
+            None => format!("{}/", path),
+        };
+
+        match self.lookup(method, &alternate) {
+            Some(matched) => match self.trailing_slash {
+                TrailingSlash::Merge => RouteMatch::Found(matched),
+                _ => match query {
+                    Some(query) => RouteMatch::Redirect(format!("{}?{}", alternate, query)),
+                    None => RouteMatch::Redirect(alternate),
+                },
+            },
+            None => RouteMatch::NotFound,
+        }
+    }
+
+    // Literal routes win over patterns, then the most specific matching pattern wins,
+    // with registration order breaking ties
+    fn lookup(&self, method: &str, path: &str) -> Option<Matched> {
+        let candidates = || self.routes.iter().filter(|route| route.method == method);
+
+        if let Some(route) = candidates().find(|route| route.path == path) {
+            return Some(Matched { route: route.clone(), params: HashMap::new() });
+        }
+        candidates()
+            .filter_map(|route| Some((route, match_pattern(route, path)?)))
+            .min_by_key(|(route, _)| std::cmp::Reverse(specificity(&route.path)))
+            .map(|(route, params)| Matched { route: route.clone(), params })
+    }
+}
+
+// Compile the constraints of `:name(regex)` segments, e.g. `/users/:id(\d+)`. The
+// regex must match the whole decoded segment and can't contain a `/`.
+fn parse_constraints(pattern: &str) -> HashMap<String, Regex> {
+    pattern
+        .split('/')
+        .filter_map(|segment| {
+            let (name, regex) = segment.strip_prefix(':')?.split_once('(')?;
+            let regex = regex.strip_suffix(')')?;
+            let regex = Regex::new(&format!("^(?:{})$", regex))
+                .unwrap_or_else(|e| panic!("Invalid constraint in route {}: {}", pattern, e));
+            Some((name.to_string(), regex))
+        })
+        .collect()
+}
+
+// Rank of each segment of a pattern, compared from the left: a literal segment is more
+// specific than a constrained `:name(regex)`, then a plain `:name`, then a `*name` wildcard
+fn specificity(pattern: &str) -> Vec<u8> {
+    pattern
+        .split('/')
+        .map(|segment| match segment.strip_prefix(':') {
+            Some(param) if param.contains('(') => 2,
+            Some(_) => 1,
+            None if segment.starts_with('*') => 0,
+            None => 3,
+        })
+        .collect()
+}
+
+// Match a path against a pattern with `:name` segments, returning the captured values.
+// A last `*name` segment captures the rest of the path, slashes included.
+fn match_pattern(route: &Route, path: &str) -> Option<HashMap<String, String>> {
+    if !route.path.contains("/:") && !route.path.contains("/*") {
+        return None;
+    }
+
+    let mut pattern_segments = route.path.split('/');
+    let mut path_segments = path.split('/');
+    let mut params = HashMap::new();
+
+    loop {
+        match (pattern_segments.next(), path_segments.next()) {
+            (None, None) => return Some(params),
+            (Some(expected), Some(actual)) if expected.starts_with('*') => {
+                let rest = std::iter::once(actual).chain(path_segments).collect::<Vec<_>>().join("/");
+                if rest.is_empty() || pattern_segments.next().is_some() {
+                    return None;
+                }
+                params.insert(expected[1..].to_string(), percent_decode(&rest));
+                return Some(params);
+            }
+            (Some(expected), Some(actual)) => match expected.strip_prefix(':') {
+                Some(segment) if !actual.is_empty() => {
+                    let name = segment.split('(').next().unwrap_or_default();
+                    let value = percent_decode(actual);
+                    // A segment failing its constraint leaves the path to other routes
+                    if route.constraints.get(name).is_some_and(|regex| !regex.is_match(&value)) {
+                        return None;
+                    }
+                    params.insert(name.to_string(), value);
+                }
+                Some(_) => return None,
+                None if expected == actual => {}
+                None => return None,
+            },
+            _ => return None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn router_with_about(policy: TrailingSlash) -> Router {
+        let mut router = Router::new();
+        router.trailing_slash = policy;
+        router.add("GET", "/about", |_| HttpResponse::new(200, HashMap::new(), Some("about".to_string())));
+        router
+    }
+
+    #[test]
+    fn test_strict_policy_distinguishes_trailing_slash() {
+        let router = router_with_about(TrailingSlash::Strict);
+
+        assert!(matches!(router.find("GET", "/about"), RouteMatch::Found(_)));
+        assert!(matches!(router.find("GET", "/about/"), RouteMatch::NotFound));
+    }
+
+    #[test]
+    fn test_redirect_policy_points_to_registered_form() {
+        let router = router_with_about(TrailingSlash::Redirect);
+
+        match router.find("GET", "/about/?lang=es") {
+            RouteMatch::Redirect(location) => assert_eq!(location, "/about?lang=es"),
+            _ => panic!("Expected a redirect"),
+        }
+    }
+
+    #[test]
+    fn test_merge_policy_serves_both_forms() {
+        let router = router_with_about(TrailingSlash::Merge);
+
+        assert!(matches!(router.find("GET", "/about"), RouteMatch::Found(_)));
+        assert!(matches!(router.find("GET", "/about/"), RouteMatch::Found(_)));
+        assert!(matches!(router.find("POST", "/about/"), RouteMatch::NotFound));
+    }
+
+    #[test]
+    fn test_routes_lists_mounted_routers_with_prefix() {
+        let ok = |_: &HttpRequest| HttpResponse::new(200, HashMap::new(), None);
+        let mut users = Router::new();
+        users.add("GET", "/", ok);
+        users.add("DELETE", "/remove", ok);
+        let mut router = Router::new();
+        router.add("GET", "/health", ok);
+        router.mount("/api/users/", users);
+
+        assert_eq!(router.routes(), vec![
+            (HttpMethod::Get, "/health".to_string()),
+            (HttpMethod::Get, "/api/users".to_string()),
+            (HttpMethod::Delete, "/api/users/remove".to_string()),
+        ]);
+        assert!(matches!(router.find("DELETE", "/api/users/remove"), RouteMatch::Found(_)));
+    }
+
+    #[test]
+    fn test_http_method_round_trip() {
+        assert_eq!("PATCH".parse::<HttpMethod>().unwrap(), HttpMethod::Patch);
+        assert_eq!("PURGE".parse::<HttpMethod>().unwrap().to_string(), "PURGE");
+    }
+
+    #[test]
+    fn test_pattern_captures_params() {
+        let mut router = Router::new();
+        router.add("GET", "/users/new", |_| HttpResponse::ok("form"));
+        router.add_ctx("GET", "/users/:id/orders/:order", |_| HttpResponse::ok("order"));
+        router.add_ctx("GET", "/users/:id", |_| HttpResponse::ok("user"));
+
+        match router.find("GET", "/users/42/orders/a%20b?full=1") {
+            RouteMatch::Found(matched) => {
+                assert_eq!(matched.params["id"], "42");
+                assert_eq!(matched.params["order"], "a b");
+            }
+            _ => panic!("Expected a match"),
+        }
+        match router.find("GET", "/users/new") {
+            RouteMatch::Found(matched) => assert!(matched.params.is_empty()),
+            _ => panic!("Expected a match"),
+        }
+        assert!(matches!(router.find("GET", "/users/"), RouteMatch::NotFound));
+        assert!(matches!(router.find("GET", "/users/1/orders"), RouteMatch::NotFound));
+    }
+
+    #[test]
+    fn test_specific_routes_win_over_registration_order() {
+        let mut router = Router::new();
+        router.add_ctx("GET", "/users/:id", |_| HttpResponse::ok("param"));
+        router.add("GET", "/users/me", |_| HttpResponse::ok("literal"));
+        router.add_ctx("GET", "/users/:id/:tab", |_| HttpResponse::ok("params"));
+        router.add_ctx("GET", r"/users/:id(\d+)/:tab", |_| HttpResponse::ok("numeric"));
+        router.add_ctx("GET", "/users/me/:tab", |_| HttpResponse::ok("mine"));
+        let matched_path = |path: &str| match router.find("GET", path) {
+            RouteMatch::Found(matched) => matched.route.path,
+            _ => panic!("Expected a match for {}", path),
+        };
+
+        assert_eq!(matched_path("/users/me"), "/users/me");
+        assert_eq!(matched_path("/users/42"), "/users/:id");
+        assert_eq!(matched_path("/users/me/orders"), "/users/me/:tab");
+        assert_eq!(matched_path("/users/42/orders"), r"/users/:id(\d+)/:tab");
+        assert_eq!(matched_path("/users/bob/orders"), "/users/:id/:tab");
+    }
+
+    #[test]
+    fn test_constrained_segment_matches() {
+        let mut router = Router::new();
+        router.add_ctx("GET", r"/users/:id(\d+)", |_| HttpResponse::ok("by id"));
+
+        match router.find("GET", "/users/42") {
+            RouteMatch::Found(matched) => assert_eq!(matched.params["id"], "42"),
+            _ => panic!("Expected a match"),
+        }
+    }
+
+    #[test]
+    fn test_failed_constraint_falls_through() {
+        let mut router = Router::new();
+        router.add_ctx("GET", r"/users/:id(\d+)", |_| HttpResponse::ok("by id"));
+        router.add_ctx("GET", "/users/:name", |_| HttpResponse::ok("by name"));
+
+        match router.find("GET", "/users/alice") {
+            RouteMatch::Found(matched) => {
+                assert_eq!(matched.route.path, "/users/:name");
+                assert_eq!(matched.params["name"], "alice");
+            }
+            _ => panic!("Expected the name route"),
+        }
+        assert!(matches!(router.find("GET", "/users/42x"), RouteMatch::Found(matched) if matched.route.path == "/users/:name"));
+
+        let mut numeric_only = Router::new();
+        numeric_only.add_ctx("GET", r"/users/:id(\d+)", |_| HttpResponse::ok("by id"));
+        assert!(matches!(numeric_only.find("GET", "/users/alice"), RouteMatch::NotFound));
+    }
+
+    #[test]
+    fn test_wildcard_ranks_below_params() {
+        let mut router = Router::new();
+        router.add_ctx("GET", "/files/*rest", |_| HttpResponse::ok("wildcard"));
+        router.add_ctx("GET", "/files/:name", |_| HttpResponse::ok("param"));
+
+        match router.find("GET", "/files/docs/a%20b.txt") {
+            RouteMatch::Found(matched) => {
+                assert_eq!(matched.route.path, "/files/*rest");
+                assert_eq!(matched.params["rest"], "docs/a b.txt");
+            }
+            _ => panic!("Expected the wildcard route"),
+        }
+        assert!(matches!(router.find("GET", "/files/readme"), RouteMatch::Found(matched) if matched.route.path == "/files/:name"));
+        assert!(matches!(router.find("GET", "/files/"), RouteMatch::NotFound));
+    }
+}
@@ -1,85 +1,703 @@
-use std::collections::HashMap;
-use std::sync::{Arc, Mutex};
-use uuid::Uuid;
-use crate::request::HttpRequest;
-use crate::client::Client;
-use std::net::{TcpStream, TcpListener};
+use std::sync::{Arc, Mutex, MutexGuard};
+use crate::request::{HttpRequest, ParseOptions};
+use crate::body::BodyReader;
+use crate::validate::FieldError;
+use crate::response::HttpResponse;
+use crate::router::{HttpMethod, Matched, RouteMatch, Router, TrailingSlash};
+use crate::context::{Context, State};
+use crate::error::IntoResponse;
+use crate::negotiate::Representations;
+use crate::auth::BasicAuthGuard;
+use crate::signing;
+use crate::middleware::{Middleware, DEFAULT_MAX_PATH_DEPTH};
+use crate::logging::AccessLogFormat;
+use crate::compression::GzipOptions;
+use crate::static_files::StaticFiles;
+use crate::client::{Client, KeepAliveOptions};
+use crate::lifecycle::{Lifecycle, ReaperOptions};
+use crate::stats::{Metered, Metrics, ServerStats};
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+use socket2::{Domain, Socket, Type};
+#[cfg(feature = "tls")]
+use crate::tls;
 use threadpool::ThreadPool;
-use log::{error, info};
-use std::thread;
-use std::io::{Write, Read};
+use crate::trace::{error, info, warn};
+use crate::session::{self, CookieSessions, MemorySessionStore, SessionStore};
 use std::time::Duration;
 
 // Main server struct with session management
 pub struct Server {
-    pub sessions: HashMap<String, String>,
+    pub address: String,
+    // Pending connections the OS queues before `accept`
+    pub backlog: i32,
+    // Threads handling connections
+    pub workers: usize,
+    // Connections allowed to wait for a free worker before new ones get a 503
+    pub max_queued: Option<usize>,
+    pub lifecycle: Arc<Lifecycle>,
+    pub sessions: Arc<dyn SessionStore>,
+    // Key for signing session cookies; unsigned or tampered cookies start a new session
+    pub cookie_secret: Option<Vec<u8>>,
+    // Give every client a session and send its cookie with each response
+    pub sessions_enabled: bool,
+    // Keep sessions encrypted in the cookie instead of the store
+    pub cookie_sessions: Option<Arc<CookieSessions>>,
+    pub router: Router,
+    pub state: Option<State>,
+    pub health_check: Option<String>,
+    // Path answered with the server counters as JSON
+    pub stats_path: Option<String>,
+    pub metrics: Arc<Metrics>,
+    pub auth_guards: Vec<BasicAuthGuard>,
+    pub middleware: Vec<Middleware>,
+    // Requests with deeper paths get a 400 before any middleware runs
+    pub max_path_depth: Option<usize>,
+    pub read_timeout: Option<Duration>,
+    pub write_timeout: Option<Duration>,
+    pub handler_timeout: Option<Duration>,
+    pub access_log_format: AccessLogFormat,
+    // Log every response in full, bodies included, at debug level
+    pub log_bodies: bool,
+    // Put what made a handler panic in the 500 body instead of a generic message
+    pub error_details: bool,
+    pub gzip: Option<GzipOptions>,
+    pub parse_options: ParseOptions,
+    // Honor X-HTTP-Method-Override on POST requests
+    pub method_override: bool,
+    // Cancel a request's token when its client disconnects mid-handler
+    pub watch_disconnects: bool,
+    // Take the client address from Forwarded / X-Forwarded-For headers
+    pub trust_proxy: bool,
+    // Give bodies sent without a Content-Type the one their content suggests
+    pub sniff_bodies: bool,
+    // Serve further requests on a connection after the first one
+    pub keep_alive: Option<KeepAliveOptions>,
+    // Close connections left waiting for a request from a background thread
+    pub idle_reaper: Option<ReaperOptions>,
+    // Value of the Server header on every response, None leaves it out
+    pub banner: Option<String>,
+    pub static_files: Option<Arc<StaticFiles>>,
+    #[cfg(feature = "tls")]
+    pub tls: Option<Arc<rustls::ServerConfig>>,
+}
+
+// Server header sent unless the banner is changed or hidden
+pub const DEFAULT_BANNER: &str = "rust-http";
+
+impl Default for Server {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Server {
     pub fn new() -> Self {
         Self {
-            sessions: HashMap::new(),
+            address: "127.0.0.1:8080".to_string(),
+            backlog: 1024,
+            workers: 100,
+            max_queued: None,
+            lifecycle: Arc::new(Lifecycle::new()),
+            sessions: Arc::new(MemorySessionStore::new()),
+            cookie_secret: None,
+            sessions_enabled: true,
+            cookie_sessions: None,
+            router: Router::new(),
+            state: None,
+            health_check: None,
+            stats_path: None,
+            metrics: Arc::new(Metrics::new()),
+            auth_guards: Vec::new(),
+            middleware: Vec::new(),
+            max_path_depth: Some(DEFAULT_MAX_PATH_DEPTH),
+            read_timeout: Some(Duration::from_secs(30)),
+            write_timeout: Some(Duration::from_secs(30)),
+            handler_timeout: None,
+            access_log_format: AccessLogFormat::default(),
+            log_bodies: false,
+            error_details: false,
+            gzip: None,
+            parse_options: ParseOptions::default(),
+            method_override: false,
+            watch_disconnects: false,
+            trust_proxy: false,
+            sniff_bodies: false,
+            keep_alive: None,
+            idle_reaper: None,
+            banner: Some(DEFAULT_BANNER.to_string()),
+            static_files: None,
+            #[cfg(feature = "tls")]
+            tls: None,
+        }
+    }
+
+    // Register a handler for a method and path
+    pub fn route<F, R>(&mut self, method: &str, path: &str, handler: F)
+    where
+        F: Fn(&HttpRequest) -> R + Send + Sync + 'static,
+        R: IntoResponse,
+    {
+        self.router.add(method, path, handler);
+    }
+
+    // Register the GET handler for `/`
+    pub fn set_index<F, R>(&mut self, handler: F)
+    where
+        F: Fn(&HttpRequest) -> R + Send + Sync + 'static,
+        R: IntoResponse,
+    {
+        self.router.add("GET", "/", handler);
+    }
+
+    // Run `middleware` for every request before routing, in the order added
+    pub fn use_middleware<F>(&mut self, middleware: F)
+    where
+        F: Fn(&mut HttpRequest) -> Option<HttpResponse> + Send + Sync + 'static,
+    {
+        self.middleware.push(Arc::new(middleware));
+    }
+
+    // Share `state` with every handler through `Context::state`
+    pub fn set_state<T: std::any::Any + Send + Sync>(&mut self, state: T) {
+        self.state = Some(Arc::new(state));
+    }
+
+    // Register a handler that takes the request context, with path parameters
+    // for `:name` segments, e.g. `/users/:id`
+    pub fn route_ctx<F, R>(&mut self, method: &str, path: &str, handler: F)
+    where
+        F: Fn(&Context) -> R + Send + Sync + 'static,
+        R: IntoResponse,
+    {
+        self.router.add_ctx(method, path, handler);
+    }
+
+    // Register a handler whose successful responses are reused for `ttl`, keyed by
+    // method, target and the headers the response varies on
+    pub fn route_cached<F, R>(&mut self, method: &str, path: &str, ttl: Duration, handler: F)
+    where
+        F: Fn(&Context) -> R + Send + Sync + 'static,
+        R: IntoResponse,
+    {
+        self.router.add_cached(method, path, ttl, handler);
+    }
+
+    // Register a handler behind a validator of its JSON body. Bodies with field errors
+    // get a 422 listing them without the handler running.
+    pub fn route_validated<V, F, R>(&mut self, method: &str, path: &str, validator: V, handler: F)
+    where
+        V: Fn(&serde_json::Value) -> Vec<FieldError> + Send + Sync + 'static,
+        F: Fn(&Context) -> R + Send + Sync + 'static,
+        R: IntoResponse,
+    {
+        self.router.add_validated(method, path, validator, handler);
+    }
+
+    // Register a handler that reads the request body from the connection as it arrives,
+    // for uploads too big to buffer. With a handler timeout the body is buffered first.
+    pub fn route_streaming<F, R>(&mut self, method: &str, path: &str, handler: F)
+    where
+        F: Fn(&Context, &mut BodyReader) -> R + Send + Sync + 'static,
+        R: IntoResponse,
+    {
+        self.router.add_streaming(method, path, handler);
+    }
+
+    // Register a route serving one of several representations by the Accept header,
+    // answering 406 when the client accepts none of them
+    pub fn route_negotiated(&mut self, method: &str, path: &str, representations: Representations) {
+        self.router.add_negotiated(method, path, representations);
+    }
+
+    // Register a handler that gets `timeout` instead of the server's handler timeout
+    pub fn route_with_timeout<F, R>(&mut self, method: &str, path: &str, timeout: Duration, handler: F)
+    where
+        F: Fn(&HttpRequest) -> R + Send + Sync + 'static,
+        R: IntoResponse,
+    {
+        self.router.add_with_timeout(method, path, Some(timeout), handler);
+    }
+
+    // Serve every route of `router` under `prefix`
+    pub fn mount(&mut self, prefix: &str, router: Router) {
+        self.router.mount(prefix, router);
+    }
+
+    // Method and path of every registered route, mounted routers included
+    pub fn routes(&self) -> Vec<(HttpMethod, String)> {
+        self.router.routes()
+    }
+
+    // Serve a file kept in memory at `path`, with byte range support
+    pub fn serve_bytes(&mut self, path: &str, content_type: &str, bytes: Vec<u8>) {
+        self.router.add_bytes(path, content_type, bytes);
+    }
+
+    // Choose how paths with or without a trailing slash are routed
+    pub fn set_trailing_slash(&mut self, policy: TrailingSlash) {
+        self.router.trailing_slash = policy;
+    }
+
+    // Gzip response bodies of at least `min_size` bytes for clients that accept it
+    pub fn enable_gzip(&mut self, min_size: usize) {
+        self.gzip = Some(GzipOptions { min_size });
+    }
+
+    // Let POST requests pick PUT, PATCH or DELETE through X-HTTP-Method-Override
+    pub fn enable_method_override(&mut self) {
+        self.method_override = true;
+    }
+
+    // Bytes requested from the socket per read while receiving a request
+    pub fn set_read_buffer_size(&mut self, size: usize) {
+        self.parse_options.read_buffer_size = size.max(1);
+    }
+
+    // Answer 431 as soon as one request or header line grows past `size` bytes,
+    // whatever the limit on the headers as a whole
+    pub fn set_max_header_line_size(&mut self, size: usize) {
+        self.parse_options.max_header_line_size = size;
+    }
+
+    // Answer 413 to bodies over `size` bytes, before reading them when Content-Length tells
+    pub fn set_max_body_size(&mut self, size: usize) {
+        self.parse_options.max_body_size = Some(size);
+    }
+
+    // Answer 400 to requests with more than `limit` query string parameters
+    pub fn set_max_query_params(&mut self, limit: usize) {
+        self.parse_options.max_query_params = limit;
+    }
+
+    // Watch connections while their handler runs and cancel the request once the
+    // client goes away, so handlers checking `request.cancellation` can stop early
+    pub fn enable_disconnect_watch(&mut self) {
+        self.watch_disconnects = true;
+    }
+
+    // Number of threads handling connections
+    pub fn set_workers(&mut self, workers: usize) {
+        self.workers = workers;
+    }
+
+    // Let at most `limit` accepted connections wait for a worker; the ones after
+    // that are answered 503 right away instead of waiting without bound
+    pub fn set_max_queued(&mut self, limit: usize) {
+        self.max_queued = Some(limit);
+    }
+
+    // Send `banner` as the Server header instead of the default
+    pub fn set_banner(&mut self, banner: &str) {
+        self.banner = Some(banner.to_string());
+    }
+
+    // Leave the Server header out so responses don't reveal the implementation
+    pub fn hide_banner(&mut self) {
+        self.banner = None;
+    }
+
+    // Keep connections open for further, possibly pipelined, requests instead of
+    // closing them after one response
+    pub fn enable_keep_alive(&mut self) {
+        self.keep_alive = Some(KeepAliveOptions::default());
+    }
+
+    // Keep connections open with custom idle timeout and request limits
+    pub fn set_keep_alive_options(&mut self, options: KeepAliveOptions) {
+        self.keep_alive = Some(options);
+    }
+
+    // Check every `interval` for connections that waited longer than `idle_limit` for a
+    // request and close them, freeing their workers before the per-connection timeouts do
+    pub fn enable_idle_reaper(&mut self, idle_limit: Duration, interval: Duration) {
+        self.idle_reaper = Some(ReaperOptions { idle_limit, interval });
+    }
+
+    // Trust the Forwarded and X-Forwarded-For headers set by a reverse proxy in front
+    // of the server when reporting client addresses
+    pub fn enable_trust_proxy(&mut self) {
+        self.trust_proxy = true;
+    }
+
+    // Label bodies sent without a Content-Type as JSON or form data when they look
+    // like it, so handlers checking the type accept sloppy clients
+    pub fn enable_body_sniffing(&mut self) {
+        self.sniff_bodies = true;
+    }
+
+    // Choose the layout of the access log lines
+    pub fn set_access_log_format(&mut self, format: AccessLogFormat) {
+        self.access_log_format = format;
+    }
+
+    // Log whole responses at debug level instead of their sizes. Bodies may be large,
+    // binary or private, so only turn this on while debugging.
+    pub fn enable_body_logging(&mut self) {
+        self.log_bodies = true;
+    }
+
+    // Send the panic message of a failed handler to the client, for development only.
+    // Either way the message is logged.
+    pub fn enable_error_details(&mut self) {
+        self.error_details = true;
+    }
+
+    // Serve the files under `root` for GET requests below `prefix`
+    pub fn serve_static(&mut self, prefix: &str, root: &str) {
+        self.static_files = Some(Arc::new(StaticFiles::new(prefix, root)));
+    }
+
+    // Serve `page` from the static root with a 404 when a file is missing (needs serve_static first)
+    pub fn set_not_found_page(&mut self, page: &str) {
+        if let Some(files) = self.static_files.as_mut() {
+            Arc::make_mut(files).not_found_page = Some(page.to_string());
+        }
+    }
+
+    // Serve the static index.html for missing paths without an extension (needs serve_static first)
+    pub fn set_spa_fallback(&mut self, enabled: bool) {
+        if let Some(files) = self.static_files.as_mut() {
+            Arc::make_mut(files).spa_fallback = enabled;
+        }
+    }
+
+    // Let caches keep static files for `max_age` instead of the default hour (needs serve_static first)
+    pub fn set_static_max_age(&mut self, max_age: Duration) {
+        if let Some(files) = self.static_files.as_mut() {
+            Arc::make_mut(files).max_age = max_age;
+        }
+    }
+
+    // Parse malformed request lines as best as possible instead of answering 400
+    pub fn disable_hardened_parsing(&mut self) {
+        self.parse_options.hardened = false;
+    }
+
+    // Answer `path` with a built-in 200 status response
+    pub fn enable_health_check(&mut self, path: &str) {
+        self.health_check = Some(path.to_string());
+    }
+
+    // Check whether the request goes to a streaming route that runs without a timeout
+    pub fn streams_body(&self, request: &HttpRequest) -> bool {
+        match self.router.find(&request.method, &request.path) {
+            RouteMatch::Found(Matched { route, .. }) => route.streaming && route.timeout.or(self.handler_timeout).is_none(),
+            _ => false,
         }
     }
-    pub fn handle_cookie(&mut self, request: &HttpRequest) -> String {
-        if let Some(cookie) = &request.cookie {
-            if let Some(session_data) = self.sessions.get(cookie) {
-                println!("Existing session for cookie: {} -> {}", cookie, session_data);
-                return cookie.clone(); // Return the existing session ID
+
+    // Answer `path` with the counters from `stats` as JSON
+    pub fn enable_stats(&mut self, path: &str) {
+        self.stats_path = Some(path.to_string());
+    }
+
+    // Check whether a request target is the stats path
+    pub fn is_stats_path(&self, target: &str) -> bool {
+        let path = target.split('?').next().unwrap_or_default();
+        self.stats_path.as_deref() == Some(path)
+    }
+
+    // Requests, connections, bytes and errors counted since the server was created
+    pub fn stats(&self) -> ServerStats {
+        self.metrics.snapshot()
+    }
+
+    // Methods some path on the server answers: the built-in file handlers' and those of
+    // registered routes, in first-seen order
+    pub fn allowed_methods(&self) -> Vec<String> {
+        self.collect_methods(|_| true)
+    }
+
+    // Methods `target` answers: the built-in file handlers' and those of the routes matching it
+    pub fn allowed_methods_for(&self, target: &str) -> Vec<String> {
+        self.collect_methods(|method| matches!(self.router.find(method, target), RouteMatch::Found(_)))
+    }
+
+    fn collect_methods(&self, answers: impl Fn(&str) -> bool) -> Vec<String> {
+        let mut methods: Vec<String> = ["GET", "POST", "PUT", "PATCH", "DELETE", "OPTIONS"].map(String::from).to_vec();
+        for (method, _) in self.router.routes() {
+            if !methods.iter().any(|known| known == method.as_str()) && answers(method.as_str()) {
+                methods.push(method.to_string());
             }
         }
+        methods
+    }
+
+    // Check whether a request target is the health check path
+    pub fn is_health_check(&self, target: &str) -> bool {
+        let path = target.split('?').next().unwrap_or_default();
+        self.health_check.as_deref() == Some(path)
+    }
 
-        // If no valid session, create a new one
-        let session_id = Uuid::new_v4().to_string();
-        self.sessions.insert(session_id.clone(), "user_data".to_string());
-        println!("New session created: {}", session_id);
+    // Require a fixed username and password for every path under `prefix`
+    pub fn protect_basic(&mut self, prefix: &str, realm: &str, username: &str, password: &str) {
+        let (username, password) = (username.to_string(), password.to_string());
+        // Both fields are always compared so the timing doesn't tell which one was wrong
+        self.protect_basic_with(prefix, realm, move |user, pass| {
+            signing::constant_time_eq(user.as_bytes(), username.as_bytes()) & signing::constant_time_eq(pass.as_bytes(), password.as_bytes())
+        });
+    }
 
-        // Return the new session ID and set it in the Set-Cookie header
-        session_id
+    // Protect every path under `prefix` with a credential-checking closure
+    pub fn protect_basic_with<F>(&mut self, prefix: &str, realm: &str, check: F)
+    where
+        F: Fn(&str, &str) -> bool + Send + Sync + 'static,
+    {
+        self.auth_guards.push(BasicAuthGuard::new(prefix, realm, check));
     }
 
-    pub fn run(server: Arc<Mutex<Server>>) -> Result<(), Box<dyn std::error::Error>> {
-        let listener = TcpListener::bind("127.0.0.1:8080")?;
-        info!("Server running on port 8080");
-
-        // Create a thread pool with 4 threads
-        let pool = ThreadPool::new(100);
-
-        for stream in listener.incoming() {
-            match stream {
-                Ok(stream) => {
-                    let server_clone = Arc::clone(&server);
-                    pool.execute(move || {
-                        let mut client = Client { stream };
-                        client.handle(server_clone);
-                    });
+    // Find the guard protecting a request target, cloned so it can run without the server lock
+    pub fn auth_guard(&self, target: &str) -> Option<BasicAuthGuard> {
+        self.auth_guards.iter().find(|guard| guard.covers(target)).cloned()
+    }
+
+    // Sign session cookies with an HMAC of `secret`
+    pub fn set_cookie_secret(&mut self, secret: &[u8]) {
+        self.cookie_secret = Some(secret.to_vec());
+    }
+
+    // Keep sessions in `store` instead of the in-memory default
+    pub fn set_session_store(&mut self, store: Arc<dyn SessionStore>) {
+        self.sessions = store;
+    }
+
+    // Keep sessions in the client's cookie, encrypted with a key derived from `secret`,
+    // instead of the session store. Servers sharing the secret share the sessions.
+    pub fn enable_cookie_sessions(&mut self, secret: &[u8]) {
+        self.cookie_sessions = Some(Arc::new(CookieSessions::new(secret)));
+    }
+
+    // Stop creating sessions and sending the sessionId cookie, e.g. for pure APIs.
+    // Handlers can still set cookies themselves.
+    pub fn disable_sessions(&mut self) {
+        self.sessions_enabled = false;
+    }
+
+    // Value for the sessionId cookie, signed when a secret is set
+    pub fn session_cookie_value(&self, session_id: &str) -> String {
+        session::cookie_value(session_id, self.cookie_secret.as_deref())
+    }
+
+    pub fn handle_cookie(&self, request: &HttpRequest) -> String {
+        let secret = self.cookie_secret.as_deref();
+        self.sessions.resolve(request.cookie.as_deref().and_then(|cookie| session::id_from_cookie(cookie, secret)))
+    }
+
+    // Serve HTTPS using a PEM certificate chain and private key
+    #[cfg(feature = "tls")]
+    pub fn enable_tls(&mut self, cert_path: &str, key_path: &str) -> std::io::Result<()> {
+        self.tls = Some(tls::load_server_config(cert_path, key_path)?);
+        Ok(())
+    }
+
+    // Handle an accepted connection, wrapping it in TLS when enabled
+    pub fn serve_connection(server: Arc<Mutex<Server>>, stream: TcpStream, connection: Option<u64>) {
+        let metrics = Arc::clone(&lock_server(&server).metrics);
+        #[cfg(feature = "tls")]
+        {
+            let tls_config = lock_server(&server).tls.clone();
+            if let Some(config) = tls_config {
+                match tls::accept(config, stream) {
+                    Ok(stream) => Client::new(Metered::new(stream, metrics)).handle(server, connection),
+                    Err(e) => error!("TLS setup failed: {}", e),
                 }
-                Err(e) => error!("Connection failed: {}", e),
+                return;
             }
         }
 
+        let mut client = Client::new(Metered::new(stream, metrics));
+        client.handle(server, connection);
+    }
+
+    pub fn run(server: Arc<Mutex<Server>>) -> Result<(), Box<dyn std::error::Error>> {
+        let (address, backlog, lifecycle, workers, max_queued, metrics, idle_reaper) = {
+            let server_lock = lock_server(&server);
+            (
+                server_lock.address.clone(),
+                server_lock.backlog,
+                Arc::clone(&server_lock.lifecycle),
+                server_lock.workers,
+                server_lock.max_queued,
+                Arc::clone(&server_lock.metrics),
+                server_lock.idle_reaper,
+            )
+        };
+        let listener = bind_listener(&address, backlog)?;
+        lifecycle.set_local_addr(listener.local_addr()?);
+        info!("Server running on {}", listener.local_addr()?);
+
+        let pool = ThreadPool::new(workers.max(1));
+        if let Some(options) = idle_reaper {
+            lifecycle.spawn_reaper(options);
+        }
+
+        accept_connections(&listener, &lifecycle, ACCEPT_BACKOFF, |stream| {
+            // Every worker is busy and the queue is full, turn the client away now
+            if max_queued.is_some_and(|limit| pool.queued_count() >= limit) {
+                warn!("Rejecting connection, {} connections already queued", pool.queued_count());
+                reject_busy(stream);
+                metrics.record_response(503);
+                return;
+            }
+            let server_clone = Arc::clone(&server);
+            let lifecycle = Arc::clone(&lifecycle);
+            let id = lifecycle.register(&stream);
+            pool.execute(move || {
+                Server::serve_connection(server_clone, stream, Some(id));
+                lifecycle.unregister(id);
+            });
+        });
+
+        info!("Server stopped accepting connections");
         Ok(())
     }
+
+    // Swap in a new route table on a running server. Requests dispatched after the swap use
+    // `router`; in-flight ones finish with the route they matched, and no connection is dropped.
+    // Every route is replaced, including those added with `serve_bytes`, so `router` has to
+    // register them again (`Router::add_bytes`). The trailing slash policy is server
+    // configuration and stays as set with `set_trailing_slash`.
+    pub fn reload(server: &Arc<Mutex<Server>>, mut router: Router) {
+        let mut server_lock = lock_server(server);
+        router.trailing_slash = server_lock.router.trailing_slash;
+        let previous = std::mem::replace(&mut server_lock.router, router);
+        info!("Reloaded routes, {} replaced", previous.routes().len());
+    }
+
+    // Stop accepting, wait up to `timeout` for in-flight requests, then drop the remaining connections.
+    // Returns true if every request finished in time.
+    pub fn shutdown_with_timeout(server: &Arc<Mutex<Server>>, timeout: Duration) -> bool {
+        let lifecycle = Arc::clone(&lock_server(server).lifecycle);
+        lifecycle.shutdown(timeout)
+    }
+}
+
+// How long the accept loop pauses when the process is out of file descriptors
+const ACCEPT_BACKOFF: Duration = Duration::from_millis(100);
+
+// Source of incoming connections for the accept loop
+pub trait Listener {
+    type Stream;
+
+    fn accept(&self) -> std::io::Result<Self::Stream>;
+}
+
+impl Listener for TcpListener {
+    type Stream = TcpStream;
+
+    fn accept(&self) -> std::io::Result<TcpStream> {
+        TcpListener::accept(self).map(|(stream, _)| stream)
+    }
+}
+
+// Hand each accepted connection to `handle` until the server is stopping. When the
+// process or system runs out of descriptors, pause for `backoff` instead of spinning
+// on the same error, and carry on once connections have been closed.
+pub fn accept_connections<L: Listener>(
+    listener: &L,
+    lifecycle: &Lifecycle,
+    backoff: Duration,
+    mut handle: impl FnMut(L::Stream),
+) {
+    loop {
+        let accepted = listener.accept();
+        if lifecycle.is_stopping() {
+            break;
+        }
+
+        match accepted {
+            Ok(stream) => handle(stream),
+            Err(e) if is_out_of_descriptors(&e) => {
+                warn!("Out of file descriptors accepting connections, pausing for {:?}: {}", backoff, e);
+                std::thread::sleep(backoff);
+            }
+            Err(e) => error!("Connection failed: {}", e),
+        }
+    }
+}
+
+// Check whether an accept failed on a descriptor or socket buffer limit, which clears
+// as connections close
+fn is_out_of_descriptors(error: &std::io::Error) -> bool {
+    // EMFILE, ENFILE, ENOBUFS and ENOMEM
+    #[cfg(target_os = "linux")]
+    const CODES: &[i32] = &[24, 23, 105, 12];
+    #[cfg(all(unix, not(target_os = "linux")))]
+    const CODES: &[i32] = &[24, 23, 55, 12];
+    // WSAEMFILE and WSAENOBUFS
+    #[cfg(windows)]
+    const CODES: &[i32] = &[10024, 10055];
+    error.raw_os_error().is_some_and(|code| CODES.contains(&code))
+}
+
+// Answer 503 on a connection no worker can take
+fn reject_busy(mut stream: TcpStream) {
+    let response = HttpResponse::error(503, "Server is busy")
+        .with_header("Retry-After", "1")
+        .with_header("Connection", "close");
+    let _ = stream.set_write_timeout(Some(Duration::from_secs(1)));
+    if response.write_to(&mut stream).is_ok() {
+        let _ = stream.shutdown(std::net::Shutdown::Write);
+    }
 }
 
+// Lock the server even if a thread panicked while holding it. Its settings are only
+// changed between requests, so one panic shouldn't stop every later request.
+pub fn lock_server(server: &Mutex<Server>) -> MutexGuard<'_, Server> {
+    server.lock().unwrap_or_else(|poisoned| {
+        warn!("Recovering the server lock after a panic");
+        server.clear_poison();
+        poisoned.into_inner()
+    })
+}
+
+// Bind a listener with SO_REUSEADDR, so restarts don't fail on connections
+// left in TIME_WAIT, and the given accept backlog
+pub fn bind_listener(address: &str, backlog: i32) -> std::io::Result<TcpListener> {
+    let addr = address
+        .to_socket_addrs()?
+        .next()
+        .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::InvalidInput, "Address did not resolve"))?;
+
+    let socket = Socket::new(Domain::for_address(addr), Type::STREAM, None)?;
+    #[cfg(not(windows))]
+    socket.set_reuse_address(true)?;
+    socket.bind(&addr.into())?;
+    socket.listen(backlog)?;
+    Ok(socket.into())
+}
 
 // Fixed Thread Pool Tests
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::collections::HashMap;
+    use std::io::{Read, Write};
+    use crate::test_util::read_full_response;
+
+    // Wait until a test server is accepting connections on port 8080
+    fn wait_for_server() {
+        for _ in 0..100 {
+            if TcpStream::connect("127.0.0.1:8080").is_ok() {
+                return;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(20));
+        }
+    }
 
     #[test]
     fn test_new_session_creation_without_cookie() {
         // New server
-        let mut server = Server::new();
+        let server = Server::new();
 
         // Request without cookie 
         let request = HttpRequest {
             method: "GET".to_string(),
             path: "/".to_string(),
-            _headers: vec![],
-            body: "".to_string(),
+            headers: vec![],
+            body: Vec::new(),
             cookie: None,
+            ..Default::default()
         };
 
         // Generate cookie
@@ -89,10 +707,53 @@ mod tests {
         assert!(server.sessions.contains_key(&session_id));
         assert_eq!(server.sessions.get(&session_id).unwrap(), "user_data");
     }
+
+    #[test]
+    fn test_concurrent_session_creation_gives_distinct_ids() {
+        let store = Arc::new(MemorySessionStore::new());
+        let mut server = Server::new();
+        server.set_session_store(store.clone());
+        let server = Arc::new(Mutex::new(server));
+
+        let workers: Vec<_> = (0..16)
+            .map(|_| {
+                let server = Arc::clone(&server);
+                std::thread::spawn(move || {
+                    (0..50)
+                        .map(|_| server.lock().unwrap().handle_cookie(&HttpRequest::default()))
+                        .collect::<Vec<_>>()
+                })
+            })
+            .collect();
+        let ids: Vec<String> = workers.into_iter().flat_map(|worker| worker.join().unwrap()).collect();
+
+        let distinct: std::collections::HashSet<_> = ids.iter().collect();
+        let server = server.lock().unwrap();
+        assert_eq!(distinct.len(), 16 * 50);
+        assert_eq!(store.len(), 16 * 50);
+        assert!(ids.iter().all(|id| server.sessions.contains_key(id)));
+    }
+
+    #[test]
+    fn test_sessions_resolve_while_server_is_locked() {
+        let server = Arc::new(Mutex::new(Server::new()));
+        let sessions = Arc::clone(&server.lock().unwrap().sessions);
+        let (sender, receiver) = std::sync::mpsc::channel();
+
+        // Another request holds the whole server, session handling must not wait for it
+        let busy = server.lock().unwrap();
+        std::thread::spawn(move || {
+            let _ = sender.send(sessions.resolve(None));
+        });
+        let session_id = receiver.recv_timeout(Duration::from_secs(2)).expect("Session creation blocked on the server lock");
+
+        assert!(busy.sessions.contains_key(&session_id));
+    }
+
     #[test]
     fn test_new_session_creation_existing_cookie() {
         // New server
-        let mut server = Server::new();
+        let server = Server::new();
         
         // Manual Session
         server.sessions.insert("abc".to_string(), "user_data".to_string());
@@ -101,9 +762,10 @@ mod tests {
         let request = HttpRequest {
             method: "GET".to_string(),
             path: "/".to_string(),
-            _headers: vec![],
-            body: "".to_string(),
+            headers: vec![],
+            body: Vec::new(),
             cookie: Some("abc".to_string()),
+            ..Default::default()
         };
 
         // Handle cookie
@@ -112,6 +774,41 @@ mod tests {
         assert_eq!(session, "abc", "Cookie should be abc");
     }
 
+    #[test]
+    fn test_full_queue_answers_503() {
+        let mut server = Server::new();
+        server.address = "127.0.0.1:0".to_string();
+        server.set_workers(1);
+        server.set_max_queued(1);
+        server.route("GET", "/slow", |_| {
+            std::thread::sleep(Duration::from_millis(500));
+            HttpResponse::ok("slow")
+        });
+        let server = Arc::new(Mutex::new(server));
+        let lifecycle = Arc::clone(&server.lock().unwrap().lifecycle);
+        let server_clone = Arc::clone(&server);
+        std::thread::spawn(move || Server::run(server_clone).unwrap());
+        while lifecycle.local_addr().is_none() {
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        let addr = lifecycle.local_addr().unwrap();
+
+        // One connection keeps the only worker busy, the next one fills the queue
+        let mut busy = TcpStream::connect(addr).unwrap();
+        busy.write_all(b"GET /slow HTTP/1.1\r\n\r\n").unwrap();
+        std::thread::sleep(Duration::from_millis(100));
+        let _queued = TcpStream::connect(addr).unwrap();
+        std::thread::sleep(Duration::from_millis(100));
+
+        let mut overflow = TcpStream::connect(addr).unwrap();
+        let response = read_full_response(&mut overflow);
+
+        assert!(response.starts_with("HTTP/1.1 503 Service Unavailable"), "{}", response);
+        assert!(response.contains("Retry-After: 1\r\n"));
+        assert!(read_full_response(&mut busy).starts_with("HTTP/1.1 200 OK"));
+        Server::shutdown_with_timeout(&server, Duration::from_millis(100));
+    }
+
     #[test]
     fn test_server_run_single_connection() {
         let server = Arc::new(Mutex::new(Server::new()));
@@ -121,16 +818,16 @@ mod tests {
         std::thread::spawn(move || {
             Server::run(server_clone).unwrap();
         });
+        wait_for_server();
     
         //Connects with the server
         match std::net::TcpStream::connect("127.0.0.1:8080") {
             Ok(mut stream) => {
-                stream.write(b"GET /get HTTP/1.1\r\n\r\n").unwrap();
+                stream.write_all(b"GET /get HTTP/1.1\r\n\r\n").unwrap();
     
-                let mut buffer = [0; 512];
-                let bytes_read = stream.read(&mut buffer).unwrap();
+                let response = read_full_response(&mut stream);
                 // Verify that has read something from connection
-                assert!(bytes_read > 0);
+                assert!(!response.is_empty());
             }
             Err(e) => {
                 panic!("Failed to connect to the server: {:?}", e);
@@ -146,21 +843,20 @@ mod tests {
         std::thread::spawn(move || {
             Server::run(server_clone).unwrap();
         });
+        wait_for_server();
     
         // Simulates multiple clients in separate threads
         let mut handles = vec![];
-        for i in 0..100{
+        for _ in 0..100 {
             let handle = std::thread::spawn(move || {
                 match TcpStream::connect("127.0.0.1:8080") {
                     Ok(mut stream) => {
-                        let request = format!("GET /get HTTP/1.1\r\n\r\n");
-                        stream.write(request.as_bytes()).unwrap();
+                        let request = "GET /get HTTP/1.1\r\n\r\n";
+                        stream.write_all(request.as_bytes()).unwrap();
     
-                        let mut buffer = [0; 512];
-                        let bytes_read = stream.read(&mut buffer).unwrap();
-    
-                        assert!(bytes_read > 0);
-                        let response = String::from_utf8_lossy(&buffer[..bytes_read]);
+                        let response = read_full_response(&mut stream);
+
+                        assert!(!response.is_empty());
                         assert!(response.contains("HTTP/1.1 200 OK"));
                     }
                     Err(e) => {
@@ -177,4 +873,206 @@ mod tests {
         } 
     }
 
-}
\ No newline at end of file
+
+    #[test]
+    fn test_shutdown_with_timeout_returns_near_deadline() {
+        let mut server = Server::new();
+        server.address = "127.0.0.1:0".to_string();
+        server.route("GET", "/slow", |_| {
+            std::thread::sleep(Duration::from_secs(2));
+            HttpResponse::new(200, HashMap::new(), Some("slow".to_string()))
+        });
+        let server = Arc::new(Mutex::new(server));
+        let lifecycle = Arc::clone(&server.lock().unwrap().lifecycle);
+
+        let server_clone = Arc::clone(&server);
+        let runner = std::thread::spawn(move || {
+            Server::run(server_clone).unwrap();
+        });
+        while lifecycle.local_addr().is_none() {
+            std::thread::sleep(Duration::from_millis(10));
+        }
+
+        // Start a slow request and wait until it is in flight
+        let mut stream = TcpStream::connect(lifecycle.local_addr().unwrap()).unwrap();
+        stream.write_all(b"GET /slow HTTP/1.1\r\n\r\n").unwrap();
+        while lifecycle.open_connections() == 0 {
+            std::thread::sleep(Duration::from_millis(10));
+        }
+
+        let started = std::time::Instant::now();
+        let drained = Server::shutdown_with_timeout(&server, Duration::from_millis(300));
+        let elapsed = started.elapsed();
+
+        assert!(!drained, "The slow request should not finish in time");
+        assert!(elapsed >= Duration::from_millis(300));
+        assert!(elapsed < Duration::from_secs(1), "Shutdown took {:?}", elapsed);
+        runner.join().unwrap();
+    }
+
+
+    #[test]
+    fn test_idle_keep_alive_connection_is_reaped() {
+        let mut server = Server::new();
+        server.address = "127.0.0.1:0".to_string();
+        server.set_keep_alive_options(KeepAliveOptions { idle_timeout: Duration::from_secs(30), ..Default::default() });
+        server.enable_idle_reaper(Duration::from_millis(100), Duration::from_millis(50));
+        server.route("GET", "/ping", |_| HttpResponse::ok("pong"));
+        let server = Arc::new(Mutex::new(server));
+        let lifecycle = Arc::clone(&server.lock().unwrap().lifecycle);
+        let server_clone = Arc::clone(&server);
+        std::thread::spawn(move || Server::run(server_clone).unwrap());
+        while lifecycle.local_addr().is_none() {
+            std::thread::sleep(Duration::from_millis(10));
+        }
+
+        let mut stream = TcpStream::connect(lifecycle.local_addr().unwrap()).unwrap();
+        stream.write_all(b"GET /ping HTTP/1.1\r\n\r\n").unwrap();
+        let response = read_full_response(&mut stream);
+        assert!(response.contains("Connection: keep-alive\r\n"), "{}", response);
+
+        // Left idle, the connection is closed long before the keep-alive timeout
+        let started = std::time::Instant::now();
+        stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        let mut buffer = [0; 1];
+        assert_eq!(stream.read(&mut buffer).unwrap(), 0);
+        assert!(started.elapsed() < Duration::from_secs(1), "Reaped after {:?}", started.elapsed());
+        Server::shutdown_with_timeout(&server, Duration::from_millis(100));
+    }
+
+    #[test]
+    fn test_listener_rebinds_same_port_immediately() {
+        let listener = bind_listener("127.0.0.1:0", 16).unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        // Close a connection from the server side so the port has one in TIME_WAIT
+        let client = TcpStream::connect(addr).unwrap();
+        let (accepted, _) = listener.accept().unwrap();
+        drop(accepted);
+        drop(client);
+        drop(listener);
+
+        let rebound = bind_listener(&addr.to_string(), 16).unwrap();
+        assert_eq!(rebound.local_addr().unwrap(), addr);
+    }
+
+    fn request_with_cookie(cookie: &str) -> HttpRequest {
+        HttpRequest {
+            method: "GET".to_string(),
+            path: "/".to_string(),
+            cookie: Some(cookie.to_string()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_signed_cookie_is_accepted() {
+        let mut server = Server::new();
+        server.set_cookie_secret(b"top secret");
+        server.sessions.insert("abc".to_string(), "user_data".to_string());
+        let cookie = server.session_cookie_value("abc");
+
+        let session = server.handle_cookie(&request_with_cookie(&cookie));
+
+        assert_eq!(session, "abc");
+        assert_ne!(cookie, "abc");
+    }
+
+    #[test]
+    fn test_tampered_cookie_is_rejected() {
+        let mut server = Server::new();
+        server.set_cookie_secret(b"top secret");
+        server.sessions.insert("abc".to_string(), "user_data".to_string());
+        server.sessions.insert("abd".to_string(), "someone_else".to_string());
+        let signature = server.session_cookie_value("abc").rsplit_once('.').unwrap().1.to_string();
+
+        let swapped = server.handle_cookie(&request_with_cookie(&format!("abd.{}", signature)));
+        let unsigned = server.handle_cookie(&request_with_cookie("abd"));
+
+        assert!(swapped != "abd" && swapped != "abc");
+        assert!(unsigned != "abd" && unsigned != "abc");
+    }
+
+    // Listener replaying queued accept results, then failing as if closed
+    struct ExhaustedListener(Mutex<Vec<std::io::Result<u32>>>);
+
+    impl Listener for ExhaustedListener {
+        type Stream = u32;
+
+        fn accept(&self) -> std::io::Result<u32> {
+            let mut results = self.0.lock().unwrap();
+            if results.is_empty() {
+                return Err(std::io::Error::other("listener closed"));
+            }
+            results.remove(0)
+        }
+    }
+
+    #[test]
+    // Verify that running out of descriptors pauses the accept loop and accepting resumes afterwards
+    fn test_accept_loop_survives_descriptor_exhaustion() {
+        let emfile = || Err(std::io::Error::from_raw_os_error(if cfg!(windows) { 10024 } else { 24 }));
+        let listener = ExhaustedListener(Mutex::new(vec![emfile(), emfile(), emfile(), Ok(1), emfile(), Ok(2)]));
+        let lifecycle = Lifecycle::new();
+        let mut accepted = Vec::new();
+
+        let started = std::time::Instant::now();
+        accept_connections(&listener, &lifecycle, Duration::from_millis(10), |stream| {
+            accepted.push(stream);
+            if stream == 2 {
+                lifecycle.shutdown(Duration::ZERO);
+            }
+        });
+
+        assert_eq!(accepted, vec![1, 2]);
+        assert!(started.elapsed() >= Duration::from_millis(40));
+        assert!(is_out_of_descriptors(&emfile().unwrap_err()));
+        assert!(!is_out_of_descriptors(&std::io::Error::from(std::io::ErrorKind::ConnectionAborted)));
+    }
+
+    // Session store keeping a log of the calls the server makes
+    #[derive(Default)]
+    struct RecordingStore {
+        sessions: Mutex<HashMap<String, String>>,
+        calls: Mutex<Vec<String>>,
+    }
+
+    impl SessionStore for RecordingStore {
+        fn get(&self, id: &str) -> Option<String> {
+            self.calls.lock().unwrap().push(format!("get {}", id));
+            self.sessions.lock().unwrap().get(id).cloned()
+        }
+
+        fn insert(&self, id: String, data: String) {
+            self.calls.lock().unwrap().push("insert".to_string());
+            self.sessions.lock().unwrap().insert(id, data);
+        }
+
+        fn remove(&self, id: &str) -> Option<String> {
+            self.sessions.lock().unwrap().remove(id)
+        }
+
+        fn sweep_expired(&self) -> usize {
+            0
+        }
+    }
+
+    #[test]
+    // Verify that session lookup and creation go through a custom store
+    fn test_custom_session_store_is_used() {
+        let store = Arc::new(RecordingStore::default());
+        store.sessions.lock().unwrap().insert("abc".to_string(), "user_data".to_string());
+        let mut server = Server::new();
+        server.set_session_store(store.clone());
+
+        let existing = server.handle_cookie(&request_with_cookie("abc"));
+        let created = server.handle_cookie(&HttpRequest::default());
+
+        assert_eq!(existing, "abc");
+        assert_ne!(created, "abc");
+        assert!(store.sessions.lock().unwrap().contains_key(&created));
+        let calls = store.calls.lock().unwrap();
+        assert_eq!(calls.first().map(String::as_str), Some("get abc"));
+        assert_eq!(calls.last().map(String::as_str), Some("insert"));
+    }
+}
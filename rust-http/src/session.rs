@@ -0,0 +1,270 @@
+use std::collections::HashMap;
+use std::sync::RwLock;
+use std::time::{Duration, Instant};
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use ring::aead::{Aad, LessSafeKey, Nonce, UnboundKey, CHACHA20_POLY1305, NONCE_LEN};
+use ring::rand::{SecureRandom, SystemRandom};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+use crate::signing;
+use crate::trace::{debug, warn};
+
+// Where session ids and their data live. The server only goes through this trait,
+// so sessions can be kept in e.g. Redis or files instead of memory.
+pub trait SessionStore: Send + Sync {
+    fn get(&self, id: &str) -> Option<String>;
+
+    fn insert(&self, id: String, data: String);
+
+    fn remove(&self, id: &str) -> Option<String>;
+
+    // Drop sessions that outlived the store's expiry, returning how many went
+    fn sweep_expired(&self) -> usize;
+
+    fn contains_key(&self, id: &str) -> bool {
+        self.get(id).is_some()
+    }
+
+    // Keep the session `id` names if it exists, otherwise create a new one
+    fn resolve(&self, id: Option<&str>) -> String {
+        if let Some(id) = id {
+            if let Some(session_data) = self.get(id) {
+                debug!("Existing session for cookie: {} -> {}", id, session_data);
+                return id.to_string();
+            }
+        }
+
+        let session_id = loop {
+            let candidate = Uuid::new_v4().to_string();
+            if !self.contains_key(&candidate) {
+                break candidate;
+            }
+        };
+        self.insert(session_id.clone(), "user_data".to_string());
+        debug!("New session created: {}", session_id);
+        session_id
+    }
+}
+
+// Default store, keeping sessions in memory. They are locked on their own so cookie
+// handling doesn't contend with the rest of the server.
+#[derive(Debug, Default)]
+pub struct MemorySessionStore {
+    // Session data and when it was last stored
+    sessions: RwLock<HashMap<String, (String, Instant)>>,
+    // Sessions older than this are gone, None keeps them forever
+    ttl: Option<Duration>,
+}
+
+impl MemorySessionStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Expire sessions `ttl` after they were last stored
+    pub fn with_ttl(ttl: Duration) -> Self {
+        Self { ttl: Some(ttl), ..Self::default() }
+    }
+
+    pub fn len(&self) -> usize {
+        self.sessions.read().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.sessions.read().unwrap().is_empty()
+    }
+
+    fn is_expired(&self, stored: Instant) -> bool {
+        self.ttl.is_some_and(|ttl| stored.elapsed() >= ttl)
+    }
+}
+
+impl SessionStore for MemorySessionStore {
+    fn get(&self, id: &str) -> Option<String> {
+        let sessions = self.sessions.read().unwrap();
+        let (data, stored) = sessions.get(id)?;
+        (!self.is_expired(*stored)).then(|| data.clone())
+    }
+
+    fn insert(&self, id: String, data: String) {
+        self.sessions.write().unwrap().insert(id, (data, Instant::now()));
+    }
+
+    fn remove(&self, id: &str) -> Option<String> {
+        self.sessions.write().unwrap().remove(id).map(|(data, _)| data)
+    }
+
+    fn sweep_expired(&self) -> usize {
+        let mut sessions = self.sessions.write().unwrap();
+        let before = sessions.len();
+        sessions.retain(|_, (_, stored)| !self.is_expired(*stored));
+        before - sessions.len()
+    }
+
+    fn resolve(&self, id: Option<&str>) -> String {
+        if let Some(id) = id {
+            if let Some(session_data) = self.get(id) {
+                debug!("Existing session for cookie: {} -> {}", id, session_data);
+                return id.to_string();
+            }
+        }
+
+        // Checking for a clash and inserting happen under one write lock, so two
+        // clients can never end up with the same id
+        let mut sessions = self.sessions.write().unwrap();
+        let session_id = loop {
+            let candidate = Uuid::new_v4().to_string();
+            if !sessions.contains_key(&candidate) {
+                break candidate;
+            }
+        };
+        sessions.insert(session_id.clone(), ("user_data".to_string(), Instant::now()));
+        debug!("New session created: {}", session_id);
+        session_id
+    }
+}
+
+// Session kept entirely in the client's cookie instead of a store
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ClientSession {
+    pub id: String,
+    pub data: String,
+}
+
+impl ClientSession {
+    pub fn new() -> Self {
+        Self { id: Uuid::new_v4().to_string(), data: "user_data".to_string() }
+    }
+}
+
+impl Default for ClientSession {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Seals client-side sessions into cookie values with ChaCha20-Poly1305, so clients
+// can neither read nor change them and the server keeps nothing
+pub struct CookieSessions {
+    key: LessSafeKey,
+    rng: SystemRandom,
+}
+
+impl CookieSessions {
+    // Derive the encryption key from `secret`, which every server instance must share
+    pub fn new(secret: &[u8]) -> Self {
+        let digest = Sha256::digest(secret);
+        let key = UnboundKey::new(&CHACHA20_POLY1305, digest.as_slice()).expect("SHA-256 gives a ChaCha20 sized key");
+        Self { key: LessSafeKey::new(key), rng: SystemRandom::new() }
+    }
+
+    // Encrypt the session as base64 of a random nonce, the ciphertext and its tag
+    pub fn seal(&self, session: &ClientSession) -> String {
+        let mut nonce = [0u8; NONCE_LEN];
+        self.rng.fill(&mut nonce).expect("System randomness is available");
+        let mut sealed = serde_json::to_vec(session).unwrap_or_default();
+        self.key
+            .seal_in_place_append_tag(Nonce::assume_unique_for_key(nonce), Aad::empty(), &mut sealed)
+            .expect("Session payloads are far below the ChaCha20 limit");
+        URL_SAFE_NO_PAD.encode([nonce.as_slice(), &sealed].concat())
+    }
+
+    // Decrypt a cookie value, None if it was altered or sealed with another secret
+    pub fn open(&self, cookie: &str) -> Option<ClientSession> {
+        let sealed = URL_SAFE_NO_PAD.decode(cookie).ok()?;
+        if sealed.len() < NONCE_LEN {
+            return None;
+        }
+        let (nonce, ciphertext) = sealed.split_at(NONCE_LEN);
+        let nonce = Nonce::try_assume_unique_for_key(nonce).ok()?;
+        let mut ciphertext = ciphertext.to_vec();
+        let plaintext = self.key.open_in_place(nonce, Aad::empty(), &mut ciphertext).ok()?;
+        serde_json::from_slice(plaintext).ok()
+    }
+
+    // Cookie value carrying the session `cookie` holds, or a new session if it holds none
+    pub fn resolve(&self, cookie: Option<&str>) -> String {
+        let session = match cookie.map(|cookie| self.open(cookie)) {
+            Some(Some(session)) => {
+                debug!("Existing client-side session: {}", session.id);
+                session
+            }
+            Some(None) => {
+                warn!("Rejected a session cookie that failed to decrypt");
+                ClientSession::new()
+            }
+            None => ClientSession::new(),
+        };
+        self.seal(&session)
+    }
+}
+
+// Session id from a cookie value, if its signature checks out when a secret is set
+pub fn id_from_cookie<'a>(cookie: &'a str, secret: Option<&[u8]>) -> Option<&'a str> {
+    match secret {
+        Some(secret) => {
+            let verified = signing::verify(cookie, secret);
+            if verified.is_none() {
+                warn!("Rejected session cookie with an invalid signature");
+            }
+            verified
+        }
+        None => Some(cookie),
+    }
+}
+
+// Value for the sessionId cookie, signed when a secret is set
+pub fn cookie_value(session_id: &str, secret: Option<&[u8]>) -> String {
+    match secret {
+        Some(secret) => signing::sign(session_id, secret),
+        None => session_id.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_memory_store_sweeps_expired_sessions() {
+        let store = MemorySessionStore::with_ttl(Duration::from_millis(20));
+        store.insert("old".to_string(), "a".to_string());
+        std::thread::sleep(Duration::from_millis(30));
+        store.insert("new".to_string(), "b".to_string());
+
+        assert_eq!(store.get("old"), None);
+        assert_eq!(store.sweep_expired(), 1);
+        assert_eq!(store.len(), 1);
+        assert_eq!(store.remove("new"), Some("b".to_string()));
+        assert!(store.is_empty());
+    }
+
+    #[test]
+    fn test_client_session_round_trips_through_cookie() {
+        let sessions = CookieSessions::new(b"top secret");
+        let session = ClientSession { id: "abc".to_string(), data: "cart=3".to_string() };
+
+        let cookie = sessions.seal(&session);
+
+        assert!(!cookie.contains("cart"));
+        assert_eq!(sessions.open(&cookie), Some(session));
+        assert_eq!(CookieSessions::new(b"other secret").open(&cookie), None);
+    }
+
+    #[test]
+    fn test_tampered_client_session_is_rejected() {
+        let sessions = CookieSessions::new(b"top secret");
+        let cookie = sessions.seal(&ClientSession::new());
+
+        let mut tampered = cookie.clone().into_bytes();
+        let middle = tampered.len() / 2;
+        tampered[middle] = if tampered[middle] == b'A' { b'B' } else { b'A' };
+        let tampered = String::from_utf8(tampered).unwrap();
+
+        assert_eq!(sessions.open(&tampered), None);
+        assert_eq!(sessions.open(&cookie[..cookie.len() - 4]), None);
+        assert_eq!(sessions.open("not a session"), None);
+    }
+}
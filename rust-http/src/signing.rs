@@ -0,0 +1,55 @@
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use hmac::digest::CtOutput;
+use hmac::{Hmac, Mac};
+use sha2::{Digest, Sha256};
+
+type HmacSha256 = Hmac<Sha256>;
+
+// Append an HMAC-SHA256 signature to a cookie value as `value.signature`
+pub fn sign(value: &str, key: &[u8]) -> String {
+    let mut mac = HmacSha256::new_from_slice(key).expect("HMAC accepts keys of any length");
+    mac.update(value.as_bytes());
+    format!("{}.{}", value, URL_SAFE_NO_PAD.encode(mac.finalize().into_bytes()))
+}
+
+// Check the signature of a `value.signature` cookie, returning the value if it
+// wasn't tampered with
+pub fn verify<'a>(signed: &'a str, key: &[u8]) -> Option<&'a str> {
+    let (value, signature) = signed.rsplit_once('.')?;
+    let signature = URL_SAFE_NO_PAD.decode(signature).ok()?;
+
+    let mut mac = HmacSha256::new_from_slice(key).ok()?;
+    mac.update(value.as_bytes());
+    // Constant time comparison
+    mac.verify_slice(&signature).ok()?;
+    Some(value)
+}
+
+// Compare two secrets in time independent of where they differ. Both sides are hashed
+// first so the comparison doesn't leak their lengths either.
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    CtOutput::<Sha256>::new(Sha256::digest(a)) == CtOutput::new(Sha256::digest(b))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sign_and_verify() {
+        let signed = sign("session-1", b"secret");
+
+        assert!(signed.starts_with("session-1."));
+        assert_eq!(verify(&signed, b"secret"), Some("session-1"));
+        assert_eq!(verify(&signed, b"other key"), None);
+        assert_eq!(verify("session-1", b"secret"), None);
+    }
+
+    #[test]
+    fn test_constant_time_eq() {
+        assert!(constant_time_eq(b"secret", b"secret"));
+        assert!(!constant_time_eq(b"secret", b"secreT"));
+        assert!(!constant_time_eq(b"secret", b"secret2"));
+    }
+}
@@ -0,0 +1,109 @@
+use std::collections::HashMap;
+use std::io::{self, Write};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::Mutex;
+use crate::response::HttpResponse;
+
+// One Server-Sent Event
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Event {
+    pub event: Option<String>,
+    pub id: Option<String>,
+    pub data: String,
+}
+
+impl Event {
+    pub fn new(data: &str) -> Self {
+        Self { data: data.to_string(), ..Default::default() }
+    }
+
+    // Name the event so clients can listen for it with addEventListener
+    pub fn event(mut self, name: &str) -> Self {
+        self.event = Some(name.to_string());
+        self
+    }
+
+    // Set the id a reconnecting client sends back in Last-Event-ID
+    pub fn id(mut self, id: &str) -> Self {
+        self.id = Some(id.to_string());
+        self
+    }
+
+    // Frame the event, with a `data:` line per line of data and a blank line after it.
+    // Line breaks are dropped from the name and id so they can't start a field.
+    pub fn encode(&self) -> String {
+        let single_line = |value: &str| value.replace(['\r', '\n'], "");
+        let mut framed = String::new();
+        if let Some(event) = &self.event {
+            framed.push_str(&format!("event: {}\n", single_line(event)));
+        }
+        if let Some(id) = &self.id {
+            framed.push_str(&format!("id: {}\n", single_line(id)));
+        }
+        // Any of \r\n, \r or \n ends a line, each becomes its own data field
+        for line in self.data.replace("\r\n", "\n").split(['\r', '\n']) {
+            framed.push_str(&format!("data: {}\n", line));
+        }
+        framed.push('\n');
+        framed
+    }
+}
+
+// Events a handler keeps producing after it returned, kept in the response extensions
+pub struct EventStream(Mutex<Receiver<Event>>);
+
+impl EventStream {
+    // Write each event as it arrives, flushing after every one, until the sender is dropped
+    pub fn write_to<W: Write + ?Sized>(&self, writer: &mut W) -> io::Result<()> {
+        let events = self.0.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        for event in events.iter() {
+            writer.write_all(event.encode().as_bytes())?;
+            writer.flush()?;
+        }
+        Ok(())
+    }
+}
+
+// Build a `text/event-stream` response sending what `events` receives. The connection
+// stays open until the sender is dropped or the client goes away.
+pub fn response(events: Receiver<Event>) -> HttpResponse {
+    let mut headers = HashMap::new();
+    headers.insert("Content-Type".to_string(), "text/event-stream".to_string());
+    headers.insert("Cache-Control".to_string(), "no-cache".to_string());
+    // The stream ends when the connection does
+    headers.insert("Connection".to_string(), "close".to_string());
+    // Ask proxies like nginx not to hold events back
+    headers.insert("X-Accel-Buffering".to_string(), "no".to_string());
+    let mut response = HttpResponse::new(200, headers, None);
+    response.insert_ext(EventStream(Mutex::new(events)));
+    response
+}
+
+// Sender for a handler to produce events on, with the response to return
+pub fn channel() -> (Sender<Event>, HttpResponse) {
+    let (sender, receiver) = mpsc::channel();
+    (sender, response(receiver))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_event_framing() {
+        let event = Event::new("first\nsecond").event("update").id("7");
+
+        assert_eq!(event.encode(), "event: update\nid: 7\ndata: first\ndata: second\n\n");
+        assert_eq!(Event::new("").encode(), "data: \n\n");
+    }
+
+    #[test]
+    fn test_event_fields_stay_on_one_line() {
+        let event = Event::new("x").event("tick\r\ndata: injected");
+        assert_eq!(event.encode(), "event: tickdata: injected\ndata: x\n\n");
+
+        // A bare \r ends a data line too, it can't smuggle in another field
+        let event = Event::new("a\revent: injected\r\nb\nc");
+        assert_eq!(event.encode(), "data: a\ndata: event: injected\ndata: b\ndata: c\n\n");
+    }
+}
@@ -0,0 +1,223 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+use std::time::Duration;
+use crate::request::HttpRequest;
+use crate::response::HttpResponse;
+
+// Serves files from a directory on disk under a URL prefix
+#[derive(Clone)]
+pub struct StaticFiles {
+    pub prefix: String,
+    pub root: PathBuf,
+    // File inside `root` served with a 404 when nothing matches
+    pub not_found_page: Option<String>,
+    // Serve `index.html` for missing paths that don't look like files
+    pub spa_fallback: bool,
+    // How long clients and proxies may reuse a served file
+    pub max_age: Duration,
+}
+
+// Default lifetime of a served file in caches
+pub const DEFAULT_MAX_AGE: Duration = Duration::from_secs(3600);
+
+impl StaticFiles {
+    pub fn new(prefix: &str, root: &str) -> Self {
+        Self {
+            prefix: prefix.trim_end_matches('/').to_string(),
+            root: PathBuf::from(root),
+            not_found_page: None,
+            spa_fallback: false,
+            max_age: DEFAULT_MAX_AGE,
+        }
+    }
+
+    // Check whether a request target falls under the prefix
+    pub fn covers(&self, target: &str) -> bool {
+        self.relative_path(target).is_some()
+    }
+
+    // Serve the file the request points at, or a 404
+    pub fn serve(&self, request: &HttpRequest) -> HttpResponse {
+        let Some(relative) = self.relative_path(&request.path) else {
+            return self.not_found();
+        };
+
+        // Never leave the root directory
+        let relative = Path::new(relative);
+        if relative.components().any(|c| !matches!(c, Component::Normal(_))) {
+            return self.not_found();
+        }
+
+        let mut file_path = self.root.join(relative);
+        if file_path.is_dir() {
+            file_path = file_path.join("index.html");
+        }
+
+        match fs::read(&file_path) {
+            Ok(contents) => file_response(200, &file_path, contents).public(self.max_age),
+            Err(_) if self.spa_fallback && relative.extension().is_none() => self.spa_index(),
+            Err(_) => self.not_found(),
+        }
+    }
+
+    // Let client-side routing handle paths without a file behind them
+    fn spa_index(&self) -> HttpResponse {
+        let index_path = self.root.join("index.html");
+        match fs::read(&index_path) {
+            Ok(contents) => file_response(200, &index_path, contents).public(self.max_age),
+            Err(_) => self.not_found(),
+        }
+    }
+
+    fn relative_path<'a>(&self, target: &'a str) -> Option<&'a str> {
+        let path = target.split('?').next().unwrap_or_default();
+        let rest = path.strip_prefix(&self.prefix)?;
+        if rest.is_empty() {
+            Some("")
+        } else {
+            rest.strip_prefix('/')
+        }
+    }
+
+    fn not_found(&self) -> HttpResponse {
+        if let Some(page) = &self.not_found_page {
+            let page_path = self.root.join(page);
+            if let Ok(contents) = fs::read(&page_path) {
+                return file_response(404, &page_path, contents);
+            }
+        }
+
+        HttpResponse::error(404, "File not found")
+    }
+}
+
+fn file_response(status_code: u16, path: &Path, contents: Vec<u8>) -> HttpResponse {
+    let mut headers = HashMap::new();
+    headers.insert("Content-Type".to_string(), content_type(path).to_string());
+    // Files are served whole or as the byte range a GET asks for
+    if status_code == 200 {
+        headers.insert("Accept-Ranges".to_string(), "bytes".to_string());
+    }
+    HttpResponse::from_bytes(status_code, headers, contents)
+}
+
+// Guess the Content-Type from a file extension
+pub fn content_type(path: &Path) -> &'static str {
+    let extension = path.extension().and_then(|e| e.to_str()).unwrap_or_default();
+    match extension.to_ascii_lowercase().as_str() {
+        "html" | "htm" => "text/html; charset=utf-8",
+        "css" => "text/css; charset=utf-8",
+        "js" | "mjs" => "application/javascript",
+        "json" => "application/json",
+        "txt" => "text/plain; charset=utf-8",
+        "csv" => "text/csv",
+        "xml" => "application/xml",
+        "svg" => "image/svg+xml",
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        "ico" => "image/x-icon",
+        "pdf" => "application/pdf",
+        "zip" => "application/zip",
+        "gz" => "application/gzip",
+        "wasm" => "application/wasm",
+        _ => "application/octet-stream",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn get(path: &str) -> HttpRequest {
+        HttpRequest {
+            method: "GET".to_string(),
+            path: path.to_string(),
+            headers: vec![],
+            body: Vec::new(),
+            cookie: None,
+            ..Default::default()
+        }
+    }
+
+    // Create an empty directory for a test under the system temp dir
+    fn temp_root(name: &str) -> PathBuf {
+        let root = std::env::temp_dir().join(format!("rust-http-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(&root).unwrap();
+        root
+    }
+
+    #[test]
+    fn test_serve_existing_file() {
+        let root = temp_root("static-file");
+        fs::write(root.join("app.css"), "body {}").unwrap();
+        let files = StaticFiles::new("/static", root.to_str().unwrap());
+
+        let response = files.serve(&get("/static/app.css"));
+
+        assert_eq!(response.status_code, 200);
+        assert_eq!(response.headers.get("Content-Type").unwrap(), "text/css; charset=utf-8");
+        assert_eq!(response.headers.get("Accept-Ranges").unwrap(), "bytes");
+        assert_eq!(response.headers.get("Cache-Control").unwrap(), "public, max-age=3600");
+        assert_eq!(response.body, Some(b"body {}".to_vec()));
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn test_custom_not_found_page() {
+        let root = temp_root("static-404-page");
+        fs::write(root.join("404.html"), "<h1>Nothing here</h1>").unwrap();
+        let mut files = StaticFiles::new("/", root.to_str().unwrap());
+        files.not_found_page = Some("404.html".to_string());
+
+        let response = files.serve(&get("/missing.html"));
+
+        assert_eq!(response.status_code, 404);
+        assert_eq!(response.body, Some(b"<h1>Nothing here</h1>".to_vec()));
+        assert_eq!(response.headers.get("Content-Type").unwrap(), "text/html; charset=utf-8");
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn test_default_not_found_without_page() {
+        let root = temp_root("static-404-default");
+        let mut files = StaticFiles::new("/", root.to_str().unwrap());
+        files.not_found_page = Some("404.html".to_string());
+
+        let response = files.serve(&get("/missing.html"));
+
+        assert_eq!(response.status_code, 404);
+        assert!(String::from_utf8(response.body.unwrap()).unwrap().contains("File not found"));
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn test_path_traversal_is_rejected() {
+        let root = temp_root("static-traversal");
+        let files = StaticFiles::new("/static", root.to_str().unwrap());
+
+        let response = files.serve(&get("/static/../Cargo.toml"));
+
+        assert_eq!(response.status_code, 404);
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn test_spa_fallback_serves_index() {
+        let root = temp_root("static-spa");
+        fs::write(root.join("index.html"), "<div id=\"app\"></div>").unwrap();
+        let mut files = StaticFiles::new("/", root.to_str().unwrap());
+        files.spa_fallback = true;
+
+        let route = files.serve(&get("/some/spa/route"));
+        let asset = files.serve(&get("/missing.js"));
+
+        assert_eq!(route.status_code, 200);
+        assert_eq!(route.body, Some(b"<div id=\"app\"></div>".to_vec()));
+        assert_eq!(asset.status_code, 404);
+        fs::remove_dir_all(root).unwrap();
+    }
+}
@@ -0,0 +1,196 @@
+use std::collections::HashMap;
+use std::io::{self, Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use serde::Serialize;
+use crate::client::Connection;
+use crate::response::HttpResponse;
+
+// Counters every worker updates as it serves connections
+#[derive(Debug, Default)]
+pub struct Metrics {
+    requests: AtomicU64,
+    open_connections: AtomicU64,
+    bytes_received: AtomicU64,
+    bytes_sent: AtomicU64,
+    client_errors: AtomicU64,
+    server_errors: AtomicU64,
+}
+
+// Copy of the counters at one point in time
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct ServerStats {
+    // Responses sent, including errors for requests that couldn't be parsed
+    pub requests: u64,
+    pub open_connections: u64,
+    pub bytes_received: u64,
+    pub bytes_sent: u64,
+    // Responses with a 4xx status
+    pub client_errors: u64,
+    // Responses with a 5xx status
+    pub server_errors: u64,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn snapshot(&self) -> ServerStats {
+        ServerStats {
+            requests: self.requests.load(Ordering::Relaxed),
+            open_connections: self.open_connections.load(Ordering::Relaxed),
+            bytes_received: self.bytes_received.load(Ordering::Relaxed),
+            bytes_sent: self.bytes_sent.load(Ordering::Relaxed),
+            client_errors: self.client_errors.load(Ordering::Relaxed),
+            server_errors: self.server_errors.load(Ordering::Relaxed),
+        }
+    }
+
+    // Count a response going out with `status_code`
+    pub fn record_response(&self, status_code: u16) {
+        self.requests.fetch_add(1, Ordering::Relaxed);
+        match status_code {
+            400..=499 => self.client_errors.fetch_add(1, Ordering::Relaxed),
+            500..=599 => self.server_errors.fetch_add(1, Ordering::Relaxed),
+            _ => 0,
+        };
+    }
+
+    // Count a connection as open until the returned guard is dropped
+    pub fn open_connection(self: &Arc<Self>) -> OpenConnection {
+        self.open_connections.fetch_add(1, Ordering::Relaxed);
+        OpenConnection(Arc::clone(self))
+    }
+}
+
+// Keeps a connection counted as open
+pub struct OpenConnection(Arc<Metrics>);
+
+impl Drop for OpenConnection {
+    fn drop(&mut self) {
+        self.0.open_connections.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+// Connection counting the bytes it carries. The counts are plain fields, so reads and
+// writes don't touch shared atomics; they are added to the metrics when it's dropped.
+pub struct Metered<S> {
+    inner: S,
+    metrics: Arc<Metrics>,
+    bytes_read: u64,
+    bytes_written: u64,
+}
+
+impl<S> Metered<S> {
+    pub fn new(inner: S, metrics: Arc<Metrics>) -> Self {
+        Self { inner, metrics, bytes_read: 0, bytes_written: 0 }
+    }
+
+    // Bytes read from this connection so far
+    pub fn bytes_read(&self) -> u64 {
+        self.bytes_read
+    }
+
+    // Bytes written to this connection so far
+    pub fn bytes_written(&self) -> u64 {
+        self.bytes_written
+    }
+}
+
+impl<S> Drop for Metered<S> {
+    fn drop(&mut self) {
+        self.metrics.bytes_received.fetch_add(self.bytes_read, Ordering::Relaxed);
+        self.metrics.bytes_sent.fetch_add(self.bytes_written, Ordering::Relaxed);
+    }
+}
+
+impl<S: Read> Read for Metered<S> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.bytes_read += read as u64;
+        Ok(read)
+    }
+}
+
+impl<S: Write> Write for Metered<S> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.bytes_written += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl<S: Connection> Connection for Metered<S> {
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        self.inner.set_read_timeout(timeout)
+    }
+
+    fn set_write_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        self.inner.set_write_timeout(timeout)
+    }
+
+    fn peer_addr(&self) -> io::Result<SocketAddr> {
+        self.inner.peer_addr()
+    }
+
+    fn shutdown(&self) -> io::Result<()> {
+        self.inner.shutdown()
+    }
+
+    fn socket(&self) -> Option<TcpStream> {
+        self.inner.socket()
+    }
+}
+
+// JSON response for the built-in stats endpoint
+pub fn stats_response(stats: &ServerStats) -> HttpResponse {
+    let mut headers = HashMap::new();
+    headers.insert("Content-Type".to_string(), "application/json".to_string());
+    HttpResponse::new(200, headers, Some(serde_json::to_string(stats).unwrap_or_default()))
+        .cache_control("no-store")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_metered_connection_counts_bytes() {
+        let metrics = Arc::new(Metrics::new());
+        let mut stream = Metered::new(io::Cursor::new(b"GET / HTTP/1.1\r\n\r\n".to_vec()), Arc::clone(&metrics));
+
+        let mut request = Vec::new();
+        stream.read_to_end(&mut request).unwrap();
+        stream.write_all(b"HTTP/1.1 200 OK\r\n\r\n").unwrap();
+
+        assert_eq!((stream.bytes_read(), stream.bytes_written()), (18, 19));
+        assert_eq!(metrics.snapshot().bytes_received, 0);
+        drop(stream);
+        let stats = metrics.snapshot();
+        assert_eq!(stats.bytes_received, 18);
+        assert_eq!(stats.bytes_sent, 19);
+    }
+
+    #[test]
+    fn test_responses_and_connections_are_counted() {
+        let metrics = Arc::new(Metrics::new());
+        let connection = metrics.open_connection();
+        metrics.record_response(200);
+        metrics.record_response(404);
+        metrics.record_response(503);
+
+        assert_eq!(
+            metrics.snapshot(),
+            ServerStats { requests: 3, open_connections: 1, client_errors: 1, server_errors: 1, ..Default::default() }
+        );
+        drop(connection);
+        assert_eq!(metrics.snapshot().open_connections, 0);
+    }
+}
@@ -0,0 +1,56 @@
+use std::io::Read;
+
+// Read a whole HTTP response, stopping once Content-Length is satisfied or the
+// connection closes, so tests don't depend on how much a single read returns
+pub fn read_full_response<R: Read>(stream: &mut R) -> String {
+    let mut received = Vec::new();
+    let mut buffer = [0; 1024];
+
+    loop {
+        if let Some(expected) = expected_len(&received) {
+            if received.len() >= expected {
+                break;
+            }
+        }
+        match stream.read(&mut buffer) {
+            Ok(0) | Err(_) => break,
+            Ok(n) => received.extend_from_slice(&buffer[..n]),
+        }
+    }
+
+    String::from_utf8_lossy(&received).into_owned()
+}
+
+// Total length of the response once its headers are in, if it declares a Content-Length
+// or its chunked body has ended. None means reading on until the connection closes.
+fn expected_len(received: &[u8]) -> Option<usize> {
+    let header_end = received.windows(4).position(|window| window == b"\r\n\r\n")?;
+    // An interim response like 100 Continue comes before the final one
+    if received.starts_with(b"HTTP/1.1 1") {
+        return expected_len(&received[header_end + 4..]).map(|len| header_end + 4 + len);
+    }
+    let headers = String::from_utf8_lossy(&received[..header_end]);
+    if headers.lines().any(|line| line.eq_ignore_ascii_case("Transfer-Encoding: chunked")) {
+        // The last chunk is followed by the trailers and a blank line
+        let body = &received[header_end + 2..];
+        let last_chunk = body.windows(5).position(|window| window == b"\r\n0\r\n")?;
+        let end = body[last_chunk + 3..].windows(4).position(|window| window == b"\r\n\r\n")?;
+        return Some(header_end + 2 + last_chunk + 3 + end + 4);
+    }
+    let content_length = headers.lines().skip(1).find_map(|line| {
+        let (name, value) = line.split_once(':')?;
+        if name.trim().eq_ignore_ascii_case("Content-Length") {
+            value.trim().parse::<usize>().ok()
+        } else {
+            None
+        }
+    });
+
+    // Without a length a closing response runs until the connection ends, like an event stream
+    let closes = headers.lines().any(|line| line.eq_ignore_ascii_case("Connection: close"));
+    if content_length.is_none() && closes {
+        return None;
+    }
+
+    Some(header_end + 4 + content_length.unwrap_or(0))
+}
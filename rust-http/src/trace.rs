@@ -0,0 +1,95 @@
+// Logging macros for request handling: tracing events with the `tracing` feature, log records otherwise
+#[cfg(feature = "tracing")]
+pub use tracing::{debug, error, info, warn};
+#[cfg(not(feature = "tracing"))]
+pub use log::{debug, error, info, warn};
+
+#[cfg(feature = "tracing")]
+use crate::request::HttpRequest;
+
+// Span covering the handling of one request
+#[cfg(feature = "tracing")]
+pub fn request_span(request: &HttpRequest, request_id: &str) -> tracing::Span {
+    tracing::info_span!("request", method = %request.method, path = %request.path, request_id = %request_id)
+}
+
+#[cfg(all(test, feature = "tracing"))]
+mod tests {
+    use std::sync::{Arc, Mutex};
+    use tracing::field::{Field, Visit};
+    use tracing::span::{Attributes, Id, Record};
+    use tracing::{Event, Metadata, Subscriber};
+
+    // Name of a span with its recorded fields
+    type RecordedSpan = (String, Vec<(String, String)>);
+
+    // Subscriber that records the name and fields of every span it sees
+    #[derive(Clone, Default)]
+    struct RecordingSubscriber {
+        spans: Arc<Mutex<Vec<RecordedSpan>>>,
+    }
+
+    struct FieldRecorder(Vec<(String, String)>);
+
+    impl Visit for FieldRecorder {
+        fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+            self.0.push((field.name().to_string(), format!("{:?}", value)));
+        }
+    }
+
+    impl Subscriber for RecordingSubscriber {
+        fn enabled(&self, _: &Metadata<'_>) -> bool {
+            true
+        }
+
+        fn new_span(&self, attributes: &Attributes<'_>) -> Id {
+            let mut recorder = FieldRecorder(Vec::new());
+            attributes.record(&mut recorder);
+            let mut spans = self.spans.lock().unwrap();
+            spans.push((attributes.metadata().name().to_string(), recorder.0));
+            Id::from_u64(spans.len() as u64)
+        }
+
+        fn record(&self, _: &Id, _: &Record<'_>) {}
+        fn record_follows_from(&self, _: &Id, _: &Id) {}
+        fn event(&self, _: &Event<'_>) {}
+        fn enter(&self, _: &Id) {}
+        fn exit(&self, _: &Id) {}
+    }
+
+    #[test]
+    // Verify that handling a request opens a span carrying its method and path
+    fn test_request_span_per_request() {
+        use std::io::{Read, Write};
+        use std::net::{Shutdown, TcpListener, TcpStream};
+        use crate::client::Client;
+        use crate::server::Server;
+
+        let subscriber = RecordingSubscriber::default();
+        let spans = Arc::clone(&subscriber.spans);
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let handle = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            stream.write_all(b"GET /get HTTP/1.1\r\n\r\n").unwrap();
+            stream.shutdown(Shutdown::Write).unwrap();
+            let mut response = String::new();
+            stream.read_to_string(&mut response).unwrap();
+        });
+
+        let mut client = Client::new(TcpStream::connect(addr).unwrap());
+        tracing::subscriber::with_default(subscriber, || {
+            client.handle(Arc::new(Mutex::new(Server::new())), None);
+        });
+        drop(client);
+        handle.join().unwrap();
+
+        let spans = spans.lock().unwrap();
+        let (name, fields) = spans.iter().find(|(name, _)| name == "request").expect("request span");
+        assert_eq!(name, "request");
+        assert!(fields.contains(&("method".to_string(), "GET".to_string())));
+        assert!(fields.contains(&("path".to_string(), "/get".to_string())));
+        assert!(fields.iter().any(|(field, value)| field == "request_id" && !value.is_empty()));
+    }
+}
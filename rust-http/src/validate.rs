@@ -0,0 +1,63 @@
+use std::sync::Arc;
+use serde::Serialize;
+use serde_json::Value;
+use crate::context::Context;
+use crate::error::HttpError;
+use crate::response::HttpResponse;
+use crate::router::Handler;
+
+// A problem with one field of a request body
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct FieldError {
+    pub field: String,
+    pub message: String,
+}
+
+impl FieldError {
+    pub fn new(field: &str, message: &str) -> Self {
+        Self { field: field.to_string(), message: message.to_string() }
+    }
+}
+
+// Wrap a handler so it only runs for JSON bodies `validator` finds no problems with.
+// A body that isn't JSON gets a 400, field errors a 422 listing them as a JSON array.
+pub fn validated<V>(validator: V, handler: Handler) -> Handler
+where
+    V: Fn(&Value) -> Vec<FieldError> + Send + Sync + 'static,
+{
+    Arc::new(move |ctx: &Context| {
+        let body = match serde_json::from_slice::<Value>(&ctx.request.body) {
+            Ok(body) => body,
+            Err(e) => return HttpError::bad_request(&format!("Invalid JSON body: {}", e)).to_response(ctx.request),
+        };
+        let errors = validator(&body);
+        if !errors.is_empty() {
+            return HttpResponse::json_array(errors).with_status(422);
+        }
+        handler(ctx)
+    })
+}
+
+// Validator helper: an error for `field` unless it holds a non-empty string
+pub fn require_string(body: &Value, field: &str) -> Option<FieldError> {
+    match body.get(field) {
+        Some(Value::String(value)) if !value.trim().is_empty() => None,
+        Some(Value::String(_)) => Some(FieldError::new(field, "must not be empty")),
+        Some(_) => Some(FieldError::new(field, "must be a string")),
+        None => Some(FieldError::new(field, "is required")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_require_string() {
+        assert_eq!(require_string(&json!({"name": "a"}), "name"), None);
+        assert_eq!(require_string(&json!({"name": " "}), "name"), Some(FieldError::new("name", "must not be empty")));
+        assert_eq!(require_string(&json!({"name": 1}), "name"), Some(FieldError::new("name", "must be a string")));
+        assert_eq!(require_string(&json!({}), "name"), Some(FieldError::new("name", "is required")));
+    }
+}